@@ -5,8 +5,17 @@ layout (std140) uniform Matrices {
 	mat4 view;
 	mat4 projection;
 };
+#define MAX_LIGHTS 16
+struct Light {
+	vec4 position; // w: 0 directional, 1 point, 2 spot
+	vec4 direction; // w: range, 0 for unlimited
+	vec4 color; // w: intensity
+	vec4 cone; // x: cos(inner angle), y: cos(outer angle)
+};
 layout (std140, binding=1) uniform World {
 	vec4 ambient;
+	int lightCount;
+	Light lights[MAX_LIGHTS];
 };
 
 in VS_OUT {
@@ -16,6 +25,12 @@ in VS_OUT {
 #ifdef TEXTURES
 	vec2 TexCoord;
 #endif
+#ifdef VERTEX_COLORS
+	vec4 Color;
+#endif
+#ifdef NORMAL_MAP_TEXTURE
+	vec4 Tangent;
+#endif
 } fs_in;
 
 out vec4 FragColor;
@@ -31,7 +46,7 @@ uniform sampler2D emissive;
 uniform vec4 emissive;
 #endif
 
-float specular_exponent = 256.0;
+uniform float specular_exponent = 256.0;
 
 #ifdef SPECULAR_TEXTURE
 uniform sampler2D specular;
@@ -39,24 +54,100 @@ uniform sampler2D specular;
 uniform float specular;
 #endif
 
+#ifdef OPACITY_TEXTURE
+uniform sampler2D opacity;
+#else
+uniform float opacity = 1.0;
+#endif
+
+#ifdef NORMAL_MAP_TEXTURE
+uniform sampler2D normal_map;
+#endif
+
+#ifdef METALLIC_TEXTURE
+uniform sampler2D metallic;
+#else
+uniform float metallic = 0.0;
+#endif
+
+// Negative to use specular_exponent instead.
+#ifdef ROUGHNESS_TEXTURE
+uniform sampler2D roughness;
+#else
+uniform float roughness = -1.0;
+#endif
+
 
 
 void main() {
 #ifdef DIFFUSE_TEXTURE
-	vec4 diffuse = texture(diffuse, fs_in.TexCoord);
+	vec4 diffuseColor = texture(diffuse, fs_in.TexCoord);
+#else
+	vec4 diffuseColor = diffuse;
+#endif
+#ifdef VERTEX_COLORS
+	diffuseColor *= fs_in.Color;
 #endif
 #ifdef EMISSIVE_TEXTURE
 	vec4 emissive = texture(emissive, fs_in.TexCoord);
 #endif
 #ifdef SPECULAR_TEXTURE
-	float specular = texture(specular, fs_in.TexCoord);
-#endif
-    vec3 lightPos = vec3(-10.0f, 15.0f, 1.0f);
-    vec3 lightDir = normalize(lightPos - fs_in.FragPos);
-    vec3 normal = normalize(cross(dFdx(fs_in.FragPos), dFdy(fs_in.FragPos)));
-    float diff = max(dot(normal, normalize(lightDir)), 0.0);
-    float spec = pow(max(dot(normal, normalize(lightDir)), 0.0), specular_exponent);
-    FragColor = (specular * spec) + emissive + (diff * diffuse) + ambient;
+	float specular = texture(specular, fs_in.TexCoord).r;
+#endif
+#ifdef OPACITY_TEXTURE
+	float opacity = texture(opacity, fs_in.TexCoord).r;
+#endif
+#ifdef METALLIC_TEXTURE
+	float metallic = texture(metallic, fs_in.TexCoord).r;
+#endif
+#ifdef ROUGHNESS_TEXTURE
+	float roughness = texture(roughness, fs_in.TexCoord).r;
+#endif
+    // Metals tint their highlights with the base color and have no diffuse term.
+    vec4 specularColor = mix(vec4(specular), diffuseColor, metallic);
+    vec4 albedo = diffuseColor * (1.0 - metallic);
+    // Blinn-Phong exponent matching a GGX distribution with alpha = roughness^2.
+    float shininess = roughness < 0.0 ? specular_exponent : max(2.0 / max(pow(roughness, 4.0), 0.0001) - 2.0, 1.0);
+    // Meshes without normals fall back to flat shading.
+    vec3 normal = length(fs_in.Normal) > 0.0 ? normalize(fs_in.Normal) : normalize(cross(dFdx(fs_in.FragPos), dFdy(fs_in.FragPos)));
+#ifdef NORMAL_MAP_TEXTURE
+    vec3 tangent = normalize(fs_in.Tangent.xyz - normal * dot(normal, fs_in.Tangent.xyz));
+    vec3 bitangent = cross(normal, tangent) * fs_in.Tangent.w;
+    vec3 mapped = texture(normal_map, fs_in.TexCoord).xyz * 2.0 - 1.0;
+    normal = normalize(mat3(tangent, bitangent, normal) * mapped);
+#endif
+    vec4 lit = vec4(0.0);
+    if (lightCount == 0) {
+        // Fallback light for scenes without any lights.
+        vec3 lightPos = vec3(-10.0f, 15.0f, 1.0f);
+        vec3 lightDir = normalize(lightPos - fs_in.FragPos);
+        float diff = max(dot(normal, normalize(lightDir)), 0.0);
+        float spec = pow(max(dot(normal, normalize(lightDir)), 0.0), shininess);
+        lit = (specularColor * spec) + (diff * albedo);
+    }
+    vec3 viewDir = normalize(cameraPos - fs_in.FragPos);
+    for (int i = 0; i < min(lightCount, MAX_LIGHTS); i++) {
+        vec3 toLight = -lights[i].direction.xyz;
+        float attenuation = 1.0;
+        if (lights[i].position.w != 0.0) {
+            toLight = lights[i].position.xyz - fs_in.FragPos;
+            float dist = length(toLight);
+            attenuation = 1.0 / max(dist * dist, 0.0001);
+            if (lights[i].direction.w > 0.0) {
+                attenuation *= clamp(1.0 - pow(dist / lights[i].direction.w, 4.0), 0.0, 1.0);
+            }
+            if (lights[i].position.w == 2.0) {
+                float angle = dot(normalize(lights[i].direction.xyz), -normalize(toLight));
+                attenuation *= smoothstep(lights[i].cone.y, lights[i].cone.x, angle);
+            }
+        }
+        vec3 lightDir = normalize(toLight);
+        float diff = max(dot(normal, lightDir), 0.0);
+        float spec = pow(max(dot(normal, normalize(lightDir + viewDir)), 0.0), shininess);
+        vec4 radiance = vec4(lights[i].color.rgb * (lights[i].color.w * attenuation), 1.0);
+        lit += radiance * ((specularColor * spec) + (diff * albedo));
+    }
+    FragColor = vec4((lit + emissive + ambient).rgb, diffuseColor.a * opacity);
 //    FragColor = vec4(1.0f,1.0f,1.0f,1.0f);
     //    FragColor = vec4(1.0f, 0.5f, 0.2f, 1.0f);
 }
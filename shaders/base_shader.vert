@@ -5,16 +5,27 @@ layout (location = 1) in vec3 aNormal;
 #ifdef TEXTURES
 layout (location = 2) in vec2 aTexCoord;
 #endif
+#ifdef SKINNING
+layout (location = 3) in vec4 aJoints;
+layout (location = 4) in vec4 aWeights;
+#define MAX_JOINTS 128
+// A block, since this many matrices exceed the uniforms a vertex shader is guaranteed to have.
+layout (std140, binding=2) uniform Joints {
+	mat4 jointMatrices[MAX_JOINTS];
+};
+#endif
+#ifdef VERTEX_COLORS
+layout (location = 5) in vec4 aColor;
+#endif
+#ifdef NORMAL_MAP_TEXTURE
+layout (location = 6) in vec4 aTangent;
+#endif
 
 layout (std140) uniform Matrices {
 	vec3 cameraPos;
 	mat4 view;
 	mat4 projection;
 };
-layout (std140, binding=1) uniform World {
-	vec4 ambient;
-};
-
 uniform mat4 model;
 
 out VS_OUT {
@@ -24,6 +35,12 @@ out VS_OUT {
 #ifdef TEXTURES
 	vec2 TexCoord;
 #endif
+#ifdef VERTEX_COLORS
+	vec4 Color;
+#endif
+#ifdef NORMAL_MAP_TEXTURE
+	vec4 Tangent;
+#endif
 } vs_out;
 
 uniform float time;
@@ -33,9 +50,22 @@ void main()
 #ifdef TEXTURES
 	 vs_out.TexCoord = aTexCoord;
 #endif
-    vs_out.Normal = mat3(transpose(inverse(model))) * aNormal;
-    vs_out.FragPos = vec3(model * vec4(aPos, 1.0));
+#ifdef VERTEX_COLORS
+    vs_out.Color = aColor;
+#endif
+    mat4 world = model;
+#ifdef SKINNING
+    world = model * (aWeights.x * jointMatrices[int(aJoints.x)]
+                   + aWeights.y * jointMatrices[int(aJoints.y)]
+                   + aWeights.z * jointMatrices[int(aJoints.z)]
+                   + aWeights.w * jointMatrices[int(aJoints.w)]);
+#endif
+    vs_out.Normal = mat3(transpose(inverse(world))) * aNormal;
+#ifdef NORMAL_MAP_TEXTURE
+    vs_out.Tangent = vec4(mat3(world) * aTangent.xyz, aTangent.w);
+#endif
+    vs_out.FragPos = vec3(world * vec4(aPos, 1.0));
     vs_out.Time = time;
-    gl_Position = projection * view * model * vec4(aPos.xyz, 1.0);
+    gl_Position = projection * view * world * vec4(aPos.xyz, 1.0);
 
 }
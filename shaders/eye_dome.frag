@@ -0,0 +1,46 @@
+#version 460 core
+// Eye-dome lighting: darkens pixels whose neighbours are closer to the camera, outlining the
+// shape of unlit point clouds.
+out vec4 FragColor;
+in vec2 uv;
+
+layout (std140) uniform Matrices {
+    vec3 cameraPos;
+    mat4 view;
+    mat4 projection;
+};
+
+uniform sampler2D colorTexture;
+uniform sampler2D depthTexture;
+uniform float strength;
+// Distance of the sampled neighbours in pixels.
+uniform float radius;
+
+float linearDepth(float depth) {
+    // Orthographic depth is already linear.
+    if (projection[2][3] == 0.0) {
+        return depth + 1.0;
+    }
+    return projection[3][2] / (depth * 2.0 - 1.0 + projection[2][2]);
+}
+
+void main() {
+    float depth = texture(depthTexture, uv).r;
+    if (depth >= 1.0) {
+        discard;
+    }
+    float center = log2(linearDepth(depth));
+    vec2 texel = radius / vec2(textureSize(depthTexture, 0));
+    float response = 0.0;
+    for (int i = 0; i < 8; i++) {
+        float angle = float(i) * 0.78539816;
+        float neighbour = texture(depthTexture, uv + texel * vec2(cos(angle), sin(angle))).r;
+        if (neighbour < 1.0) {
+            response += max(0.0, center - log2(linearDepth(neighbour)));
+        }
+    }
+    float shade = exp(-response / 8.0 * 300.0 * strength);
+    vec4 color = texture(colorTexture, uv);
+    FragColor = vec4(color.rgb * shade, color.a);
+    gl_FragDepth = depth;
+}
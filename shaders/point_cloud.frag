@@ -0,0 +1,17 @@
+#version 460 core
+out vec4 FragColor;
+in VS_OUT {
+    vec4 Color;
+} fs_in;
+
+uniform int roundPoints;
+
+void main() {
+    if (roundPoints != 0) {
+        vec2 offset = gl_PointCoord * 2.0 - 1.0;
+        if (dot(offset, offset) > 1.0) {
+            discard;
+        }
+    }
+    FragColor = fs_in.Color;
+}
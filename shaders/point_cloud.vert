@@ -0,0 +1,31 @@
+#version 460 core
+layout (location = 0) in vec3 aPos;
+layout (location = 5) in vec4 aColor;
+
+layout (std140) uniform Matrices {
+    vec3 cameraPos;
+    mat4 view;
+    mat4 projection;
+};
+
+uniform mat4 model;
+// Diameter in pixels, or in world units if worldSpace is set.
+uniform float pointSize;
+uniform int worldSpace;
+uniform float viewportHeight;
+
+out VS_OUT {
+    vec4 Color;
+} vs_out;
+
+void main()
+{
+    gl_Position = projection * view * model * vec4(aPos, 1.0);
+    if (worldSpace != 0) {
+        // Project the diameter at this depth onto the viewport.
+        gl_PointSize = pointSize * projection[1][1] * viewportHeight * 0.5 / gl_Position.w;
+    } else {
+        gl_PointSize = pointSize;
+    }
+    vs_out.Color = aColor;
+}
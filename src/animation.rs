@@ -0,0 +1,438 @@
+//! Keyframe animation and skinning.
+//!
+//! Samplers and poses are plain CPU math and don't need an OpenGL context; `RenderableGroup`
+//! uses them to drive its `SceneGraph` and to compute the joint matrices for skinned meshes.
+use crate::scene::{NodeHandle, SceneGraph};
+use cgmath::{InnerSpace, Matrix4, Quaternion, SquareMatrix, Vector3};
+use std::collections::HashMap;
+use std::error::Error;
+use std::ops::{Add, Mul};
+
+/// Must match `MAX_JOINTS` in `base_shader.vert`.
+pub const MAX_JOINTS: usize = 128;
+
+/// The uniform buffer binding of the `Joints` block in `base_shader.vert`.
+pub const JOINTS_BINDING: u32 = 2;
+
+/// How values between two keyframes are computed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Holds the value of the previous keyframe.
+    Step,
+    /// Interpolates linearly, or spherically for rotations.
+    Linear,
+    /// Cubic Hermite spline; every keyframe stores an in-tangent, a value and an out-tangent.
+    CubicSpline,
+}
+
+impl From<gltf::animation::Interpolation> for Interpolation {
+    fn from(value: gltf::animation::Interpolation) -> Self {
+        match value {
+            gltf::animation::Interpolation::Step => Self::Step,
+            gltf::animation::Interpolation::Linear => Self::Linear,
+            gltf::animation::Interpolation::CubicSpline => Self::CubicSpline,
+        }
+    }
+}
+
+/// A value that can be stored in a keyframe.
+pub trait Keyframe: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    /// Interpolates between `a` and `b` for `Interpolation::Linear`.
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        a * (1.0 - t) + b * t
+    }
+    /// Post-processes a cubic spline result, e.g. to renormalize rotations.
+    #[must_use]
+    fn finish(self) -> Self {
+        self
+    }
+}
+
+impl Keyframe for f32 {}
+impl Keyframe for Vector3<f32> {}
+impl Keyframe for Quaternion<f32> {
+    fn interpolate(a: Self, b: Self, t: f32) -> Self {
+        // Take the shortest path around the hypersphere.
+        let b = if a.dot(b) < 0.0 { -b } else { b };
+        a.slerp(b, t)
+    }
+    fn finish(self) -> Self {
+        self.normalize()
+    }
+}
+
+/// Keyframe times and values for one animated property.
+#[derive(Clone, Debug)]
+pub struct Sampler<T> {
+    pub interpolation: Interpolation,
+    times: Vec<f32>,
+    values: Vec<T>,
+}
+
+impl<T: Keyframe> Sampler<T> {
+    /// Creates a sampler from ascending keyframe `times` and their `values`.
+    ///
+    /// Cubic spline samplers need three values per keyframe: in-tangent, value, out-tangent.
+    /// # Errors
+    /// If there are no keyframes or the number of values doesn't match the number of times.
+    pub fn new(interpolation: Interpolation, times: Vec<f32>, values: Vec<T>) -> Result<Self, Box<dyn Error>> {
+        let per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        if times.is_empty() {
+            return Err("Sampler has no keyframes".into());
+        }
+        if values.len() != times.len() * per_key {
+            return Err(format!(
+                "Sampler has {} keyframes but {} values",
+                times.len(),
+                values.len()
+            )
+            .into());
+        }
+        Ok(Self {
+            interpolation,
+            times,
+            values,
+        })
+    }
+
+    /// Time of the last keyframe.
+    #[must_use]
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+
+    /// The value stored for keyframe `index`, skipping cubic spline tangents.
+    fn value(&self, index: usize) -> T {
+        if self.interpolation == Interpolation::CubicSpline {
+            self.values[index * 3 + 1]
+        } else {
+            self.values[index]
+        }
+    }
+
+    /// Evaluates the sampler at `time`, clamping to the first and last keyframes.
+    #[must_use]
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.times.partition_point(|t| *t <= time);
+        if next == 0 {
+            return self.value(0);
+        }
+        if next == self.times.len() {
+            return self.value(next - 1);
+        }
+        let previous = next - 1;
+        let delta = self.times[next] - self.times[previous];
+        let t = (time - self.times[previous]) / delta;
+        match self.interpolation {
+            Interpolation::Step => self.value(previous),
+            Interpolation::Linear => T::interpolate(self.value(previous), self.value(next), t),
+            Interpolation::CubicSpline => {
+                let t2 = t * t;
+                let t3 = t2 * t;
+                let start = self.values[previous * 3 + 1];
+                let out_tangent = self.values[previous * 3 + 2] * delta;
+                let end = self.values[next * 3 + 1];
+                let in_tangent = self.values[next * 3] * delta;
+                // Hermite basis functions.
+                let h00 = 2.0f32.mul_add(t3, (-3.0f32).mul_add(t2, 1.0));
+                let h10 = (-2.0f32).mul_add(t2, t3) + t;
+                let h01 = (-2.0f32).mul_add(t3, 3.0 * t2);
+                let h11 = t3 - t2;
+                (start * h00 + out_tangent * h10 + end * h01 + in_tangent * h11).finish()
+            }
+        }
+    }
+}
+
+impl Sampler<f32> {
+    /// Splits glTF morph target weight keyframes, which store `count` weights per value, into one
+    /// sampler per morph target.
+    /// # Errors
+    /// If the number of values doesn't match the number of times and targets.
+    pub fn split_weights(
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[f32],
+        count: usize,
+    ) -> Result<Vec<Self>, Box<dyn Error>> {
+        if count == 0 || !values.len().is_multiple_of(count) {
+            return Err(format!("Can't split {} weights into {count} morph targets", values.len()).into());
+        }
+        (0..count)
+            .map(|target| {
+                let weights = values.iter().skip(target).step_by(count).copied().collect();
+                Self::new(interpolation, times.to_vec(), weights)
+            })
+            .collect()
+    }
+}
+
+/// A node's local transform split into translation, rotation and scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Pose {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Pose {
+    #[must_use]
+    pub fn mat(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+}
+
+/// The property of a node an animation channel writes to.
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Sampler<Vector3<f32>>),
+    Rotation(Sampler<Quaternion<f32>>),
+    Scale(Sampler<Vector3<f32>>),
+    /// One sampler per morph target of the node's mesh.
+    Weights(Vec<Sampler<f32>>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub target: NodeHandle,
+    pub values: ChannelValues,
+}
+
+impl Channel {
+    #[must_use]
+    pub fn duration(&self) -> f32 {
+        match &self.values {
+            ChannelValues::Translation(s) | ChannelValues::Scale(s) => s.duration(),
+            ChannelValues::Rotation(s) => s.duration(),
+            ChannelValues::Weights(s) => s.iter().map(Sampler::duration).fold(0.0, f32::max),
+        }
+    }
+
+    /// Overwrites the animated part of `pose` with the value at `time`.
+    ///
+    /// Morph target weights aren't part of the pose; see `Animation::sample_weights`.
+    pub fn apply(&self, time: f32, pose: &mut Pose) {
+        match &self.values {
+            ChannelValues::Translation(s) => pose.translation = s.sample(time),
+            ChannelValues::Rotation(s) => pose.rotation = s.sample(time),
+            ChannelValues::Scale(s) => pose.scale = s.sample(time),
+            ChannelValues::Weights(_) => {}
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+}
+
+impl Animation {
+    /// Time of the last keyframe of any channel.
+    #[must_use]
+    pub fn duration(&self) -> f32 {
+        self.channels.iter().map(Channel::duration).fold(0.0, f32::max)
+    }
+
+    /// Poses every targeted node at `time`, starting from its `rest` pose.
+    #[must_use]
+    pub fn sample(&self, time: f32, rest: &HashMap<NodeHandle, Pose>) -> HashMap<NodeHandle, Pose> {
+        let mut poses = HashMap::new();
+        for channel in &self.channels {
+            if matches!(channel.values, ChannelValues::Weights(_)) {
+                continue;
+            }
+            let pose = poses
+                .entry(channel.target)
+                .or_insert_with(|| rest.get(&channel.target).copied().unwrap_or_default());
+            channel.apply(time, pose);
+        }
+        poses
+    }
+
+    /// Morph target weights of every node with an animated weights channel at `time`.
+    #[must_use]
+    pub fn sample_weights(&self, time: f32) -> HashMap<NodeHandle, Vec<f32>> {
+        self.channels
+            .iter()
+            .filter_map(|channel| match &channel.values {
+                ChannelValues::Weights(s) => Some((channel.target, s.iter().map(|s| s.sample(time)).collect())),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Keeps track of which animation is playing and maps the engine clock onto it.
+#[derive(Clone, Debug)]
+pub struct AnimationPlayer {
+    animation: Option<usize>,
+    start: Option<f64>,
+    pub speed: f32,
+    pub looping: bool,
+}
+
+impl Default for AnimationPlayer {
+    fn default() -> Self {
+        Self {
+            animation: None,
+            start: None,
+            speed: 1.0,
+            looping: true,
+        }
+    }
+}
+
+impl AnimationPlayer {
+    /// Starts `animation` from its beginning on the next clock tick.
+    pub const fn play(&mut self, animation: usize) {
+        self.animation = Some(animation);
+        self.start = None;
+    }
+
+    pub const fn stop(&mut self) {
+        self.animation = None;
+    }
+
+    #[must_use]
+    pub const fn playing(&self) -> Option<usize> {
+        self.animation
+    }
+
+    /// Returns the playing animation and its local time for the engine time `clock`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn tick(&mut self, clock: f64, duration: f32) -> Option<(usize, f32)> {
+        let animation = self.animation?;
+        let start = *self.start.get_or_insert(clock);
+        let mut time = (clock - start) as f32 * self.speed;
+        if self.looping && duration > 0.0 {
+            time = time.rem_euclid(duration);
+        }
+        Some((animation, time))
+    }
+}
+
+/// Joints and inverse bind matrices for a skinned mesh.
+#[derive(Clone, Debug)]
+pub struct Skin {
+    pub joints: Vec<NodeHandle>,
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Skin {
+    /// Joint matrices relative to the skinned mesh's node, ready for `jointMatrices`.
+    #[must_use]
+    pub fn joint_matrices(&self, graph: &mut SceneGraph, mesh_node: NodeHandle) -> Vec<Matrix4<f32>> {
+        let to_mesh = graph
+            .world_matrix(mesh_node)
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| to_mesh * graph.world_matrix(*joint) * inverse_bind)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Rotation3};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn step_holds_the_previous_keyframe() {
+        let sampler = Sampler::new(Interpolation::Step, vec![0.0, 1.0, 2.0], vec![1.0, 5.0, 3.0]).unwrap();
+        assert!(close(sampler.sample(0.0), 1.0));
+        assert!(close(sampler.sample(0.99), 1.0));
+        assert!(close(sampler.sample(1.0), 5.0));
+        assert!(close(sampler.sample(1.5), 5.0));
+    }
+
+    #[test]
+    fn linear_interpolates_between_keyframes() {
+        let sampler = Sampler::new(Interpolation::Linear, vec![1.0, 3.0], vec![2.0, 6.0]).unwrap();
+        assert!(close(sampler.sample(1.0), 2.0));
+        assert!(close(sampler.sample(2.0), 4.0));
+        assert!(close(sampler.sample(2.5), 5.0));
+        assert!(close(sampler.duration(), 3.0));
+    }
+
+    #[test]
+    fn sampling_clamps_outside_the_keyframes() {
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            let sampler = Sampler::new(interpolation, vec![1.0, 2.0], vec![2.0, 4.0]).unwrap();
+            assert!(close(sampler.sample(-5.0), 2.0));
+            assert!(close(sampler.sample(0.5), 2.0));
+            assert!(close(sampler.sample(2.0), 4.0));
+            assert!(close(sampler.sample(10.0), 4.0));
+        }
+        let cubic = Sampler::new(Interpolation::CubicSpline, vec![1.0, 2.0], vec![9.0, 2.0, 9.0, 9.0, 4.0, 9.0]).unwrap();
+        assert!(close(cubic.sample(0.0), 2.0));
+        assert!(close(cubic.sample(3.0), 4.0));
+    }
+
+    #[test]
+    fn cubic_spline_hits_its_endpoints_and_scales_tangents() {
+        // In-tangent, value, out-tangent per keyframe.
+        let values = vec![0.0, 0.0, 1.0, 0.0, 1.0, 0.0];
+        let sampler = Sampler::new(Interpolation::CubicSpline, vec![0.0, 2.0], values).unwrap();
+        assert!(close(sampler.sample(0.0), 0.0));
+        assert!(close(sampler.sample(2.0), 1.0));
+        // h01(0.5) = 0.5 and h10(0.5) = 0.125, with the out-tangent scaled by the 2s interval.
+        assert!(close(sampler.sample(1.0), 0.75));
+
+        let values = vec![0.0, 0.0, 0.0, 1.0, 1.0, 0.0];
+        let sampler = Sampler::new(Interpolation::CubicSpline, vec![0.0, 2.0], values).unwrap();
+        // h11(0.5) = -0.125 with the in-tangent scaled by the interval.
+        assert!(close(sampler.sample(1.0), 0.25));
+    }
+
+    #[test]
+    fn cubic_spline_needs_three_values_per_keyframe() {
+        assert!(Sampler::new(Interpolation::CubicSpline, vec![0.0, 1.0], vec![0.0, 1.0]).is_err());
+        assert!(Sampler::<f32>::new(Interpolation::Linear, vec![], vec![]).is_err());
+    }
+
+    #[test]
+    fn rotations_slerp_along_the_shortest_path() {
+        let start = Quaternion::from_angle_z(Deg(0.0));
+        let end = Quaternion::from_angle_z(Deg(90.0));
+        let halfway = Quaternion::from_angle_z(Deg(45.0));
+        for end in [end, -end] {
+            let sampler = Sampler::new(Interpolation::Linear, vec![0.0, 1.0], vec![start, end]).unwrap();
+            let rotation = sampler.sample(0.5);
+            assert!(close(rotation.magnitude(), 1.0));
+            assert!(close(rotation.dot(halfway).abs(), 1.0), "{rotation:?}");
+        }
+    }
+
+    #[test]
+    fn split_weights_deinterleaves_targets() {
+        let times = [0.0, 1.0];
+        let values = [0.0, 1.0, 0.5, 0.25, 0.5, 0.75];
+        let samplers = Sampler::split_weights(Interpolation::Linear, &times, &values, 3).unwrap();
+        assert_eq!(samplers.len(), 3);
+        assert!(close(samplers[0].sample(1.0), 0.25));
+        assert!(close(samplers[1].sample(0.0), 1.0));
+        assert!(close(samplers[2].sample(0.5), 0.625));
+        assert!(Sampler::split_weights(Interpolation::Linear, &times, &values, 4).is_err());
+        assert!(Sampler::split_weights(Interpolation::Linear, &times, &values, 0).is_err());
+    }
+}
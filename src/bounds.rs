@@ -0,0 +1,615 @@
+//! Bounding volumes for culling, picking and framing the camera.
+//!
+//! Transforms must be affine, such as model matrices; projections are not supported.
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// An axis-aligned bounding box. The empty box has `min` above `max`, so any point extends it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Default for Aabb {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl Aabb {
+    pub const EMPTY: Self = Self {
+        min: Vector3 { x: f32::INFINITY, y: f32::INFINITY, z: f32::INFINITY },
+        max: Vector3 { x: f32::NEG_INFINITY, y: f32::NEG_INFINITY, z: f32::NEG_INFINITY },
+    };
+
+    #[must_use]
+    pub const fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// The smallest box containing `points`, or the empty box if there are none.
+    #[must_use]
+    pub fn from_points(points: &[Vector3<f32>]) -> Self {
+        points.iter().fold(Self::EMPTY, |aabb, p| aabb.with_point(*p))
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    #[must_use]
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+
+    #[must_use]
+    pub fn size(&self) -> Vector3<f32> {
+        self.max - self.min
+    }
+
+    /// Half of the box's size along each axis.
+    #[must_use]
+    pub fn half_extents(&self) -> Vector3<f32> {
+        self.size() / 2.0
+    }
+
+    /// The box extended to contain `point`.
+    #[must_use]
+    pub const fn with_point(&self, point: Vector3<f32>) -> Self {
+        Self {
+            min: Vector3::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            max: Vector3::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        }
+    }
+
+    /// The smallest box containing both boxes.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        if other.is_empty() {
+            return *self;
+        }
+        self.with_point(other.min).with_point(other.max)
+    }
+
+    #[must_use]
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x)
+            && (self.min.y..=self.max.y).contains(&point.y)
+            && (self.min.z..=self.max.z).contains(&point.z)
+    }
+
+    /// The distance from `point` to the box, zero inside it.
+    #[must_use]
+    pub fn distance(&self, point: Vector3<f32>) -> f32 {
+        let outside = Vector3::new(
+            (self.min.x - point.x).max(point.x - self.max.x).max(0.0),
+            (self.min.y - point.y).max(point.y - self.max.y).max(0.0),
+            (self.min.z - point.z).max(point.z - self.max.z).max(0.0),
+        );
+        outside.magnitude()
+    }
+
+    /// The eight corners, with bit 0 of the index selecting max x, bit 1 max y and bit 2 max z.
+    #[must_use]
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        std::array::from_fn(|i| {
+            Vector3::new(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+            )
+        })
+    }
+
+    /// The axis-aligned box containing this box transformed by `matrix`.
+    #[must_use]
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        // Each axis of the result only depends on how far each column moves it (Arvo's method).
+        let center = (matrix * self.center().extend(1.0)).truncate();
+        let half = self.half_extents();
+        let extent = matrix.x.truncate().map(f32::abs) * half.x
+            + matrix.y.truncate().map(f32::abs) * half.y
+            + matrix.z.truncate().map(f32::abs) * half.z;
+        Self::new(center - extent, center + extent)
+    }
+}
+
+/// A sphere containing an object. The empty sphere has a negative radius.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+impl Default for BoundingSphere {
+    fn default() -> Self {
+        Self::EMPTY
+    }
+}
+
+impl BoundingSphere {
+    pub const EMPTY: Self = Self {
+        center: Vector3 { x: 0.0, y: 0.0, z: 0.0 },
+        radius: -1.0,
+    };
+
+    #[must_use]
+    pub const fn new(center: Vector3<f32>, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    /// A sphere around the center of the bounding box of `points`, reaching the farthest point.
+    #[must_use]
+    pub fn from_points(points: &[Vector3<f32>]) -> Self {
+        let aabb = Aabb::from_points(points);
+        if aabb.is_empty() {
+            return Self::EMPTY;
+        }
+        let center = aabb.center();
+        let radius = points.iter().map(|p| (p - center).magnitude()).fold(0.0, f32::max);
+        Self::new(center, radius)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    /// The smallest sphere containing both spheres.
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        if other.is_empty() {
+            return *self;
+        }
+        if self.is_empty() {
+            return *other;
+        }
+        let offset = other.center - self.center;
+        let distance = offset.magnitude();
+        if distance + other.radius <= self.radius {
+            return *self;
+        }
+        if distance + self.radius <= other.radius {
+            return *other;
+        }
+        let radius = (distance + self.radius + other.radius) / 2.0;
+        Self::new(self.center + offset * ((radius - self.radius) / distance), radius)
+    }
+
+    #[must_use]
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        (point - self.center).magnitude2() <= self.radius * self.radius
+    }
+
+    /// A sphere containing this sphere transformed by `matrix`, scaled by its largest axis scale.
+    #[must_use]
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let scale = [matrix.x, matrix.y, matrix.z]
+            .iter()
+            .map(|axis| axis.truncate().magnitude())
+            .fold(0.0, f32::max);
+        Self::new((matrix * self.center.extend(1.0)).truncate(), self.radius * scale)
+    }
+}
+
+/// A box along arbitrary axes, e.g. a local `Aabb` in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Obb {
+    pub center: Vector3<f32>,
+    /// Unit vectors along the box's edges.
+    pub axes: [Vector3<f32>; 3],
+    /// Half of the box's size along each of `axes`.
+    pub half_extents: Vector3<f32>,
+}
+
+impl Obb {
+    /// `aabb` transformed by `matrix`, which must not shear.
+    #[must_use]
+    pub fn new(aabb: &Aabb, matrix: &Matrix4<f32>) -> Self {
+        let columns = [matrix.x, matrix.y, matrix.z].map(Vector4::truncate);
+        let half = aabb.half_extents();
+        let scales = columns.map(InnerSpace::magnitude);
+        Self {
+            center: (matrix * aabb.center().extend(1.0)).truncate(),
+            axes: std::array::from_fn(|i| if scales[i] > 0.0 { columns[i] / scales[i] } else { columns[i] }),
+            half_extents: Vector3::new(half.x * scales[0], half.y * scales[1], half.z * scales[2]),
+        }
+    }
+
+    /// The eight corners, ordered like `Aabb::corners`.
+    #[must_use]
+    pub fn corners(&self) -> [Vector3<f32>; 8] {
+        std::array::from_fn(|i| {
+            let sign = |bit: usize| if i & bit == 0 { -1.0 } else { 1.0 };
+            self.center
+                + self.axes[0] * (self.half_extents.x * sign(1))
+                + self.axes[1] * (self.half_extents.y * sign(2))
+                + self.axes[2] * (self.half_extents.z * sign(4))
+        })
+    }
+
+    #[must_use]
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        let offset = point - self.center;
+        let half: [f32; 3] = self.half_extents.into();
+        self.axes.iter().zip(half).all(|(axis, half)| offset.dot(*axis).abs() <= half)
+    }
+
+    /// The axis-aligned box containing this box.
+    #[must_use]
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(&self.corners())
+    }
+}
+
+/// A half-line from `origin` along `direction`. Distances along it are in multiples of
+/// `direction`, so they stay valid for the same ray in another space; see `transformed`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    /// A ray along the normalized `direction`.
+    #[must_use]
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    #[must_use]
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// The ray transformed by `matrix`, e.g. into an object's model space with the inverse of its
+    /// world matrix. The direction isn't normalized again, so hits keep their distance.
+    #[must_use]
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        Self {
+            origin: (matrix * self.origin.extend(1.0)).truncate(),
+            direction: (matrix * self.direction.extend(0.0)).truncate(),
+        }
+    }
+
+    /// The distance at which the ray enters the box, zero if it starts inside it (slab test).
+    #[must_use]
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        if aabb.is_empty() {
+            return None;
+        }
+        let (mut near, mut far) = (0.0_f32, f32::INFINITY);
+        let origin: [f32; 3] = self.origin.into();
+        let direction: [f32; 3] = self.direction.into();
+        let (min, max): ([f32; 3], [f32; 3]) = (aabb.min.into(), aabb.max.into());
+        for axis in 0..3 {
+            if direction[axis] == 0.0 {
+                // Parallel to the slab, which would give NaN for a ray in one of its planes.
+                if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inverse = 1.0 / direction[axis];
+            let (a, b) = ((min[axis] - origin[axis]) * inverse, (max[axis] - origin[axis]) * inverse);
+            near = near.max(a.min(b));
+            far = far.min(a.max(b));
+        }
+        (near <= far).then_some(near)
+    }
+
+    /// The distance at which the ray first hits the sphere, zero if it starts inside it.
+    #[must_use]
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        if sphere.is_empty() {
+            return None;
+        }
+        let offset = self.origin - sphere.center;
+        let length2 = self.direction.magnitude2();
+        let half_b = offset.dot(self.direction);
+        let c = sphere.radius.mul_add(-sphere.radius, offset.magnitude2());
+        if c <= 0.0 {
+            return Some(0.0);
+        }
+        let discriminant = half_b.mul_add(half_b, -length2 * c);
+        if discriminant < 0.0 || half_b > 0.0 {
+            return None;
+        }
+        Some((-half_b - discriminant.sqrt()) / length2)
+    }
+
+    /// The distance at which the ray hits either side of a triangle (Möller and Trumbore).
+    #[must_use]
+    pub fn intersect_triangle(&self, corners: [Vector3<f32>; 3]) -> Option<f32> {
+        self.intersect_triangle_barycentric(corners).map(|(distance, _)| distance)
+    }
+
+    /// Like `intersect_triangle`, also returning the weights of the corners at the hit point.
+    #[must_use]
+    pub fn intersect_triangle_barycentric(&self, corners: [Vector3<f32>; 3]) -> Option<(f32, Vector3<f32>)> {
+        let (edge1, edge2) = (corners[1] - corners[0], corners[2] - corners[0]);
+        let across = self.direction.cross(edge2);
+        let determinant = edge1.dot(across);
+        if determinant.abs() < f32::EPSILON * edge1.magnitude() * edge2.magnitude() * self.direction.magnitude() {
+            return None;
+        }
+        let offset = self.origin - corners[0];
+        let u = offset.dot(across) / determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let up = offset.cross(edge1);
+        let v = self.direction.dot(up) / determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = edge2.dot(up) / determinant;
+        (distance >= 0.0).then(|| (distance, Vector3::new(1.0 - u - v, u, v)))
+    }
+}
+
+/// An axis-aligned box and a sphere around the same object.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub const EMPTY: Self = Self {
+        aabb: Aabb::EMPTY,
+        sphere: BoundingSphere::EMPTY,
+    };
+
+    #[must_use]
+    pub fn from_points(points: &[Vector3<f32>]) -> Self {
+        Self {
+            aabb: Aabb::from_points(points),
+            sphere: BoundingSphere::from_points(points),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.aabb.is_empty()
+    }
+
+    #[must_use]
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        Self {
+            aabb: self.aabb.transformed(matrix),
+            sphere: self.sphere.transformed(matrix),
+        }
+    }
+
+    #[must_use]
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            aabb: self.aabb.union(&other.aabb),
+            sphere: self.sphere.union(&other.sphere),
+        }
+    }
+}
+
+/// The space a camera sees, as six planes `(normal, d)` with normals facing inwards, so points
+/// inside have `normal · p + d >= 0` for every plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far.
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// A frustum containing everything, for drawing without culling.
+    pub const EVERYTHING: Self = Self {
+        planes: [Vector4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }; 6],
+    };
+
+    /// The frustum of an OpenGL projection times view matrix, in world space (Gribb and Hartmann).
+    /// An infinite far plane contains everything in front of the near plane.
+    #[must_use]
+    pub fn from_matrix(view_projection: &Matrix4<f32>) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|i| view_projection.row(i));
+        let planes = [w + x, w - x, w + y, w - y, w + z, w - z].map(|plane| {
+            let length = plane.truncate().magnitude();
+            if length > 0.0 { plane / length } else { plane }
+        });
+        Self { planes }
+    }
+
+    #[must_use]
+    pub fn contains(&self, point: Vector3<f32>) -> bool {
+        self.planes.iter().all(|plane| plane.dot(point.extend(1.0)) >= 0.0)
+    }
+
+    /// Whether the sphere may be inside. Spheres near corners can pass while outside.
+    #[must_use]
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        !sphere.is_empty() && self.planes.iter().all(|plane| plane.dot(sphere.center.extend(1.0)) >= -sphere.radius)
+    }
+
+    /// Whether the box may be inside, testing the corner farthest along each plane's normal.
+    #[must_use]
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        !aabb.is_empty()
+            && self.planes.iter().all(|plane| {
+                let corner = Vector3::new(
+                    if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                    if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                    if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+                );
+                plane.dot(corner.extend(1.0)) >= 0.0
+            })
+    }
+
+    /// Whether both the sphere and the box may be inside; the sphere test rejects most objects
+    /// cheaply.
+    #[must_use]
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformation::Projection;
+    use cgmath::{Deg, Rad};
+    use std::f32::consts::FRAC_PI_2;
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    fn model() -> Matrix4<f32> {
+        Matrix4::from_translation(Vector3::new(1.0, -2.0, 3.0))
+            * Matrix4::from_axis_angle(Vector3::new(1.0, 2.0, 0.5).normalize(), Deg(35.0))
+            * Matrix4::from_nonuniform_scale(2.0, 0.5, 1.5)
+    }
+
+    #[test]
+    fn transformed_aabb_matches_its_transformed_corners() {
+        let aabb = Aabb::new(Vector3::new(-1.0, 0.0, 2.0), Vector3::new(3.0, 1.0, 2.5));
+        let matrix = model();
+        let expected = Aabb::from_points(&aabb.corners().map(|c| (matrix * c.extend(1.0)).truncate()));
+        let transformed = aabb.transformed(&matrix);
+        assert!(close(transformed.min, expected.min), "{transformed:?} != {expected:?}");
+        assert!(close(transformed.max, expected.max), "{transformed:?} != {expected:?}");
+        assert!(Aabb::EMPTY.transformed(&matrix).is_empty());
+    }
+
+    #[test]
+    fn sphere_union_contains_both_spheres() {
+        let pairs = [
+            (BoundingSphere::new(Vector3::new(0.0, 0.0, 0.0), 1.0), BoundingSphere::new(Vector3::new(3.0, 1.0, 0.0), 0.5)),
+            (BoundingSphere::new(Vector3::new(1.0, 1.0, 1.0), 2.0), BoundingSphere::new(Vector3::new(1.5, 1.0, 1.0), 0.5)),
+            (BoundingSphere::new(Vector3::new(0.0, 0.0, 0.0), 0.0), BoundingSphere::new(Vector3::new(0.0, -4.0, 2.0), 3.0)),
+        ];
+        let directions = Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0)).corners().map(InnerSpace::normalize);
+        for (a, b) in pairs {
+            let union = a.union(&b);
+            for sphere in [a, b] {
+                for direction in directions {
+                    let point = sphere.center + direction * sphere.radius;
+                    assert!((point - union.center).magnitude() <= union.radius + 1e-4, "{union:?} misses {point:?}");
+                }
+            }
+            assert!(union.radius <= a.radius + b.radius + (a.center - b.center).magnitude() + 1e-4);
+        }
+        let small = BoundingSphere::new(Vector3::new(0.5, 0.0, 0.0), 0.25);
+        let large = BoundingSphere::new(Vector3::new(0.0, 0.0, 0.0), 2.0);
+        assert_eq!(small.union(&large), large);
+        assert_eq!(BoundingSphere::EMPTY.union(&small), small);
+    }
+
+    #[test]
+    fn obb_contains_points_inside_its_rotated_box() {
+        let aabb = Aabb::new(Vector3::new(-1.0, -2.0, -0.5), Vector3::new(1.0, 2.0, 0.5));
+        let matrix = Matrix4::from_translation(Vector3::new(5.0, 0.0, 0.0)) * Matrix4::from_angle_z(Deg(45.0));
+        let obb = Obb::new(&aabb, &matrix);
+        assert!(obb.contains(Vector3::new(5.0, 0.0, 0.0)));
+        let half: [f32; 3] = obb.half_extents.into();
+        for (axis, half) in obb.axes.iter().zip(half) {
+            assert!(obb.contains(obb.center + axis * (half * 0.99)));
+            assert!(obb.contains(obb.center - axis * (half * 0.99)));
+            assert!(!obb.contains(obb.center + axis * (half * 1.01)));
+        }
+        // Inside the axis-aligned box around it, but outside the rotated box.
+        assert!(obb.aabb().contains(Vector3::new(6.5, 1.5, 0.0)));
+        assert!(!obb.contains(Vector3::new(6.5, 1.5, 0.0)));
+    }
+
+    #[test]
+    fn perspective_frustum_contains_what_the_camera_sees() {
+        let projection = cgmath::perspective(Rad(FRAC_PI_2), 1.0, 0.1, 100.0);
+        let frustum = Frustum::from_matrix(&projection);
+        assert!(frustum.contains(Vector3::new(0.0, 0.0, -10.0)));
+        assert!(frustum.contains(Vector3::new(9.0, -9.0, -10.0)));
+        assert!(!frustum.contains(Vector3::new(11.0, 0.0, -10.0)));
+        assert!(!frustum.contains(Vector3::new(0.0, 11.0, -10.0)));
+        assert!(!frustum.contains(Vector3::new(0.0, 0.0, 1.0)));
+        assert!(!frustum.contains(Vector3::new(0.0, 0.0, -0.05)));
+        assert!(!frustum.contains(Vector3::new(0.0, 0.0, -200.0)));
+        // A box straddling the right plane, and one entirely to its right.
+        assert!(frustum.intersects_aabb(&Aabb::new(Vector3::new(9.0, -1.0, -11.0), Vector3::new(12.0, 1.0, -9.0))));
+        assert!(!frustum.intersects_aabb(&Aabb::new(Vector3::new(11.0, -1.0, -10.5), Vector3::new(12.0, 1.0, -9.5))));
+        // The planes follow the view, here turned to look down +X.
+        let view = Matrix4::look_at_rh(cgmath::Point3::new(0.0, 0.0, 0.0), cgmath::Point3::new(1.0, 0.0, 0.0), Vector3::unit_y());
+        let turned = Frustum::from_matrix(&(projection * view));
+        assert!(turned.contains(Vector3::new(10.0, 0.0, 0.0)));
+        assert!(!turned.contains(Vector3::new(0.0, 0.0, -10.0)));
+    }
+
+    #[test]
+    fn infinite_frustum_has_no_far_plane() {
+        let lens = Projection::Perspective {
+            yfov: FRAC_PI_2,
+            aspect_ratio: None,
+            znear: 0.1,
+            zfar: None,
+        };
+        let frustum = Frustum::from_matrix(&lens.matrix(1.0));
+        assert!(frustum.contains(Vector3::new(0.0, 0.0, -1.0e6)));
+        assert!(frustum.contains(Vector3::new(0.0, 9.0, -10.0)));
+        assert!(!frustum.contains(Vector3::new(0.0, 11.0, -10.0)));
+        assert!(!frustum.contains(Vector3::new(0.0, 0.0, -0.05)));
+        assert!(!frustum.contains(Vector3::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn rays_hit_boxes_at_the_entry_point() {
+        let aabb = Aabb::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 1.0));
+        let ray = Ray::new(Vector3::new(-5.0, 0.5, 0.5), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(ray.intersect_aabb(&aabb), Some(5.0));
+        assert_eq!(Ray::new(Vector3::new(0.5, 0.5, 0.5), Vector3::new(0.0, 1.0, 0.0)).intersect_aabb(&aabb), Some(0.0));
+        assert_eq!(Ray::new(Vector3::new(-5.0, 0.5, 0.5), Vector3::new(-1.0, 0.0, 0.0)).intersect_aabb(&aabb), None);
+        assert_eq!(Ray::new(Vector3::new(-5.0, 2.0, 0.5), Vector3::new(1.0, 0.0, 0.0)).intersect_aabb(&aabb), None);
+        // Along one of the box's faces, where the slab test divides zero by zero.
+        assert_eq!(Ray::new(Vector3::new(-5.0, 1.0, 0.5), Vector3::new(1.0, 0.0, 0.0)).intersect_aabb(&aabb), Some(5.0));
+        assert_eq!(ray.intersect_aabb(&Aabb::EMPTY), None);
+        // Distances are in multiples of the direction, so they survive transforming both.
+        let scale = Matrix4::from_scale(2.0);
+        assert_eq!(ray.transformed(&scale).intersect_aabb(&aabb.transformed(&scale)), Some(5.0));
+    }
+
+    #[test]
+    fn rays_hit_spheres_at_the_entry_point() {
+        let sphere = BoundingSphere::new(Vector3::new(0.0, 0.0, -5.0), 1.0);
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(ray.intersect_sphere(&sphere), Some(4.0));
+        assert_eq!(Ray::new(Vector3::new(0.0, 0.0, -5.5), Vector3::new(0.0, 0.0, 1.0)).intersect_sphere(&sphere), Some(0.0));
+        assert_eq!(Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0)).intersect_sphere(&sphere), None);
+        assert_eq!(Ray::new(Vector3::new(0.0, 2.0, 0.0), Vector3::new(0.0, 0.0, -1.0)).intersect_sphere(&sphere), None);
+        // Empty spheres are never hit, wherever the ray starts.
+        assert_eq!(ray.intersect_sphere(&BoundingSphere::EMPTY), None);
+        let empty = BoundingSphere::new(Vector3::new(0.0, 0.0, -5.0), -1.0);
+        assert_eq!(ray.intersect_sphere(&empty), None);
+        assert_eq!(Ray::new(empty.center, Vector3::new(0.0, 0.0, 1.0)).intersect_sphere(&empty), None);
+    }
+
+    #[test]
+    fn rays_hit_triangles_with_barycentric_weights() {
+        let corners = [Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)];
+        let ray = Ray::new(Vector3::new(0.25, 0.5, 2.0), Vector3::new(0.0, 0.0, -1.0));
+        let (distance, weights) = ray.intersect_triangle_barycentric(corners).unwrap();
+        assert!((distance - 2.0).abs() < 1e-6);
+        assert!(close(weights, Vector3::new(0.25, 0.25, 0.5)));
+        let point = corners[0] * weights.x + corners[1] * weights.y + corners[2] * weights.z;
+        assert!(close(point, ray.at(distance)));
+        // Triangles are hit from both sides.
+        assert!(Ray::new(Vector3::new(0.25, 0.5, -1.0), Vector3::new(0.0, 0.0, 1.0)).intersect_triangle(corners).is_some());
+        assert!(Ray::new(Vector3::new(0.8, 0.8, 1.0), Vector3::new(0.0, 0.0, -1.0)).intersect_triangle(corners).is_none());
+        assert!(Ray::new(Vector3::new(0.25, 0.5, -1.0), Vector3::new(0.0, 0.0, -1.0)).intersect_triangle(corners).is_none());
+        assert!(Ray::new(Vector3::new(-1.0, 0.5, 0.0), Vector3::new(1.0, 0.0, 0.0)).intersect_triangle(corners).is_none());
+    }
+}
@@ -0,0 +1,581 @@
+//! A bounding volume hierarchy for finding objects or triangles by region, ray or distance
+//! without testing every one of them.
+//!
+//! The tree is built over the axis-aligned boxes of numbered items, and queries return item
+//! numbers. When items move, `update` or `refit` adjust the boxes without rebuilding the tree;
+//! after large changes, building a new tree gives faster queries.
+use crate::bounds::{Aabb, Frustum, Ray};
+use cgmath::{Vector3, Zero};
+
+/// The most items a leaf holds.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// Bins the surface area heuristic tries split positions at.
+const BINS: usize = 12;
+
+#[derive(Clone, Copy, Debug)]
+enum NodeKind {
+    /// `count` items starting at `first` in `Bvh::items`.
+    Leaf {
+        first: u32,
+        count: u32,
+    },
+    Inner {
+        left: u32,
+        right: u32,
+    },
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    aabb: Aabb,
+    /// `u32::MAX` for the root.
+    parent: u32,
+    kind: NodeKind,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Bvh {
+    /// Parents come before their children; the root is the first node.
+    nodes: Vec<Node>,
+    /// Item numbers, grouped by leaf.
+    items: Vec<u32>,
+    /// The box of each item.
+    bounds: Vec<Aabb>,
+    /// The leaf each item is in.
+    leaf_of: Vec<u32>,
+}
+
+impl Bvh {
+    /// Builds a tree over items `0..bounds.len()` with the given boxes, splitting where the
+    /// surface area heuristic expects the cheapest queries. Empty boxes are never returned.
+    #[must_use]
+    pub fn new(bounds: Vec<Aabb>) -> Self {
+        let mut ret = Self {
+            nodes: Vec::new(),
+            items: (0..bounds.len() as u32).collect(),
+            leaf_of: vec![0; bounds.len()],
+            bounds,
+        };
+        if !ret.items.is_empty() {
+            ret.build(u32::MAX, 0, ret.items.len());
+        }
+        ret
+    }
+
+    /// A tree over the triangles of a triangle list, numbered by their position in it.
+    #[must_use]
+    pub fn from_triangles(vertices: &[Vector3<f32>], indices: &[u32]) -> Self {
+        Self::new(
+            indices
+                .chunks_exact(3)
+                .map(|t| Aabb::from_points(&[t[0], t[1], t[2]].map(|i| vertices[i as usize])))
+                .collect(),
+        )
+    }
+
+    /// The number of items.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.bounds.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.bounds.is_empty()
+    }
+
+    /// The box of `item`.
+    #[must_use]
+    pub fn item_bounds(&self, item: usize) -> &Aabb {
+        &self.bounds[item]
+    }
+
+    /// The box around all items.
+    #[must_use]
+    pub fn root_bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |root| root.aabb)
+    }
+
+    /// Adds a node for `items[start..end]` below `parent` and returns its index.
+    #[allow(clippy::cast_possible_truncation)]
+    fn build(&mut self, parent: u32, start: usize, end: usize) -> u32 {
+        let index = self.nodes.len() as u32;
+        let aabb = self.items[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |aabb, &i| aabb.union(&self.bounds[i as usize]));
+        self.nodes.push(Node {
+            aabb,
+            parent,
+            kind: NodeKind::Leaf {
+                first: start as u32,
+                count: (end - start) as u32,
+            },
+        });
+        let Some(middle) = self.split(start, end) else {
+            for &item in &self.items[start..end] {
+                self.leaf_of[item as usize] = index;
+            }
+            return index;
+        };
+        let left = self.build(index, start, middle);
+        let right = self.build(index, middle, end);
+        self.nodes[index as usize].kind = NodeKind::Inner { left, right };
+        index
+    }
+
+    /// Partitions `items[start..end]` into two halves and returns where the second one starts, or
+    /// `None` if they should stay in one leaf.
+    fn split(&mut self, start: usize, end: usize) -> Option<usize> {
+        let count = end - start;
+        if count <= MAX_LEAF_SIZE {
+            return None;
+        }
+        let centroid = |aabb: &Aabb| {
+            if aabb.is_empty() {
+                Vector3::zero()
+            } else {
+                aabb.center()
+            }
+        };
+        let centroids = self.items[start..end].iter().fold(Aabb::EMPTY, |aabb, &i| {
+            aabb.with_point(centroid(&self.bounds[i as usize]))
+        });
+        let size: [f32; 3] = centroids.size().into();
+        let axis = (0..3)
+            .max_by(|&a, &b| size[a].total_cmp(&size[b]))
+            .unwrap_or(0);
+        if size[axis] <= 0.0 {
+            // All centroids coincide, so only an arbitrary split keeps leaves small.
+            return Some(start + count / 2);
+        }
+        let min: [f32; 3] = centroids.min.into();
+        let bin_of = |aabb: &Aabb| {
+            let position: [f32; 3] = centroid(aabb).into();
+            (((position[axis] - min[axis]) / size[axis] * BINS as f32) as usize).min(BINS - 1)
+        };
+        let mut bins = [(Aabb::EMPTY, 0usize); BINS];
+        for &item in &self.items[start..end] {
+            let bin = &mut bins[bin_of(&self.bounds[item as usize])];
+            bin.0 = bin.0.union(&self.bounds[item as usize]);
+            bin.1 += 1;
+        }
+        // The cost of splitting after each bin: box area times item count on both sides.
+        let mut right_costs = [0.0; BINS];
+        let (mut aabb, mut items) = (Aabb::EMPTY, 0);
+        for bin in (1..BINS).rev() {
+            aabb = aabb.union(&bins[bin].0);
+            items += bins[bin].1;
+            right_costs[bin - 1] = surface_area(&aabb) * items as f32;
+        }
+        let (mut aabb, mut items) = (Aabb::EMPTY, 0);
+        let mut best = (f32::INFINITY, 0);
+        for bin in 0..BINS - 1 {
+            aabb = aabb.union(&bins[bin].0);
+            items += bins[bin].1;
+            let cost = surface_area(&aabb).mul_add(items as f32, right_costs[bin]);
+            if items > 0 && items < count && cost < best.0 {
+                best = (cost, bin);
+            }
+        }
+        let (bounds, items) = (&self.bounds, &mut self.items[start..end]);
+        let mut middle = 0;
+        for i in 0..items.len() {
+            if bin_of(&bounds[items[i] as usize]) <= best.1 {
+                items.swap(i, middle);
+                middle += 1;
+            }
+        }
+        Some(start + middle.clamp(1, count - 1))
+    }
+
+    /// Replaces the box of `item` and grows or shrinks the boxes of the nodes above it.
+    pub fn update(&mut self, item: usize, aabb: Aabb) {
+        self.bounds[item] = aabb;
+        let mut node = self.leaf_of[item];
+        while node != u32::MAX {
+            let refitted = self.node_bounds(node as usize);
+            let node_ref = &mut self.nodes[node as usize];
+            if node_ref.aabb == refitted {
+                break;
+            }
+            node_ref.aabb = refitted;
+            node = node_ref.parent;
+        }
+    }
+
+    /// Replaces the boxes of all items, keeping the tree's structure.
+    /// # Panics
+    /// If `bounds` has a different number of items than the tree.
+    pub fn refit(&mut self, bounds: Vec<Aabb>) {
+        assert_eq!(
+            bounds.len(),
+            self.bounds.len(),
+            "Refitting needs a box for every item"
+        );
+        self.bounds = bounds;
+        // Children come after their parents, so going backwards visits them first.
+        for node in (0..self.nodes.len()).rev() {
+            self.nodes[node].aabb = self.node_bounds(node);
+        }
+    }
+
+    /// The box around a node's children or items.
+    fn node_bounds(&self, node: usize) -> Aabb {
+        match self.nodes[node].kind {
+            NodeKind::Leaf { first, count } => self.items[first as usize..(first + count) as usize]
+                .iter()
+                .fold(Aabb::EMPTY, |aabb, &i| aabb.union(&self.bounds[i as usize])),
+            NodeKind::Inner { left, right } => self.nodes[left as usize]
+                .aabb
+                .union(&self.nodes[right as usize].aabb),
+        }
+    }
+
+    /// The items whose boxes may be inside `frustum`.
+    #[must_use]
+    pub fn query_frustum(&self, frustum: &Frustum) -> Vec<usize> {
+        self.collect(|aabb| frustum.intersects_aabb(aabb))
+    }
+
+    /// The items whose boxes overlap `aabb`.
+    #[must_use]
+    pub fn query_aabb(&self, aabb: &Aabb) -> Vec<usize> {
+        self.collect(|other| {
+            aabb.min.x <= other.max.x
+                && other.min.x <= aabb.max.x
+                && aabb.min.y <= other.max.y
+                && other.min.y <= aabb.max.y
+                && aabb.min.z <= other.max.z
+                && other.min.z <= aabb.max.z
+        })
+    }
+
+    /// The items whose boxes `ray` enters within `max_distance`, with the entry distance, nearest
+    /// first.
+    #[must_use]
+    pub fn query_ray(&self, ray: &Ray, max_distance: f32) -> Vec<(usize, f32)> {
+        let hit = |aabb: &Aabb| ray.intersect_aabb(aabb).filter(|&d| d <= max_distance);
+        let mut hits: Vec<_> = self
+            .collect(|aabb| hit(aabb).is_some())
+            .into_iter()
+            .filter_map(|item| hit(&self.bounds[item]).map(|d| (item, d)))
+            .collect();
+        hits.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+        hits
+    }
+
+    /// The nearest exact hit along `ray` within `max_distance`, where `hit` returns the distance
+    /// at which the ray hits an item, if it does. Items are tried nearest box first, and ones
+    /// whose box is farther than the best hit so far are skipped.
+    pub fn raycast(
+        &self,
+        ray: &Ray,
+        max_distance: f32,
+        mut hit: impl FnMut(usize) -> Option<f32>,
+    ) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        let limit = |best: &Option<(usize, f32)>| best.map_or(max_distance, |(_, d)| d);
+        let mut stack = Vec::new();
+        if let Some(entry) = self
+            .nodes
+            .first()
+            .and_then(|root| ray.intersect_aabb(&root.aabb))
+        {
+            stack.push((0, entry));
+        }
+        while let Some((node, entry)) = stack.pop() {
+            if entry > limit(&best) {
+                continue;
+            }
+            match self.nodes[node].kind {
+                NodeKind::Leaf { first, count } => {
+                    for &item in &self.items[first as usize..(first + count) as usize] {
+                        let item = item as usize;
+                        if ray
+                            .intersect_aabb(&self.bounds[item])
+                            .is_none_or(|d| d > limit(&best))
+                        {
+                            continue;
+                        }
+                        if let Some(distance) = hit(item).filter(|&d| d <= limit(&best)) {
+                            best = Some((item, distance));
+                        }
+                    }
+                }
+                NodeKind::Inner { left, right } => {
+                    let mut children: Vec<_> = [left, right]
+                        .into_iter()
+                        .filter_map(|c| {
+                            ray.intersect_aabb(&self.nodes[c as usize].aabb)
+                                .map(|d| (c as usize, d))
+                        })
+                        .collect();
+                    // Pushed farthest first, so the nearest child is searched first.
+                    children.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    stack.extend(children);
+                }
+            }
+        }
+        best
+    }
+
+    /// The nearest item to `point` within `max_distance`, where `distance` returns the exact
+    /// distance to an item, which must not be less than the distance to its box. Use
+    /// `|i| bvh.item_bounds(i).distance(point)` to find the nearest box.
+    pub fn nearest(
+        &self,
+        point: Vector3<f32>,
+        max_distance: f32,
+        mut distance: impl FnMut(usize) -> f32,
+    ) -> Option<(usize, f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        let limit = |best: &Option<(usize, f32)>| best.map_or(max_distance, |(_, d)| d);
+        let mut stack = Vec::new();
+        if let Some(root) = self.nodes.first().filter(|root| !root.aabb.is_empty()) {
+            stack.push((0, root.aabb.distance(point)));
+        }
+        while let Some((node, lower_bound)) = stack.pop() {
+            if lower_bound > limit(&best) {
+                continue;
+            }
+            match self.nodes[node].kind {
+                NodeKind::Leaf { first, count } => {
+                    for &item in &self.items[first as usize..(first + count) as usize] {
+                        let item = item as usize;
+                        if self.bounds[item].is_empty()
+                            || self.bounds[item].distance(point) > limit(&best)
+                        {
+                            continue;
+                        }
+                        let exact = distance(item);
+                        if exact <= limit(&best) {
+                            best = Some((item, exact));
+                        }
+                    }
+                }
+                NodeKind::Inner { left, right } => {
+                    let mut children: Vec<_> = [left, right]
+                        .into_iter()
+                        .map(|c| &self.nodes[c as usize])
+                        .zip([left, right])
+                        .filter(|(child, _)| !child.aabb.is_empty())
+                        .map(|(child, c)| (c as usize, child.aabb.distance(point)))
+                        .collect();
+                    children.sort_by(|a, b| b.1.total_cmp(&a.1));
+                    stack.extend(children);
+                }
+            }
+        }
+        best
+    }
+
+    /// The items in every leaf reachable through boxes accepted by `accept`, whose own box is
+    /// accepted as well.
+    fn collect(&self, accept: impl Fn(&Aabb) -> bool) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() {
+            Vec::new()
+        } else {
+            vec![0]
+        };
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if !accept(&node.aabb) {
+                continue;
+            }
+            match node.kind {
+                NodeKind::Leaf { first, count } => found.extend(
+                    self.items[first as usize..(first + count) as usize]
+                        .iter()
+                        .map(|&i| i as usize)
+                        .filter(|&i| accept(&self.bounds[i])),
+                ),
+                NodeKind::Inner { left, right } => stack.extend([right as usize, left as usize]),
+            }
+        }
+        found
+    }
+}
+
+/// Half the surface area of a box, which is proportional to how likely a ray hits it.
+fn surface_area(aabb: &Aabb) -> f32 {
+    if aabb.is_empty() {
+        return 0.0;
+    }
+    let size = aabb.size();
+    size.x
+        .mul_add(size.y, size.y.mul_add(size.z, size.z * size.x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bounds::BoundingSphere;
+    use cgmath::{perspective, Deg, InnerSpace, Matrix4, Point3};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    /// `count` boxes of up to 2 units scattered over 40 units, every tenth one empty.
+    fn random_boxes(rng: &mut StdRng, count: usize) -> Vec<Aabb> {
+        (0..count)
+            .map(|i| {
+                if i % 10 == 9 {
+                    return Aabb::EMPTY;
+                }
+                let min = Vector3::new(rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0), rng.gen_range(-20.0..20.0));
+                let size = Vector3::new(rng.gen_range(0.0..2.0), rng.gen_range(0.0..2.0), rng.gen_range(0.0..2.0));
+                Aabb::new(min, min + size)
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut StdRng) -> Ray {
+        let origin = Vector3::new(rng.gen_range(-30.0..30.0), rng.gen_range(-30.0..30.0), 30.0);
+        let target = Vector3::new(rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0), rng.gen_range(-15.0..15.0));
+        Ray::new(origin, target - origin)
+    }
+
+    fn sorted(mut items: Vec<usize>) -> Vec<usize> {
+        items.sort_unstable();
+        items
+    }
+
+    /// Checks that every item is in exactly the leaf `leaf_of` names, that node boxes are exactly
+    /// the union of their contents and that leaves are small.
+    fn assert_consistent(bvh: &Bvh) {
+        let mut seen = vec![0; bvh.len()];
+        for (index, node) in bvh.nodes.iter().enumerate() {
+            assert_eq!(node.aabb, bvh.node_bounds(index), "node {index} has a stale box");
+            match node.kind {
+                NodeKind::Leaf { first, count } => {
+                    assert!(count as usize <= MAX_LEAF_SIZE, "leaf {index} has {count} items");
+                    for &item in &bvh.items[first as usize..(first + count) as usize] {
+                        seen[item as usize] += 1;
+                        assert_eq!(bvh.leaf_of[item as usize] as usize, index);
+                    }
+                }
+                NodeKind::Inner { left, right } => {
+                    assert_eq!(bvh.nodes[left as usize].parent as usize, index);
+                    assert_eq!(bvh.nodes[right as usize].parent as usize, index);
+                }
+            }
+        }
+        assert!(seen.iter().all(|&n| n == 1), "items are missing or repeated");
+    }
+
+    fn items_below(bvh: &Bvh, node: usize) -> Vec<usize> {
+        match bvh.nodes[node].kind {
+            NodeKind::Leaf { first, count } => {
+                bvh.items[first as usize..(first + count) as usize].iter().map(|&i| i as usize).collect()
+            }
+            NodeKind::Inner { left, right } => {
+                let mut items = items_below(bvh, left as usize);
+                items.extend(items_below(bvh, right as usize));
+                items
+            }
+        }
+    }
+
+    fn assert_matches_linear_scan(bvh: &Bvh, boxes: &[Aabb], rng: &mut StdRng) {
+        assert_consistent(bvh);
+        let overlaps = |a: &Aabb, b: &Aabb| {
+            !a.is_empty() && !b.is_empty() && (0..3).all(|k| a.min[k] <= b.max[k] && b.min[k] <= a.max[k])
+        };
+        let linear = |accept: &dyn Fn(&Aabb) -> bool| -> Vec<usize> {
+            (0..boxes.len()).filter(|&i| accept(&boxes[i])).collect()
+        };
+        for _ in 0..20 {
+            let region = random_boxes(rng, 1)[0];
+            let region = Aabb::new(region.min, region.min + region.size() * 5.0);
+            assert_eq!(sorted(bvh.query_aabb(&region)), linear(&|b| overlaps(b, &region)));
+
+            let ray = random_ray(rng);
+            let eye = Point3::new(ray.origin.x, ray.origin.y, ray.origin.z);
+            let view = Matrix4::look_at_rh(eye, eye + ray.direction, Vector3::unit_y());
+            let frustum = Frustum::from_matrix(&(perspective(Deg(40.0), 1.5, 0.1, 60.0) * view));
+            assert_eq!(sorted(bvh.query_frustum(&frustum)), linear(&|b| frustum.intersects_aabb(b)));
+
+            let mut expected: Vec<_> = (0..boxes.len())
+                .filter_map(|i| ray.intersect_aabb(&boxes[i]).filter(|&d| d <= 40.0).map(|d| (i, d)))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+            assert_eq!(bvh.query_ray(&ray, 40.0), expected);
+
+            // Items are hit at the sphere inside their box, which may be missed though the box is hit.
+            let sphere = |i: usize| BoundingSphere::new(boxes[i].center(), boxes[i].size().magnitude() / 4.0);
+            let hit = |i: usize| (!boxes[i].is_empty()).then(|| ray.intersect_sphere(&sphere(i))).flatten();
+            let nearest_hit = (0..boxes.len())
+                .filter_map(|i| hit(i).map(|d| (i, d)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            assert_eq!(bvh.raycast(&ray, f32::INFINITY, hit), nearest_hit);
+
+            let point = ray.origin + ray.direction * 30.0;
+            let distance = |i: usize| (sphere(i).center - point).magnitude();
+            let nearest = (0..boxes.len())
+                .filter(|&i| !boxes[i].is_empty())
+                .map(|i| (i, distance(i)))
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            assert_eq!(bvh.nearest(point, f32::INFINITY, distance), nearest);
+            let limited = nearest.filter(|&(_, d)| d <= 3.0);
+            assert_eq!(bvh.nearest(point, 3.0, distance), limited);
+        }
+    }
+
+    #[test]
+    fn queries_match_a_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let boxes = random_boxes(&mut rng, 500);
+        let bvh = Bvh::new(boxes.clone());
+        assert_eq!(bvh.len(), 500);
+        let root = boxes.iter().fold(Aabb::EMPTY, |aabb, b| aabb.union(b));
+        assert_eq!(bvh.root_bounds(), root);
+        assert_matches_linear_scan(&bvh, &boxes, &mut rng);
+    }
+
+    #[test]
+    fn updated_and_refitted_trees_match_a_linear_scan() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let mut boxes = random_boxes(&mut rng, 300);
+        let mut bvh = Bvh::new(boxes.clone());
+        let moved = random_boxes(&mut rng, 300);
+        for i in (0..boxes.len()).step_by(7) {
+            boxes[i] = moved[i];
+            bvh.update(i, moved[i]);
+        }
+        assert_matches_linear_scan(&bvh, &boxes, &mut rng);
+
+        let boxes = random_boxes(&mut rng, 300);
+        bvh.refit(boxes.clone());
+        assert_matches_linear_scan(&bvh, &boxes, &mut rng);
+    }
+
+    #[test]
+    fn the_surface_area_heuristic_splits_clusters_apart() {
+        // Two tight clusters far apart along x, with one stray box in between.
+        let unit = |x: f32, y: f32| Aabb::new(Vector3::new(x, y, 0.0), Vector3::new(x + 0.1, y + 0.1, 0.1));
+        let mut boxes: Vec<_> = (0..4).map(|i| unit(0.0, i as f32 * 0.2)).collect();
+        boxes.extend((0..4).map(|i| unit(100.0, i as f32 * 0.2)));
+        boxes.push(unit(30.0, 0.0));
+        let bvh = Bvh::new(boxes);
+        assert_consistent(&bvh);
+        let NodeKind::Inner { left, right } = bvh.nodes[0].kind else {
+            panic!("nine items need more than one leaf");
+        };
+        let items = |node: u32| sorted(items_below(&bvh, node as usize));
+        let (left, right) = (items(left), items(right));
+        // The stray box joins the nearer cluster instead of splitting the space in half.
+        assert!(left == vec![0, 1, 2, 3, 8] || right == vec![0, 1, 2, 3, 8], "{left:?} | {right:?}");
+    }
+
+    #[test]
+    fn empty_trees_find_nothing() {
+        let bvh = Bvh::new(Vec::new());
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::unit_z());
+        assert!(bvh.is_empty());
+        assert!(bvh.root_bounds().is_empty());
+        assert!(bvh.query_aabb(&Aabb::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0))).is_empty());
+        assert_eq!(bvh.raycast(&ray, f32::INFINITY, |_| Some(0.0)), None);
+        assert_eq!(bvh.nearest(Vector3::new(0.0, 0.0, 0.0), f32::INFINITY, |_| 0.0), None);
+    }
+}
@@ -82,14 +82,17 @@ impl Draw {
             None,
             None,
         );
-        let mut circle_instanced = Rc::from(RefCell::from(Box::from(InstancedObject::new(
+        let mut circle_instanced = InstancedObject::new(
             circle.vertices.clone(),
             circle.indices,
             None, // Default normals
             &shader,
             vec![],
             vec![],
-        )) as Box<dyn Render>));
+        );
+        // The drawing shader places shapes in clip space, out of reach of the camera's frustum.
+        circle_instanced.set_screen_space(true);
+        let circle_instanced = Rc::from(RefCell::from(Box::from(circle_instanced) as Box<dyn Render>));
         engine.data.add_renderable_rc(&circle_instanced);
 
         let mut rectangle_instanced = InstancedObject::new(
@@ -99,6 +102,7 @@ impl Draw {
             &shader,
             vec![],
             vec![]);
+        rectangle_instanced.set_screen_space(true);
         let refr = Rc::from(RefCell::from(Box::from(rectangle_instanced) as Box<dyn Render>));
         engine.data.add_renderable_rc(&refr);
         Self {
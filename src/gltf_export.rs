@@ -0,0 +1,537 @@
+//! Writing meshes, transforms and materials to binary glTF (`.glb`) files.
+//!
+//! Shaders don't keep the material they were compiled from, so materials are passed to the
+//! exporter explicitly with `GltfExporter::add_material`.
+use crate::renderable::{InstancedObject, Mesh, Renderable, RenderableGroup};
+use crate::scene::NodeHandle;
+use crate::shader::{NarrowingMaterial, TextureOr, TextureOrScalar};
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use gl::types::GLenum;
+use gltf::binary::{Glb, Header};
+use gltf::json;
+use gltf::json::validation::Checked::Valid;
+use gltf::json::validation::USize64;
+use gltf::json::Index;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, ImageFormat, Rgba, RgbaImage};
+use log::warn;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, Write};
+
+/// Collects nodes, meshes and materials and writes them as a single-scene `.glb`.
+///
+/// All vertex, index and image data goes into the file's binary chunk. Nodes added without a
+/// parent become roots of the scene.
+pub struct GltfExporter {
+    root: json::Root,
+    bin: Vec<u8>,
+    scene_nodes: Vec<Index<json::Node>>,
+}
+
+impl Default for GltfExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GltfExporter {
+    #[must_use]
+    pub fn new() -> Self {
+        let mut root = json::Root::default();
+        root.asset.generator = Some("rust-gl".to_owned());
+        Self {
+            root,
+            bin: Vec::new(),
+            scene_nodes: Vec::new(),
+        }
+    }
+
+    /// Adds a material; texture maps are embedded as PNG images.
+    ///
+    /// Metallic and roughness maps are packed into the blue and green channels of one texture as
+    /// glTF requires. The specular strength uses `KHR_materials_specular`. Without a roughness,
+    /// it is approximated from the Phong shininess.
+    /// # Errors
+    /// If an image cannot be encoded.
+    pub fn add_material(
+        &mut self,
+        material: &NarrowingMaterial,
+        name: Option<&str>,
+    ) -> Result<Index<json::Material>, Box<dyn Error>> {
+        let mut ret = json::Material {
+            name: name.map(str::to_owned),
+            ..Default::default()
+        };
+        let pbr = &mut ret.pbr_metallic_roughness;
+        match &material.diffuse {
+            Some(TextureOr::Texture(image)) => pbr.base_color_texture = Some(self.push_texture(image)?),
+            Some(TextureOr::Value(color)) => pbr.base_color_factor = json::material::PbrBaseColorFactor(*color),
+            None => {}
+        }
+        match &material.opacity {
+            Some(TextureOr::Value(opacity)) => pbr.base_color_factor.0[3] *= opacity,
+            Some(TextureOr::Texture(_)) => warn!("Opacity maps aren't exported to glTF"),
+            None => {}
+        }
+        if pbr.base_color_factor.0[3] < 1.0 {
+            ret.alpha_mode = Valid(json::material::AlphaMode::Blend);
+        }
+
+        pbr.metallic_factor = json::material::StrengthFactor(scalar_factor(material.metallic.as_ref()).unwrap_or(0.0));
+        pbr.roughness_factor = json::material::StrengthFactor(
+            scalar_factor(material.roughness.as_ref())
+                .or_else(|| material.shininess.map(|n| (2.0 / (n + 2.0)).sqrt()))
+                .unwrap_or(1.0),
+        );
+        let metallic = scalar_texture(material.metallic.as_ref());
+        let roughness = scalar_texture(material.roughness.as_ref());
+        if let Some(first) = metallic.or(roughness) {
+            let (width, height) = (first.width(), first.height());
+            let channel = |image: Option<&DynamicImage>| {
+                image.map(|image| image::imageops::resize(&image.to_luma8(), width, height, FilterType::Triangle))
+            };
+            let (metallic, roughness): (Option<GrayImage>, Option<GrayImage>) = (channel(metallic), channel(roughness));
+            let packed = RgbaImage::from_fn(width, height, |x, y| {
+                let value = |image: &Option<GrayImage>| image.as_ref().map_or(255, |image| image.get_pixel(x, y)[0]);
+                Rgba([0, value(&roughness), value(&metallic), 255])
+            });
+            ret.pbr_metallic_roughness.metallic_roughness_texture =
+                Some(self.push_texture(&DynamicImage::ImageRgba8(packed))?);
+        }
+
+        match &material.emissive {
+            Some(TextureOr::Texture(image)) => {
+                ret.emissive_texture = Some(self.push_texture(image)?);
+                ret.emissive_factor = json::material::EmissiveFactor([1.0; 3]);
+            }
+            Some(TextureOr::Value(color)) => {
+                ret.emissive_factor = json::material::EmissiveFactor([color[0], color[1], color[2]]);
+            }
+            None => {}
+        }
+        if let Some(TextureOr::Texture(image)) = &material.normal {
+            ret.normal_texture = Some(json::material::NormalTexture {
+                index: self.push_texture(image)?.index,
+                scale: 1.0,
+                tex_coord: 0,
+                extensions: None,
+                extras: json::Extras::default(),
+            });
+        }
+        if let Some(specular) = &material.specular {
+            let mut extension = json::extensions::material::Specular::default();
+            match specular {
+                TextureOr::Texture(image) => {
+                    // The specular strength is read from the alpha channel.
+                    let mut rgba = image.to_rgba8();
+                    rgba.pixels_mut().for_each(|p| p[3] = p[0]);
+                    extension.specular_texture = Some(self.push_texture(&DynamicImage::ImageRgba8(rgba))?);
+                }
+                TextureOr::Value(value) => extension.specular_factor = json::extensions::material::SpecularFactor(*value),
+            }
+            ret.extensions.get_or_insert_with(json::extensions::material::Material::default).specular = Some(extension);
+            self.use_extension("KHR_materials_specular");
+        }
+        Ok(self.root.push(ret))
+    }
+
+    /// Adds the vertex data of `mesh` as a glTF mesh with one primitive.
+    ///
+    /// Positions, normals, texture coordinates, vertex colors and indices are written; skinning
+    /// and morph targets are not. Returns `None` for a mesh without vertices, as glTF accessors
+    /// can't be empty.
+    /// # Errors
+    /// If `draw_type` isn't an OpenGL primitive type or the mesh is too large.
+    pub fn add_mesh(
+        &mut self,
+        mesh: &Mesh,
+        draw_type: GLenum,
+        material: Option<Index<json::Material>>,
+        name: Option<&str>,
+    ) -> Result<Option<Index<json::Mesh>>, Box<dyn Error>> {
+        if mesh.vertices.is_empty() {
+            return Ok(None);
+        }
+        let mut attributes = BTreeMap::new();
+        let positions: Vec<[f32; 3]> = mesh.vertices.iter().map(|v| (*v).into()).collect();
+        let (min, max) = bounds(&mesh.vertices);
+        let attribute = self.push_accessor(&positions, json::accessor::Type::Vec3, Some((min, max)));
+        attributes.insert(Valid(json::mesh::Semantic::Positions), attribute);
+        if let Some(normals) = &mesh.normals {
+            let normals: Vec<[f32; 3]> = normals.iter().map(|v| (*v).into()).collect();
+            let attribute = self.push_accessor(&normals, json::accessor::Type::Vec3, None);
+            attributes.insert(Valid(json::mesh::Semantic::Normals), attribute);
+        }
+        if let Some(tex_coords) = &mesh.tex_coords {
+            let tex_coords: Vec<[f32; 2]> = tex_coords.iter().map(|v| (*v).into()).collect();
+            let attribute = self.push_accessor(&tex_coords, json::accessor::Type::Vec2, None);
+            attributes.insert(Valid(json::mesh::Semantic::TexCoords(0)), attribute);
+        }
+        if let Some(colors) = &mesh.colors {
+            let attribute = self.push_accessor(colors, json::accessor::Type::Vec4, None);
+            attributes.insert(Valid(json::mesh::Semantic::Colors(0)), attribute);
+        }
+        let indices = if mesh.indices.is_empty() {
+            None
+        } else {
+            let view = self.push_view(bytemuck::cast_slice(&mesh.indices), Some(json::buffer::Target::ElementArrayBuffer));
+            Some(self.root.push(accessor(view, mesh.indices.len(), json::accessor::ComponentType::U32, json::accessor::Type::Scalar)))
+        };
+        let primitive = json::mesh::Primitive {
+            attributes,
+            extensions: None,
+            extras: json::Extras::default(),
+            indices,
+            material,
+            mode: Valid(mode(draw_type)?),
+            targets: None,
+        };
+        Ok(Some(self.root.push(json::Mesh {
+            extensions: None,
+            extras: json::Extras::default(),
+            name: name.map(str::to_owned),
+            primitives: vec![primitive],
+            weights: None,
+        })))
+    }
+
+    /// Adds a node with a local `matrix`, under `parent` or as a root of the scene.
+    pub fn add_node(
+        &mut self,
+        name: Option<&str>,
+        matrix: Matrix4<f32>,
+        mesh: Option<Index<json::Mesh>>,
+        parent: Option<Index<json::Node>>,
+    ) -> Index<json::Node> {
+        let node = self.root.push(json::Node {
+            name: name.map(str::to_owned),
+            matrix: (matrix != Matrix4::identity()).then(|| *matrix.as_ref()),
+            mesh,
+            ..Default::default()
+        });
+        match parent {
+            Some(parent) => self.root.nodes[parent.value()].children.get_or_insert_with(Vec::new).push(node),
+            None => self.scene_nodes.push(node),
+        }
+        node
+    }
+
+    /// Adds a renderable as a node with its world matrix.
+    /// # Errors
+    /// If its mesh cannot be added.
+    pub fn add_renderable(
+        &mut self,
+        renderable: &Renderable,
+        material: Option<Index<json::Material>>,
+        parent: Option<Index<json::Node>>,
+    ) -> Result<Index<json::Node>, Box<dyn Error>> {
+        let mesh = self.add_mesh(&renderable.mesh_data, renderable.draw_type, material, None)?;
+        let matrix = if parent.is_some() {
+            renderable.transform.mat()
+        } else {
+            renderable.world_matrix()
+        };
+        Ok(self.add_node(None, matrix, mesh, parent))
+    }
+
+    /// Adds a group with its node hierarchy. Each renderable becomes a child of its node.
+    ///
+    /// `materials` is parallel to the group's renderables; missing entries export without a
+    /// material.
+    /// # Errors
+    /// If a mesh cannot be added.
+    pub fn add_group(
+        &mut self,
+        group: &RenderableGroup,
+        materials: &[Option<Index<json::Material>>],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut nodes = vec![None; group.graph().len()];
+        let mut stack: Vec<(NodeHandle, Option<Index<json::Node>>)> = group.graph().roots().map(|r| (r, None)).collect();
+        while let Some((handle, parent)) = stack.pop() {
+            let node = group.graph().node(handle);
+            let index = self.add_node(node.name.as_deref(), node.local_matrix(), None, parent);
+            nodes[handle.index()] = Some(index);
+            stack.extend(node.children().iter().map(|c| (*c, Some(index))));
+        }
+        for (i, renderable) in group.renderables().iter().enumerate() {
+            let parent = nodes[group.node_of(i).index()];
+            self.add_renderable(renderable, materials.get(i).copied().flatten(), parent)?;
+        }
+        Ok(())
+    }
+
+    /// Adds an instanced object as one node per instance, all sharing a mesh.
+    ///
+    /// Per-instance colors aren't exported.
+    /// # Errors
+    /// If the mesh cannot be added.
+    pub fn add_instanced(
+        &mut self,
+        object: &InstancedObject,
+        material: Option<Index<json::Material>>,
+    ) -> Result<Index<json::Node>, Box<dyn Error>> {
+        let mesh = self.add_mesh(object.mesh(), object.draw_type(), material, None)?;
+        let parent = self.add_node(None, Matrix4::identity(), None, None);
+        for transform in object.transforms() {
+            self.add_node(None, transform.mat(), mesh, Some(parent));
+        }
+        Ok(parent)
+    }
+
+    /// Writes the `.glb` to `path`.
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save_glb(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| format!("Couldn't create file {path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+        self.write_glb(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the binary glTF.
+    /// # Errors
+    /// If the JSON cannot be serialized or writing fails.
+    pub fn write_glb(&self, writer: impl Write) -> Result<(), Box<dyn Error>> {
+        let mut root = self.root.clone();
+        if !self.bin.is_empty() {
+            root.buffers.push(json::Buffer {
+                byte_length: USize64::from(self.bin.len()),
+                extensions: None,
+                extras: json::Extras::default(),
+                name: None,
+                uri: None,
+            });
+        }
+        let scene = root.push(json::Scene {
+            extensions: None,
+            extras: json::Extras::default(),
+            name: None,
+            nodes: self.scene_nodes.clone(),
+        });
+        root.scene = Some(scene);
+        let json = root.to_vec()?;
+        let glb = Glb {
+            // `to_writer` computes the padded length itself.
+            header: Header {
+                magic: *b"glTF",
+                version: 2,
+                length: 0,
+            },
+            json: Cow::Owned(json),
+            bin: (!self.bin.is_empty()).then_some(Cow::Borrowed(self.bin.as_slice())),
+        };
+        glb.to_writer(writer)?;
+        Ok(())
+    }
+
+    fn use_extension(&mut self, name: &str) {
+        if !self.root.extensions_used.iter().any(|e| e == name) {
+            self.root.extensions_used.push(name.to_owned());
+        }
+    }
+
+    /// Appends `bytes` as a buffer view; image views have no `target`.
+    fn push_view(&mut self, bytes: &[u8], target: Option<json::buffer::Target>) -> Index<json::buffer::View> {
+        // Views start at four byte boundaries so every component type is aligned.
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let offset = self.bin.len();
+        let length = bytes.len();
+        self.bin.extend_from_slice(bytes);
+        self.root.push(json::buffer::View {
+            buffer: Index::new(0),
+            byte_length: USize64::from(length),
+            byte_offset: Some(USize64::from(offset)),
+            byte_stride: None,
+            extensions: None,
+            extras: json::Extras::default(),
+            name: None,
+            target: target.map(Valid),
+        })
+    }
+
+    /// Adds tightly packed float vectors as an accessor.
+    fn push_accessor<const N: usize>(
+        &mut self,
+        data: &[[f32; N]],
+        kind: json::accessor::Type,
+        bounds: Option<([f32; 3], [f32; 3])>,
+    ) -> Index<json::Accessor> {
+        let view = self.push_view(bytemuck::cast_slice(data.as_flattened()), Some(json::buffer::Target::ArrayBuffer));
+        let mut accessor = accessor(view, data.len(), json::accessor::ComponentType::F32, kind);
+        if let Some((min, max)) = bounds {
+            accessor.min = Some(json::Value::from(min.to_vec()));
+            accessor.max = Some(json::Value::from(max.to_vec()));
+        }
+        self.root.push(accessor)
+    }
+
+    /// Embeds `image` as a PNG and returns a reference to a texture sampling it.
+    fn push_texture(&mut self, image: &DynamicImage) -> Result<json::texture::Info, Box<dyn Error>> {
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let view = self.push_view(&png, None);
+        let source = self.root.push(json::Image {
+            buffer_view: Some(view),
+            mime_type: Some(json::image::MimeType("image/png".to_owned())),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: json::Extras::default(),
+        });
+        let index = self.root.push(json::Texture {
+            name: None,
+            sampler: None,
+            source,
+            extensions: None,
+            extras: json::Extras::default(),
+        });
+        Ok(json::texture::Info {
+            index,
+            tex_coord: 0,
+            extensions: None,
+            extras: json::Extras::default(),
+        })
+    }
+}
+
+fn accessor(
+    view: Index<json::buffer::View>,
+    count: usize,
+    component: json::accessor::ComponentType,
+    kind: json::accessor::Type,
+) -> json::Accessor {
+    json::Accessor {
+        buffer_view: Some(view),
+        byte_offset: None,
+        count: USize64::from(count),
+        component_type: Valid(json::accessor::GenericComponentType(component)),
+        extensions: None,
+        extras: json::Extras::default(),
+        type_: Valid(kind),
+        min: None,
+        max: None,
+        name: None,
+        normalized: false,
+        sparse: None,
+    }
+}
+
+fn mode(draw_type: GLenum) -> Result<json::mesh::Mode, Box<dyn Error>> {
+    use json::mesh::Mode;
+    Ok(match draw_type {
+        gl::POINTS => Mode::Points,
+        gl::LINES => Mode::Lines,
+        gl::LINE_LOOP => Mode::LineLoop,
+        gl::LINE_STRIP => Mode::LineStrip,
+        gl::TRIANGLES => Mode::Triangles,
+        gl::TRIANGLE_STRIP => Mode::TriangleStrip,
+        gl::TRIANGLE_FAN => Mode::TriangleFan,
+        _ => return Err(format!("Draw type {draw_type:#x} has no glTF equivalent").into()),
+    })
+}
+
+fn bounds(points: &[Vector3<f32>]) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for point in points {
+        for i in 0..3 {
+            min[i] = min[i].min(point[i]);
+            max[i] = max[i].max(point[i]);
+        }
+    }
+    (min, max)
+}
+
+const fn scalar_factor(value: Option<&TextureOrScalar>) -> Option<f32> {
+    match value {
+        Some(TextureOr::Value(value)) => Some(*value),
+        Some(TextureOr::Texture(_)) => Some(1.0),
+        None => None,
+    }
+}
+
+const fn scalar_texture(value: Option<&TextureOrScalar>) -> Option<&DynamicImage> {
+    match value {
+        Some(TextureOr::Texture(image)) => Some(image),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Vector2, Vector3};
+
+    fn square() -> Mesh {
+        Mesh::new(
+            vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(1.0, 2.0, 0.0),
+                Vector3::new(0.0, 2.0, 0.0),
+            ],
+            vec![0, 1, 2, 0, 2, 3],
+            Some(vec![Vector3::new(0.0, 0.0, 1.0); 4]),
+            Some(vec![Vector2::new(0.0, 0.0), Vector2::new(1.0, 0.0), Vector2::new(1.0, 1.0), Vector2::new(0.0, 1.0)]),
+        )
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp, reason = "the values are exactly representable and copied as they are")]
+    fn glb_round_trips_through_the_gltf_importer() {
+        let mut exporter = GltfExporter::new();
+        let material = NarrowingMaterial {
+            diffuse: Some(TextureOr::Value([0.5, 0.25, 1.0, 1.0])),
+            metallic: Some(TextureOr::Value(0.75)),
+            roughness: Some(TextureOr::Value(0.5)),
+            ..NarrowingMaterial::default()
+        };
+        let material = exporter.add_material(&material, Some("paint")).unwrap();
+        let mesh = exporter.add_mesh(&square(), gl::TRIANGLES, Some(material), Some("square")).unwrap();
+        let matrix = Matrix4::from_translation(Vector3::new(3.0, 0.0, 0.0));
+        exporter.add_node(Some("node"), matrix, mesh, None);
+        let mut glb = Vec::new();
+        exporter.write_glb(&mut glb).unwrap();
+
+        let (document, buffers, _) = gltf::import_slice(&glb).unwrap();
+        let node = document.default_scene().unwrap().nodes().next().unwrap();
+        assert_eq!(node.name(), Some("node"));
+        let expected: &[[f32; 4]; 4] = matrix.as_ref();
+        assert_eq!(node.transform().matrix(), *expected);
+        let primitive = node.mesh().unwrap().primitives().next().unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+        let expected: Vec<[f32; 3]> = square().vertices.iter().map(|v| (*v).into()).collect();
+        assert_eq!(positions, expected);
+        assert_eq!(reader.read_tex_coords(0).unwrap().into_f32().nth(2), Some([1.0, 1.0]));
+        assert_eq!(reader.read_indices().unwrap().into_u32().collect::<Vec<_>>(), square().indices);
+        assert_eq!(primitive.bounding_box().max, [1.0, 2.0, 0.0]);
+
+        let material = primitive.material();
+        assert_eq!(material.name(), Some("paint"));
+        let pbr = material.pbr_metallic_roughness();
+        assert_eq!(pbr.base_color_factor(), [0.5, 0.25, 1.0, 1.0]);
+        assert_eq!(pbr.metallic_factor(), 0.75);
+        assert_eq!(pbr.roughness_factor(), 0.5);
+    }
+
+    #[test]
+    fn empty_meshes_are_left_out() {
+        let mut exporter = GltfExporter::new();
+        let empty = Mesh::new(Vec::new(), Vec::new(), None, None);
+        let mesh = exporter.add_mesh(&empty, gl::TRIANGLES, None, None).unwrap();
+        assert!(mesh.is_none());
+        exporter.add_node(Some("empty"), Matrix4::identity(), mesh, None);
+        let mut glb = Vec::new();
+        exporter.write_glb(&mut glb).unwrap();
+
+        let (document, _, _) = gltf::import_slice(&glb).unwrap();
+        assert_eq!(document.meshes().count(), 0);
+        assert_eq!(document.accessors().count(), 0);
+        assert!(document.nodes().next().unwrap().mesh().is_none());
+    }
+}
@@ -28,12 +28,16 @@ pub trait GLBuffer: GLObject {
     fn buffer_data<T: Sized>(&mut self, data: &[T], usage: GLenum) -> Result<(), GLFunctionError>;
 }
 /// Vertex Array Attribute
+#[derive(Clone)]
 pub struct Vaa {
     kind: GLenum,
     amount: u32,
     type_size: usize,
     mem_size: usize,
     vbo_index: u32,
+    /// Shader location; the attribute's index in the structure if `None`.
+    location: Option<u32>,
+    normalized: bool,
 }
 impl Vaa {
     /// # Panics
@@ -44,10 +48,21 @@ impl Vaa {
             kind,
             amount,
             type_size: ts,
-            mem_size: ts * amount as usize,
+            // Padded so every attribute starts at a four byte boundary.
+            mem_size: (ts * amount as usize).next_multiple_of(4),
             vbo_index,
+            location: None,
+            normalized: false,
         }
     }
+    pub(crate) const fn with_location(mut self, location: u32) -> Self {
+        self.location = Some(location);
+        self
+    }
+    pub(crate) const fn with_normalized(mut self, normalized: bool) -> Self {
+        self.normalized = normalized;
+        self
+    }
 
 }
 pub struct VertexArrayObject {
@@ -94,6 +109,22 @@ impl Default for VertexArrayObject {
 }
 
 impl VertexArrayObject {
+    /// Another handle to the same vertex array and buffers, e.g. to draw one mesh from several
+    /// renderables. Nothing deletes them, so the handles may be dropped in any order.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            id: self.id,
+            generated: self.generated,
+            bound: false,
+            structure: self.structure.clone(),
+            ebo: self.ebo.share(),
+            vbos: self.vbos.iter().map(BufferObject::share).collect(),
+        }
+    }
+    /// Whether the vertex array was created, e.g. by initializing its mesh.
+    pub(crate) const fn is_generated(&self) -> bool {
+        self.generated
+    }
     pub fn new() -> Self {
         Self {
             id: 0,
@@ -127,17 +158,18 @@ impl VertexArrayObject {
             }
         }
         for i in 0..self.structure.len() {
+            let location = self.structure[i].location.unwrap_or(i as u32);
             unsafe {
-                gl::EnableVertexArrayAttrib(self.id, i as u32);
+                gl::EnableVertexArrayAttrib(self.id, location);
                 gl::VertexArrayAttribFormat(
                     self.id,
-                    i as u32,
+                    location,
                     self.structure[i].amount as i32,
                     self.structure[i].kind,
-                    u8::from(false),
+                    u8::from(self.structure[i].normalized),
                     pointer_offset[&self.structure[i].vbo_index],
                 );
-                gl::VertexArrayAttribBinding(self.id, i as GLuint, self.structure[i].vbo_index);
+                gl::VertexArrayAttribBinding(self.id, location, self.structure[i].vbo_index);
             }
             pointer_offset
                 .get_mut(&self.structure[i].vbo_index)
@@ -184,6 +216,12 @@ impl BufferObject {
             kind,
         }
     }
+    pub(crate) const fn share(&self) -> Self {
+        Self {
+            bound: false,
+            ..*self
+        }
+    }
 }
 impl GLObject for BufferObject {
     /// Must be run with a currently bound Vertex Array Object
@@ -223,3 +261,127 @@ impl GLBuffer for BufferObject {
         find_gl_error()
     }
 }
+
+/// An offscreen render target backed by a color and a depth/stencil renderbuffer.
+pub struct FrameBufferObject {
+    pub(crate) id: u32,
+    color: u32,
+    depth: u32,
+    generated: bool,
+    bound: bool,
+    pub width: usize,
+    pub height: usize,
+    pub samples: u32,
+}
+impl FrameBufferObject {
+    pub(crate) const fn new(width: usize, height: usize, samples: u32) -> Self {
+        Self {
+            id: 0,
+            color: 0,
+            depth: 0,
+            generated: false,
+            bound: false,
+            width,
+            height,
+            samples,
+        }
+    }
+    /// Blits the color attachment into `target`, which must have the same size.
+    /// # Errors
+    /// If the blit fails.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    pub(crate) fn resolve_into(&self, target: &Self) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            gl::BlitNamedFramebuffer(
+                self.id,
+                target.id,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                0,
+                0,
+                self.width as i32,
+                self.height as i32,
+                gl::COLOR_BUFFER_BIT,
+                gl::NEAREST,
+            );
+        }
+        find_gl_error()?;
+        Ok(())
+    }
+}
+impl GLObject for FrameBufferObject {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn generate(&mut self) -> Result<(), Box<dyn Error>> {
+        unsafe {
+            gl::CreateFramebuffers(1, &mut self.id);
+            gl::CreateRenderbuffers(1, &mut self.color);
+            gl::CreateRenderbuffers(1, &mut self.depth);
+            gl::NamedRenderbufferStorageMultisample(self.color, self.samples as i32, gl::RGBA8, self.width as i32, self.height as i32);
+            gl::NamedRenderbufferStorageMultisample(self.depth, self.samples as i32, gl::DEPTH24_STENCIL8, self.width as i32, self.height as i32);
+            gl::NamedFramebufferRenderbuffer(self.id, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, self.color);
+            gl::NamedFramebufferRenderbuffer(self.id, gl::DEPTH_STENCIL_ATTACHMENT, gl::RENDERBUFFER, self.depth);
+            gl::NamedFramebufferReadBuffer(self.id, gl::COLOR_ATTACHMENT0);
+        }
+        find_gl_error()?;
+        let status = unsafe { gl::CheckNamedFramebufferStatus(self.id, gl::FRAMEBUFFER) };
+        if status != gl::FRAMEBUFFER_COMPLETE {
+            return Err(format!("Framebuffer is incomplete: {status:#x}").into());
+        }
+        self.generated = true;
+        Ok(())
+    }
+    fn bind(&mut self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.id);
+        }
+        self.bound = true;
+    }
+    fn unbind(&mut self) {
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        }
+        self.bound = false;
+    }
+}
+impl Drop for FrameBufferObject {
+    fn drop(&mut self) {
+        if self.generated {
+            unsafe {
+                gl::DeleteRenderbuffers(1, &self.color);
+                gl::DeleteRenderbuffers(1, &self.depth);
+                gl::DeleteFramebuffers(1, &self.id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vertex::{ComponentType, VertexAttribute, VertexLayout};
+
+    #[test]
+    fn vaa_sizes_are_padded_to_four_bytes() {
+        assert_eq!(Vaa::new(gl::UNSIGNED_BYTE, 3, 0).mem_size, 4);
+        assert_eq!(Vaa::new(gl::SHORT, 1, 0).mem_size, 4);
+        assert_eq!(Vaa::new(gl::UNSIGNED_SHORT, 3, 0).mem_size, 8);
+        assert_eq!(Vaa::new(gl::FLOAT, 3, 0).mem_size, 12);
+    }
+
+    #[test]
+    fn vaas_match_the_layout_stride() {
+        let layout = VertexLayout::new(vec![
+            VertexAttribute::POSITION,
+            VertexAttribute::new("aMaterial", ComponentType::U8, 1, false, 8),
+            VertexAttribute::new("aWeight", ComponentType::U16, 3, true, 9),
+            VertexAttribute::TEX_COORD,
+        ]);
+        let vaas = layout.vaas(0);
+        assert_eq!(layout.stride(), 12 + 4 + 8 + 8);
+        assert_eq!(vaas.iter().map(|v| v.mem_size).sum::<usize>(), layout.stride());
+        assert_eq!(vaas.iter().map(|v| v.location).collect::<Vec<_>>(), [Some(0), Some(8), Some(9), Some(2)]);
+        assert!(vaas[2].normalized && !vaas[1].normalized);
+    }
+}
@@ -31,25 +31,46 @@ use gl::{
     COLOR_BUFFER_BIT, DEBUG_OUTPUT, DEBUG_OUTPUT_SYNCHRONOUS, DEBUG_SEVERITY_NOTIFICATION,
     DEBUG_SOURCE_API, DEBUG_TYPE_ERROR, DEPTH_BUFFER_BIT, DEPTH_TEST, FILL, FRONT_AND_BACK,
 };
-use glfw::ffi::{glfwGetTime, glfwSetInputMode, CURSOR, CURSOR_DISABLED};
+use glfw::ffi::glfwGetTime;
 use glfw::{
-    Action, Context, CursorMode, Glfw, GlfwReceiver, Key, PWindow, SwapInterval,
+    Action, Context, CursorMode, Glfw, GlfwReceiver, Key, MouseButton, PWindow, SwapInterval,
     WindowEvent, WindowHint,
 };
 use image::{ImageBuffer, Rgba};
 use imgui::Ui;
+use log::warn;
 
 // Module declarations
+pub mod animation;
+pub mod bounds;
+pub mod bvh;
 pub mod drawing;
 mod glutil;
+pub mod gltf_export;
+pub mod lighting;
+pub mod lod;
+pub mod normals;
+pub mod optimize;
+pub mod ply;
+pub mod primitives;
+pub mod point_cloud;
 pub mod renderable;
+pub mod scene;
 pub mod shader;
+pub mod simplify;
+pub mod stl;
+pub mod testing;
 pub mod transformation;
 pub mod util;
+pub mod validate;
+pub mod vertex;
 
 // Internal module imports
+use crate::glutil::{FrameBufferObject, GLObject};
 use crate::shader::{ShaderPtr, TextureOr};
-use renderable::{Render, Renderable};
+use bounds::{Aabb, Frustum, Ray};
+use bvh::Bvh;
+use renderable::{CullStats, RayHit, Render, Renderable, RenderableGroup};
 use shader::{NarrowingMaterial, ShaderManager};
 use transformation::Camera;
 use util::debug_log;
@@ -80,6 +101,50 @@ fn new_renderable_ptr<T: Render + 'static>(renderable: T) -> RenderablePtr {
     Rc::new(RefCell::new(Box::new(renderable)))
 }
 
+/// A tree over the world space boxes of the renderables, whose items are their indices, with
+/// the `Render::bounds_key` each box was computed for.
+#[derive(Default)]
+struct RenderableBvh {
+    tree: Bvh,
+    keys: Vec<Option<u64>>,
+}
+
+impl RenderableBvh {
+    /// Updates the tree to the current bounds of `renderables`, only asking the ones whose
+    /// `bounds_key` changed, and rebuilds it if renderables were added or removed. Renderables
+    /// without bounds get an empty box. Returns how many items were updated.
+    /// # Errors
+    /// Returns an error if a renderable is already borrowed.
+    fn refresh(&mut self, renderables: &[RenderablePtr]) -> Result<usize, Box<dyn Error>> {
+        let rebuild = renderables.len() != self.tree.len();
+        if rebuild {
+            self.keys = vec![None; renderables.len()];
+        }
+        let mut bounds = Vec::with_capacity(if rebuild { renderables.len() } else { 0 });
+        let mut moved = 0;
+        for (i, r) in renderables.iter().enumerate() {
+            let mut renderable = r.try_borrow_mut()?;
+            let key = renderable.bounds_key();
+            if !rebuild && key.is_some() && key == self.keys[i] {
+                continue;
+            }
+            self.keys[i] = key;
+            let aabb = renderable.bounds().map_or(Aabb::EMPTY, |b| b.aabb);
+            if rebuild {
+                bounds.push(aabb);
+            } else if *self.tree.item_bounds(i) != aabb {
+                self.tree.update(i, aabb);
+                moved += 1;
+            }
+        }
+        if rebuild {
+            self.tree = Bvh::new(bounds);
+            return Ok(renderables.len());
+        }
+        Ok(moved)
+    }
+}
+
 //
 // Core structures
 //
@@ -101,6 +166,24 @@ pub struct Data {
     pub frame_buffer_texture: Option<(u32, u32)>,
     /// Whether to clear the screen before rendering
     pub should_clear: bool,
+    /// Whether to skip renderables outside the camera's view
+    pub frustum_culling: bool,
+    /// How many renderables the last frame drew and culled
+    pub cull_stats: CullStats,
+    /// What was under the cursor at the last left click with a visible cursor
+    pub picked: Option<Pick>,
+    /// Tree over the bounds of `renderables`, kept up to date for culling and picking
+    bvh: RenderableBvh,
+}
+
+/// A renderable hit by a ray; see `Data::pick`
+pub struct Pick {
+    /// The renderable that was hit
+    pub renderable: RenderablePtr,
+    /// Its index in `Data::renderables`
+    pub index: usize,
+    /// Where it was hit
+    pub hit: RayHit,
 }
 
 /// Implementation of the Data structure
@@ -114,9 +197,21 @@ impl Data {
         self.shader_manager.update()
     }
 
+    /// Advances the animations of all objects to `time`, in seconds.
+    /// # Errors
+    /// Returns an error if a renderable is already borrowed.
+    fn animate(&mut self, time: f64) -> Result<(), Box<dyn Error>> {
+        for i in self.renderables.iter_mut().map(|x| x.try_borrow_mut()) {
+            i?.animate(time);
+        }
+        Ok(())
+    }
+
     /// Renders all objects in the scene
     ///
-    /// Clears the screen if needed, updates camera buffers, and renders each object.
+    /// Clears the screen if needed, updates camera buffers, and prepares and renders each object
+    /// that isn't culled, counting them in `cull_stats`. Objects outside the frustum are found with
+    /// the scene's tree, which is updated for the ones that moved.
     /// If wireframe is true, uses the wireframe shader instead of the object's shader.
     /// # Errors
     /// Returns an error if any renderable fails to render.
@@ -129,8 +224,33 @@ impl Data {
             }
         }
         self.camera.update_buffers()?; // Only needs to be updated if it changes. TODO: Optimization?
-        for i in self.renderables.iter_mut().map(|x| x.try_borrow_mut()) {
-            i?.render(wireframe.then(|| self.wireframe_shader.clone()))?;
+        self.shader_manager.upload_world();
+        let frustum = if self.frustum_culling { self.camera.frustum() } else { Frustum::EVERYTHING };
+        let mut inside = vec![true; self.renderables.len()];
+        if self.frustum_culling {
+            self.bvh.refresh(&self.renderables)?;
+            inside.fill(false);
+            for i in self.bvh.tree.query_frustum(&frustum) {
+                inside[i] = true;
+            }
+        }
+        self.cull_stats = CullStats::default();
+        for (i, r) in self.renderables.iter().enumerate() {
+            let mut renderable = r.try_borrow_mut()?;
+            if !renderable.is() {
+                continue;
+            }
+            renderable.prepare(&self.camera);
+            let outside = !inside[i]
+                && !self.bvh.tree.item_bounds(i).is_empty()
+                && !renderable.deforms()
+                && !renderable.screen_space();
+            let stats =
+                if outside { CullStats { drawn: 0, culled: 1 } } else { renderable.cull(&frustum) };
+            self.cull_stats += stats;
+            if stats.drawn > 0 {
+                renderable.render(wireframe.then(|| self.wireframe_shader.clone()))?;
+            }
         }
         Ok(())
     }
@@ -156,7 +276,8 @@ impl Data {
 
     /// Creates a renderable from an OBJ file and adds it to the scene
     ///
-    /// Loads the model from the specified path and applies the shader from shaderpath.
+    /// Loads the model from the specified path as a `RenderableGroup` with one renderable per
+    /// group and material, using the shader from shaderpath.
     /// # Errors
     /// Returns an error if the OBJ file cannot be loaded or the shader fails to compile.
     pub fn add_renderable_from_obj(
@@ -164,7 +285,19 @@ impl Data {
         path: &str,
         shaderpath: &str,
     ) -> Result<RenderablePtr, Box<dyn Error>> {
-        let renderable = Renderable::from_obj(path, shaderpath, &mut self.shader_manager)?;
+        let renderable = RenderableGroup::from_obj(path, shaderpath, &mut self.shader_manager)?;
+        self.add_renderable(Box::from(renderable))
+    }
+
+    /// Creates a renderable from a binary or ASCII STL file and adds it to the scene
+    /// # Errors
+    /// Returns an error if the STL file cannot be loaded or the shader fails to compile.
+    pub fn add_renderable_from_stl(
+        &mut self,
+        path: &str,
+        shaderpath: &str,
+    ) -> Result<RenderablePtr, Box<dyn Error>> {
+        let renderable = Renderable::from_stl(path, shaderpath, &mut self.shader_manager)?;
         self.add_renderable(Box::from(renderable))
     }
 
@@ -223,6 +356,48 @@ impl Data {
         }
     }
 
+    /// Finds the nearest visible renderable `ray` hits, e.g. from `Camera::screen_ray`
+    ///
+    /// Only renderables whose bounds the ray reaches are tested, found with the scene's tree,
+    /// which is updated for the ones that moved. Renderables without bounds are always tested.
+    /// Renderables in screen space are never hit, and skinned and morphing ones are hit in their
+    /// base shape; see `Render::raycast`.
+    /// # Errors
+    /// Returns an error if a renderable is already borrowed.
+    pub fn pick(&mut self, ray: &Ray) -> Result<Option<Pick>, Box<dyn Error>> {
+        self.bvh.refresh(&self.renderables)?;
+        let mut error: Option<Box<dyn Error>> = None;
+        let mut hits = Vec::new();
+        let mut test = |index: usize| {
+            match self.renderables[index].try_borrow_mut() {
+                Ok(mut renderable) if renderable.is() && !renderable.screen_space() => {
+                    let hit = renderable.raycast(ray)?;
+                    hits.push((index, hit));
+                    Some(hit.distance)
+                }
+                Ok(_) => None,
+                Err(e) => {
+                    error.get_or_insert_with(|| e.into());
+                    None
+                }
+            }
+        };
+        let tree = &self.bvh.tree;
+        tree.raycast(ray, f32::INFINITY, &mut test);
+        for index in (0..tree.len()).filter(|&i| tree.item_bounds(i).is_empty()) {
+            test(index);
+        }
+        if let Some(e) = error {
+            return Err(e);
+        }
+        let nearest = hits.into_iter().min_by(|a, b| a.1.distance.total_cmp(&b.1.distance));
+        Ok(nearest.map(|(index, hit)| Pick {
+            renderable: self.renderables[index].clone(),
+            index,
+            hit,
+        }))
+    }
+
     /// Gets a reference to a renderable by index
     #[must_use]
     pub fn get_renderable(&self, index: usize) -> &RenderablePtr {
@@ -237,8 +412,18 @@ impl Data {
     /// Creates a framebuffer texture for rendering to texture
     ///
     /// This allows rendering the scene to a texture that can be used elsewhere.
-    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    /// The texture is three times the default window size in each dimension.
+    #[deprecated(note = "the window may have any size; use `create_framebuffer_texture_sized` with `Engine::size`")]
     pub fn create_framebuffer_texture(&mut self) {
+        self.create_framebuffer_texture_sized([WIDTH, HEIGHT]);
+    }
+
+    /// Creates a framebuffer texture for rendering to texture
+    ///
+    /// This allows rendering the scene to a texture that can be used elsewhere.
+    /// The texture is three times the given window `size` (e.g. `Engine::size`) in each dimension.
+    #[allow(clippy::cast_possible_wrap, clippy::cast_possible_truncation)]
+    pub fn create_framebuffer_texture_sized(&mut self, size: [usize; 2]) {
         let mut buff = 0;
         unsafe {
             gl::GenFramebuffers(1, &mut buff);
@@ -252,8 +437,8 @@ impl Data {
                 gl::TEXTURE_2D,
                 0,
                 gl::RGB as i32,
-                WIDTH as i32 * 3,
-                HEIGHT as i32 * 3,
+                size[0] as i32 * 3,
+                size[1] as i32 * 3,
                 0,
                 gl::RGB,
                 gl::UNSIGNED_BYTE,
@@ -293,7 +478,13 @@ pub struct Engine {
     pub frame_index: u32,
     /// Current window size [width, height]
     pub size: [usize; 2],
-    pub clear_color: (f32, f32,f32,f32)
+    /// Window size to restore when leaving fullscreen
+    windowed_size: [usize; 2],
+    pub clear_color: (f32, f32,f32,f32),
+    /// Offscreen render target used instead of the window's framebuffer in headless mode
+    offscreen: Option<FrameBufferObject>,
+    /// Single-sampled target `read_pixels` resolves a multisampled `offscreen` into
+    resolve_target: Option<FrameBufferObject>,
 }
 
 /// Implementation of the Engine structure
@@ -317,16 +508,32 @@ impl Engine {
         self.data.add_renderable_rc(renderable)
     }
 
-    /// Captures the current frame and saves it to a file
+    /// Reads the current frame back from the GPU
     ///
-    /// Reads the framebuffer pixels and saves them as an image at the specified path.
+    /// Reads from the offscreen target in headless mode and from the window otherwise.
+    /// The returned image is flipped so that the first row is the top of the frame.
     /// # Panics
-    /// If the image cannot be created or saved.
-    #[allow(clippy::cast_sign_loss)]
-    pub fn write_to_file(&self, path: &str) {
-        let (width, height) = self.window.get_size();
+    /// If the image buffer cannot be created or a multisampled target cannot be resolved.
+    #[allow(clippy::cast_sign_loss, clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    #[must_use]
+    pub fn read_pixels(&self) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        let (width, height) = self.offscreen.as_ref().map_or_else(
+            || self.window.get_framebuffer_size(),
+            |fbo| (fbo.width as i32, fbo.height as i32),
+        );
+        // Multisampled renderbuffers can't be read directly, so resolve into a plain target first.
+        if let (Some(offscreen), Some(target)) = (&self.offscreen, &self.resolve_target) {
+            offscreen.resolve_into(target).expect("Failed to resolve multisampled framebuffer.");
+        }
+        let source = self
+            .resolve_target
+            .as_ref()
+            .or(self.offscreen.as_ref())
+            .map_or(0, |fbo| fbo.id);
         let mut data = vec![0u8; (width * height * 4) as usize];
         unsafe {
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, source);
+            gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
             gl::ReadPixels(
                 0,
                 0,
@@ -339,10 +546,22 @@ impl Engine {
         }
         let img = ImageBuffer::<Rgba<u8>, _>::from_raw(width as u32, height as u32, data)
             .expect("Failed to create ImageBuffer.");
-
         image::imageops::flip_vertical(&img)
-            .save(path)
-            .expect("Failed to save image.");
+    }
+
+    /// Captures the current frame and saves it to a file
+    ///
+    /// Reads the framebuffer pixels and saves them as an image at the specified path.
+    /// # Panics
+    /// If the image cannot be created or saved.
+    pub fn write_to_file(&self, path: &str) {
+        self.read_pixels().save(path).expect("Failed to save image.");
+    }
+
+    /// Whether the engine renders into an offscreen target instead of a visible window
+    #[must_use]
+    pub const fn is_headless(&self) -> bool {
+        self.offscreen.is_some()
     }
 
     /// Sets the cursor mode (normal, hidden, disabled)
@@ -374,6 +593,12 @@ impl Engine {
         self.event_handler.current_frame_time = self.get_time();
 
         self.data.handle_input(&self.window, self.frametime as f32);
+        self.data
+            .animate(self.event_handler.current_frame_time)
+            .expect("failed to animate.");
+        if let Some(fbo) = self.offscreen.as_mut() {
+            fbo.bind();
+        }
         self.data
             .render(self.event_handler.wireframe, self.clear_color)
             .expect("failed to render.");
@@ -407,23 +632,73 @@ impl Engine {
         self.glfw.get_time()
     }
 
+    /// Sets the GLFW clock, which also drives the `time` uniform of shaders
+    pub fn set_time(&mut self, time: f64) {
+        self.glfw.set_time(time);
+    }
+
     /// Gets the current cursor position
     ///
     /// Returns (x, y) coordinates relative to the window.
     pub fn get_cursor_pos(&self) -> (f64, f64) {
         self.window.get_cursor_pos()
     }
+
+    /// The camera ray through the cursor, for `Data::pick`
+    #[allow(clippy::cast_sign_loss)]
+    pub fn cursor_ray(&self) -> Ray {
+        // The cursor is measured in screen coordinates, which differ from `size` on high DPI displays.
+        let (width, height) = self.window.get_size();
+        self.data
+            .camera
+            .screen_ray(self.get_cursor_pos(), [width.max(1) as usize, height.max(1) as usize])
+    }
     /// Creates a new Engine instance
     ///
     /// Initializes GLFW, OpenGL, and creates a window with the given name.
     /// If imgui is true, initializes the `ImGui` UI system.
+    /// Use [`EngineBuilder`] for more control over the window and context.
     /// # Errors
     /// Returns an error if GLFW initialization fails or if the shader cannot be created.
     pub fn new(imgui: bool, window_name: &str) -> Result<Self, Box<dyn Error>> {
-        env_logger::init();
-        let (glfw, mut window, events) = init_gflw(window_name);
-        let camera = Self::init_gl();
-        let event_handler = if imgui {
+        EngineBuilder::new().title(window_name).imgui(imgui).build()
+    }
+
+    /// Creates a new Engine that renders offscreen
+    ///
+    /// Shorthand for `EngineBuilder::new().size(width, height).headless(true).build()`.
+    /// # Errors
+    /// Returns an error if GLFW initialization fails, the context is older than the requested
+    /// OpenGL version, the shader cannot be created or the offscreen framebuffer is incomplete.
+    pub fn headless(width: usize, height: usize) -> Result<Self, Box<dyn Error>> {
+        EngineBuilder::new().size(width, height).headless(true).build()
+    }
+
+    /// Creates a builder for configuring a new Engine
+    #[must_use]
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    /// Creates an engine from the builder's settings
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    fn create(builder: &EngineBuilder) -> Result<Self, Box<dyn Error>> {
+        // Several engines may be created in one process (e.g. tests), so don't panic on re-init.
+        let _ = env_logger::try_init();
+        let (glfw, mut window, events) = init_gflw(builder);
+        let version = window.get_context_version();
+        let requested = (u64::from(builder.gl_version.0), u64::from(builder.gl_version.1));
+        if (version.major, version.minor) < requested {
+            return Err(format!(
+                "Requested OpenGL {}.{}, but the context is {}.{}",
+                requested.0, requested.1, version.major, version.minor
+            )
+            .into());
+        }
+        let [width, height] = builder.size;
+        let mut camera = Self::init_gl();
+        camera.update_projection(width as f32 / height as f32);
+        let event_handler = if builder.imgui && !builder.headless {
             EventHandler::new(&mut window, events)
         } else {
             EventHandler::raw(events)
@@ -437,11 +712,32 @@ impl Engine {
             roughness: None,
             ambient_scaling: None,
             normal: None,
+            shininess: None,
+            opacity: None,
         };
         let wireframe_id = shader_manager.register(mat.into_shader(
             include_str!("../shaders/base_shader.vert").to_string(),
             include_str!("../shaders/base_shader.frag").to_string(),
         )?);
+        let offscreen = if builder.headless {
+            let mut fbo = FrameBufferObject::new(width, height, builder.samples);
+            fbo.generate()?;
+            fbo.bind();
+            unsafe {
+                gl::Viewport(0, 0, width as i32, height as i32);
+            }
+            Some(fbo)
+        } else {
+            None
+        };
+        let resolve_target = match &offscreen {
+            Some(fbo) if fbo.samples > 0 => {
+                let mut target = FrameBufferObject::new(width, height, 0);
+                target.generate()?;
+                Some(target)
+            }
+            _ => None,
+        };
         Ok(Self {
             glfw,
             window,
@@ -453,11 +749,18 @@ impl Engine {
                 wireframe_shader: wireframe_id,
                 frame_buffer_texture: None,
                 should_clear: true,
+                frustum_culling: true,
+                cull_stats: CullStats::default(),
+                picked: None,
+                bvh: RenderableBvh::default(),
             },
             event_handler,
             frame_index: 0,
-            size: [WIDTH, HEIGHT],
-            clear_color: CLEARCOLOR
+            size: builder.size,
+            windowed_size: builder.size,
+            clear_color: builder.clear_color,
+            offscreen,
+            resolve_target,
         })
     }
 
@@ -479,6 +782,20 @@ impl Engine {
     }
 
 
+    /// Sets `Data::picked` to what's under the cursor, unless the cursor is captured or over the UI
+    fn pick_at_cursor(&mut self) {
+        let over_ui = self.event_handler.show_imgui
+            && self.event_handler.imgui.as_ref().is_some_and(|i| i.io().want_capture_mouse);
+        if self.window.get_cursor_mode() == CursorMode::Disabled || over_ui {
+            return;
+        }
+        let ray = self.cursor_ray();
+        match self.data.pick(&ray) {
+            Ok(picked) => self.data.picked = picked,
+            Err(e) => warn!("Picking failed: {e}"),
+        }
+    }
+
     /// Processes all pending GLFW events
     ///
     /// Handles window events like resizing, key presses, and cursor movement.
@@ -488,6 +805,7 @@ impl Engine {
     #[allow(clippy::cast_sign_loss, clippy::cast_precision_loss, clippy::cast_possible_truncation)]
     fn process_glfw_events(&mut self) {
         self.glfw.poll_events();
+        let mut clicked = false;
         for (_, event) in glfw::flush_messages(&self.event_handler.events) {
             // Forward events to ImGui if it's enabled
             if self.event_handler.imgui.is_some() && self.event_handler.show_imgui {
@@ -500,8 +818,8 @@ impl Engine {
 
             // Handle specific window events
             match event {
-                // Handle window resize
-                WindowEvent::FramebufferSize(width, height) => unsafe {
+                // Handle window resize; the offscreen target keeps its own size
+                WindowEvent::FramebufferSize(width, height) if self.offscreen.is_none() => unsafe {
                     gl::Viewport(0, 0, width, height);
                     self.size = [width as usize, height as usize];
                     self.data
@@ -561,8 +879,8 @@ impl Engine {
                             glfw::WindowMode::Windowed,
                             250,
                             250,
-                            WIDTH as u32,
-                            HEIGHT as u32,
+                            self.windowed_size[0] as u32,
+                            self.windowed_size[1] as u32,
                             None,
                         );
                     } else {
@@ -588,9 +906,148 @@ impl Engine {
                     }
                     // unsafe { glfwSetCursorPos(window.window_ptr(), (WIDTH / 2) as c_double, HEIGHT as c_double / 2.0); }
                 }
+                // Pick what's under the cursor on left click, once the events are handled
+                WindowEvent::MouseButton(MouseButton::Button1, Action::Press, _) => clicked = true,
                 _ => {}
             }
         }
+        if clicked {
+            self.pick_at_cursor();
+        }
+    }
+}
+
+/// Configures and creates an [`Engine`]
+///
+/// ```no_run
+/// # use rust_gl::EngineBuilder;
+/// let engine = EngineBuilder::new()
+///     .title("scene")
+///     .size(1280, 720)
+///     .vsync(false)
+///     .samples(4)
+///     .build()
+///     .expect("Failed to create engine");
+/// ```
+#[allow(clippy::struct_excessive_bools)]
+pub struct EngineBuilder {
+    title: String,
+    size: [usize; 2],
+    resizable: bool,
+    vsync: bool,
+    samples: u32,
+    debug_context: bool,
+    cursor_mode: CursorMode,
+    clear_color: (f32, f32, f32, f32),
+    imgui: bool,
+    headless: bool,
+    gl_version: (u32, u32),
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineBuilder {
+    /// Creates a builder with the default settings: a resizable `WIDTH` x `HEIGHT` window with
+    /// vsync, no multisampling, a GL 4.6 core debug context, a disabled cursor and `ImGui` on.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            title: "rust-gl".to_owned(),
+            size: [WIDTH, HEIGHT],
+            resizable: true,
+            vsync: true,
+            samples: 0,
+            debug_context: true,
+            cursor_mode: CursorMode::Disabled,
+            clear_color: CLEARCOLOR,
+            imgui: true,
+            headless: false,
+            gl_version: (4, 6),
+        }
+    }
+    /// Sets the window title
+    #[must_use]
+    pub fn title(mut self, title: &str) -> Self {
+        title.clone_into(&mut self.title);
+        self
+    }
+    /// Sets the window (or offscreen target) size in pixels
+    #[must_use]
+    pub const fn size(mut self, width: usize, height: usize) -> Self {
+        self.size = [width, height];
+        self
+    }
+    /// Sets whether the window can be resized by the user
+    #[must_use]
+    pub const fn resizable(mut self, resizable: bool) -> Self {
+        self.resizable = resizable;
+        self
+    }
+    /// Sets whether buffer swaps wait for vertical sync
+    #[must_use]
+    pub const fn vsync(mut self, vsync: bool) -> Self {
+        self.vsync = vsync;
+        self
+    }
+    /// Sets the number of MSAA samples; 0 disables multisampling
+    #[must_use]
+    pub const fn samples(mut self, samples: u32) -> Self {
+        self.samples = samples;
+        self
+    }
+    /// Sets whether to request a debug context and log GL debug messages
+    #[must_use]
+    pub const fn debug_context(mut self, debug_context: bool) -> Self {
+        self.debug_context = debug_context;
+        self
+    }
+    /// Sets the cursor mode the window starts with
+    #[must_use]
+    pub const fn cursor_mode(mut self, cursor_mode: CursorMode) -> Self {
+        self.cursor_mode = cursor_mode;
+        self
+    }
+    /// Sets the color the screen is cleared to every frame
+    #[must_use]
+    pub const fn clear_color(mut self, clear_color: (f32, f32, f32, f32)) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+    /// Sets whether to initialize the `ImGui` UI system
+    #[must_use]
+    pub const fn imgui(mut self, imgui: bool) -> Self {
+        self.imgui = imgui;
+        self
+    }
+    /// Renders into an offscreen framebuffer owned by a hidden window
+    ///
+    /// The hidden window only owns the OpenGL context; every frame is rendered into a
+    /// framebuffer object of the configured size, which `Engine::read_pixels` and
+    /// `Engine::write_to_file` read from. `ImGui` is disabled. This still needs a display
+    /// server (e.g. Xvfb) but works with Mesa's software rasterizer, so scenes can be
+    /// rendered on build machines.
+    #[must_use]
+    pub const fn headless(mut self, headless: bool) -> Self {
+        self.headless = headless;
+        self
+    }
+    /// Sets the requested OpenGL version; the engine needs at least 4.5 for direct state access.
+    /// Building fails if the created context is older.
+    #[must_use]
+    pub const fn gl_version(mut self, major: u32, minor: u32) -> Self {
+        self.gl_version = (major, minor);
+        self
+    }
+    /// Creates the engine
+    /// # Errors
+    /// Returns an error if GLFW initialization fails, the context is older than the requested
+    /// OpenGL version, the shader cannot be created or the offscreen framebuffer is incomplete.
+    pub fn build(&self) -> Result<Engine, Box<dyn Error>> {
+        Engine::create(self)
     }
 }
 
@@ -659,10 +1116,11 @@ impl EventHandler {
 ///
 /// This function:
 /// 1. Initializes GLFW
-/// 2. Creates a window with the specified name
-/// 3. Sets up OpenGL context with version 4.6 core profile
-/// 4. Configures window event callbacks
-/// 5. Sets up OpenGL debug output
+/// 2. Sets the context and window hints from the builder (GL version, core profile,
+///    debug context, sample count, resizability, visibility)
+/// 3. Creates a window with the configured title and size
+/// 4. Configures window event callbacks, vsync and the cursor mode
+/// 5. Sets up OpenGL debug output if a debug context was requested
 /// 6. Enables depth testing and other OpenGL features
 ///
 /// Returns a tuple containing:
@@ -672,27 +1130,32 @@ impl EventHandler {
 /// # Panics
 /// If GLFW fails to initialize or if the window cannot be created.
 #[allow(clippy::cast_possible_truncation)]
-fn init_gflw(window_name: &str) -> (Glfw, PWindow, GlfwReceiver<(f64, WindowEvent)>) {
+fn init_gflw(builder: &EngineBuilder) -> (Glfw, PWindow, GlfwReceiver<(f64, WindowEvent)>) {
     use glfw::fail_on_errors;
     // Initialize GLFW
     let mut glfw = glfw::init(fail_on_errors!()).unwrap();
 
+    // Context hints only apply to windows created after they are set
+    glfw.window_hint(WindowHint::ContextVersion(builder.gl_version.0, builder.gl_version.1));
+    glfw.window_hint(WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
+    glfw.window_hint(WindowHint::OpenGlDebugContext(builder.debug_context));
+    glfw.window_hint(WindowHint::Resizable(builder.resizable));
+    glfw.window_hint(WindowHint::Visible(!builder.headless));
+    // The offscreen target is multisampled instead of the hidden window
+    glfw.window_hint(WindowHint::Samples(
+        (builder.samples > 0 && !builder.headless).then_some(builder.samples),
+    ));
+
     // Create a window
     let (mut window, events) = glfw
         .create_window(
-            WIDTH as u32,
-            HEIGHT as u32,
-            window_name,
+            builder.size[0] as u32,
+            builder.size[1] as u32,
+            &builder.title,
             glfw::WindowMode::Windowed,
         )
         .expect("Failed to create GLFW window.");
 
-    // Set OpenGL context hints
-    glfw.window_hint(WindowHint::ContextVersionMajor(4));
-    glfw.window_hint(WindowHint::ContextVersionMinor(6));
-    glfw.window_hint(WindowHint::OpenGlProfile(glfw::OpenGlProfileHint::Core));
-    glfw.window_hint(WindowHint::OpenGlDebugContext(true));
-
     // Set up window
     window.make_current();
     window.set_framebuffer_size_polling(true);
@@ -701,33 +1164,105 @@ fn init_gflw(window_name: &str) -> (Glfw, PWindow, GlfwReceiver<(f64, WindowEven
     window.set_cursor_pos_polling(true);
     window.set_mouse_button_polling(true);
     window.set_scroll_polling(true);
-    glfw.set_swap_interval(SwapInterval::Sync(1)); // Enable VSync
+    glfw.set_swap_interval(if builder.vsync {
+        SwapInterval::Sync(1)
+    } else {
+        SwapInterval::None
+    });
+    window.set_cursor_mode(builder.cursor_mode);
 
     // Load OpenGL functions
     gl::load_with(|s| glfw.get_proc_address_raw(s));
 
     // Configure OpenGL
     unsafe {
-        // Disable cursor initially for camera control
-        glfwSetInputMode(window.window_ptr(), CURSOR, CURSOR_DISABLED);
-
         // Enable depth testing for 3D rendering
         gl::Enable(DEPTH_TEST);
+        if builder.samples > 0 {
+            gl::Enable(gl::MULTISAMPLE);
+        }
 
-        // Set up debug output
-        gl::Enable(DEBUG_OUTPUT);
-        gl::Enable(DEBUG_OUTPUT_SYNCHRONOUS);
-        // gl::Enable(PROGRAM_POINT_SIZE);
-        gl::DebugMessageControl(
-            DEBUG_SOURCE_API,
-            DEBUG_TYPE_ERROR,
-            DEBUG_SEVERITY_NOTIFICATION,
-            0,
-            ptr::null(),
-            gl::TRUE,
-        );
-        gl::DebugMessageCallback(Some(debug_log), ptr::null());
+        if builder.debug_context {
+            // Set up debug output
+            gl::Enable(DEBUG_OUTPUT);
+            gl::Enable(DEBUG_OUTPUT_SYNCHRONOUS);
+            // gl::Enable(PROGRAM_POINT_SIZE);
+            gl::DebugMessageControl(
+                DEBUG_SOURCE_API,
+                DEBUG_TYPE_ERROR,
+                DEBUG_SEVERITY_NOTIFICATION,
+                0,
+                ptr::null(),
+                gl::TRUE,
+            );
+            gl::DebugMessageCallback(Some(debug_log), ptr::null());
+        }
     }
 
     (glfw, window, events)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bounds::Bounds;
+    use core::any::Any;
+    use core::cell::Cell;
+    use transformation::{Transform, Transformable};
+
+    /// A box that counts how often its bounds are asked for.
+    struct Counted {
+        transform: Transform,
+        calls: Rc<Cell<usize>>,
+        keyed: bool,
+    }
+    derive_transformable!(Counted);
+
+    impl Render for Counted {
+        fn render(&mut self, _: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+        fn is(&self) -> bool {
+            true
+        }
+        fn set_is(&mut self, _: bool) {}
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+        fn bounds(&mut self) -> Option<Bounds> {
+            self.calls.set(self.calls.get() + 1);
+            let min = self.transform.position;
+            Some(Bounds::from_points(&[min, min + Vector3::new(1.0, 1.0, 1.0)]))
+        }
+        fn bounds_key(&self) -> Option<u64> {
+            self.keyed.then(|| u64::from(self.transform.position.x.to_bits()))
+        }
+    }
+
+    #[test]
+    fn only_renderables_whose_key_changed_are_refitted() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = |x: f32, keyed: bool| {
+            let transform = Transform::with_position(Vector3::new(x, 0.0, 0.0));
+            new_renderable_ptr(Counted { transform, calls: calls.clone(), keyed })
+        };
+        let mut renderables = vec![counted(0.0, true), counted(5.0, true), counted(10.0, false)];
+        let mut bvh = RenderableBvh::default();
+        assert_eq!(bvh.refresh(&renderables).unwrap(), 3);
+        assert_eq!(calls.get(), 3);
+
+        // Only the renderable without a key is asked again.
+        assert_eq!(bvh.refresh(&renderables).unwrap(), 0);
+        assert_eq!(calls.get(), 4);
+
+        renderables[1].borrow_mut().set_translation(20.0, 0.0, 0.0);
+        assert_eq!(bvh.refresh(&renderables).unwrap(), 1);
+        assert_eq!(calls.get(), 6);
+        assert_eq!(bvh.tree.item_bounds(1).min, Vector3::new(20.0, 0.0, 0.0));
+        assert_eq!(bvh.tree.root_bounds().max, Vector3::new(21.0, 1.0, 1.0));
+
+        renderables.push(counted(-5.0, true));
+        assert_eq!(bvh.refresh(&renderables).unwrap(), 4);
+        assert_eq!(bvh.tree.len(), 4);
+    }
+}
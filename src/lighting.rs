@@ -0,0 +1,148 @@
+//! Punctual lights, uploaded to the `World` uniform block by `ShaderManager::upload_world`.
+use cgmath::{InnerSpace, Matrix4, Vector3, Vector4};
+
+/// Must match `MAX_LIGHTS` in `base_shader.frag`.
+pub const MAX_LIGHTS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    /// Shines along `direction` from infinitely far away.
+    Directional,
+    Point,
+    /// A cone around `direction`; angles are in radians from its axis.
+    Spot {
+        inner_cone_angle: f32,
+        outer_cone_angle: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Light {
+    pub name: Option<String>,
+    pub kind: LightKind,
+    /// Linear RGB.
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    /// Distance at which the light's influence reaches zero, `None` for unlimited.
+    pub range: Option<f32>,
+    pub position: Vector3<f32>,
+    /// Unit vector the light points along; unused for point lights.
+    pub direction: Vector3<f32>,
+}
+
+impl Light {
+    /// Creates a light at the origin pointing down -Z, the glTF convention.
+    #[must_use]
+    pub const fn new(kind: LightKind, color: Vector3<f32>, intensity: f32) -> Self {
+        Self {
+            name: None,
+            kind,
+            color,
+            intensity,
+            range: None,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+        }
+    }
+
+    /// Returns the light moved by `matrix`, e.g. the world matrix of the node it's attached to.
+    #[must_use]
+    pub fn transformed(&self, matrix: Matrix4<f32>) -> Self {
+        let position = matrix * self.position.extend(1.0);
+        let direction = (matrix * self.direction.extend(0.0)).truncate();
+        Self {
+            position: position.truncate() / position.w,
+            direction: if direction.magnitude2() > 0.0 {
+                direction.normalize()
+            } else {
+                self.direction
+            },
+            ..self.clone()
+        }
+    }
+
+    /// The light in the std140 layout of `Light` in `base_shader.frag`.
+    fn std140(&self) -> [Vector4<f32>; 4] {
+        let (kind, cone) = match self.kind {
+            LightKind::Directional => (0.0, Vector4::new(0.0, 0.0, 0.0, 0.0)),
+            LightKind::Point => (1.0, Vector4::new(0.0, 0.0, 0.0, 0.0)),
+            LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => (
+                2.0,
+                Vector4::new(inner_cone_angle.cos(), outer_cone_angle.cos(), 0.0, 0.0),
+            ),
+        };
+        [
+            self.position.extend(kind),
+            self.direction.extend(self.range.unwrap_or(0.0)),
+            self.color.extend(self.intensity),
+            cone,
+        ]
+    }
+}
+
+/// The contents of the `World` uniform block: `ambient`, `lightCount` and `lights`.
+///
+/// Lights past `MAX_LIGHTS` are ignored.
+pub(crate) fn world_block(ambient: Vector4<f32>, lights: &[Light]) -> Vec<Vector4<f32>> {
+    let count = lights.len().min(MAX_LIGHTS);
+    let mut block = Vec::with_capacity(2 + 4 * MAX_LIGHTS);
+    block.push(ambient);
+    // `lightCount` is an int, so its bits are stored as they are.
+    #[allow(clippy::cast_possible_truncation)]
+    block.push(Vector4::new(f32::from_bits(count as u32), 0.0, 0.0, 0.0));
+    for light in &lights[..count] {
+        block.extend(light.std140());
+    }
+    block.resize(2 + 4 * MAX_LIGHTS, Vector4::new(0.0, 0.0, 0.0, 0.0));
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_lights_matches_the_shader() {
+        let source = include_str!("../shaders/base_shader.frag");
+        assert!(source.contains(&format!("#define MAX_LIGHTS {MAX_LIGHTS}")));
+    }
+
+    #[test]
+    fn world_block_has_the_std140_layout() {
+        let ambient = Vector4::new(0.1, 0.2, 0.3, 1.0);
+        let mut spot = Light::new(
+            LightKind::Spot { inner_cone_angle: 0.0, outer_cone_angle: std::f32::consts::FRAC_PI_2 },
+            Vector3::new(1.0, 0.5, 0.25),
+            3.0,
+        );
+        spot.range = Some(10.0);
+        spot.position = Vector3::new(1.0, 2.0, 3.0);
+        let point = Light::new(LightKind::Point, Vector3::new(1.0, 1.0, 1.0), 2.0);
+        let block = world_block(ambient, &[spot, point]);
+        let floats: Vec<f32> = block.iter().flat_map(|v| [v.x, v.y, v.z, v.w]).collect();
+
+        // vec4 ambient, int lightCount padded to 16 bytes, then 64 bytes per light.
+        assert_eq!(size_of_val(block.as_slice()), 16 + 16 + 64 * MAX_LIGHTS);
+        assert_eq!(&floats[0..4], &[0.1, 0.2, 0.3, 1.0]);
+        assert_eq!(floats[4].to_bits(), 2);
+        let light = |i: usize| &floats[8 + 16 * i..8 + 16 * (i + 1)];
+        assert_eq!(&light(0)[0..8], &[1.0, 2.0, 3.0, 2.0, 0.0, 0.0, -1.0, 10.0]);
+        assert_eq!(&light(0)[8..12], &[1.0, 0.5, 0.25, 3.0]);
+        assert!((light(0)[12] - 1.0).abs() < 1e-6 && light(0)[13].abs() < 1e-6);
+        // The point light is kind 1 and has no range.
+        assert_eq!(&light(1)[3..4], &[1.0]);
+        assert_eq!(&light(1)[7..8], &[0.0]);
+        assert!((2..MAX_LIGHTS).all(|i| light(i).iter().all(|&f| f == 0.0)));
+    }
+
+    #[test]
+    fn lights_past_the_maximum_are_dropped() {
+        let lights = vec![Light::new(LightKind::Directional, Vector3::new(1.0, 1.0, 1.0), 1.0); MAX_LIGHTS + 3];
+        let block = world_block(Vector4::new(0.0, 0.0, 0.0, 1.0), &lights);
+        assert_eq!(block.len(), 2 + 4 * MAX_LIGHTS);
+        assert_eq!(block[1].x.to_bits() as usize, MAX_LIGHTS);
+    }
+}
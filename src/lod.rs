@@ -0,0 +1,242 @@
+//! Drawing simplified versions of a mesh when it is small on screen.
+use crate::bounds::{Bounds, Ray};
+use crate::derive_transformable;
+use crate::renderable::{hash_placement, Mesh, RayHit, Render, Renderable};
+use crate::shader::ShaderPtr;
+use crate::transformation::{Camera, Transform, Transformable};
+use cgmath::{Matrix4, SquareMatrix};
+use log::warn;
+use std::any::Any;
+use std::error::Error;
+use std::hash::{DefaultHasher, Hasher};
+
+/// One level of detail of a `LodRenderable`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodLevel {
+    /// Fraction of the full mesh's triangles the level keeps.
+    pub ratio: f32,
+    /// The smallest projected size, as a fraction of the viewport's height, the level is drawn at;
+    /// see `Camera::projected_size`.
+    pub screen_size: f32,
+}
+
+impl LodLevel {
+    #[must_use]
+    pub const fn new(ratio: f32, screen_size: f32) -> Self {
+        Self { ratio, screen_size }
+    }
+}
+
+/// The full mesh and three levels down to a tenth of its triangles.
+pub const DEFAULT_LEVELS: [LodLevel; 4] = [
+    LodLevel::new(1.0, 0.5),
+    LodLevel::new(0.5, 0.25),
+    LodLevel::new(0.25, 0.1),
+    LodLevel::new(0.1, 0.0),
+];
+
+/// A simplified mesh and when to draw it.
+struct Level {
+    renderable: Renderable,
+    screen_size: f32,
+    /// The largest error of the simplification, in model units; see `simplify::simplify`.
+    error: f32,
+}
+
+/// A mesh with several simplified levels, one of which is drawn each frame depending on how large
+/// the mesh appears to the camera.
+pub struct LodRenderable {
+    levels: Vec<Level>,
+    /// Bounds of the full mesh in model space.
+    local_bounds: Bounds,
+    current: usize,
+    /// Multiplies the projected size before choosing a level; larger values keep detail longer.
+    pub bias: f32,
+    pub transform: Transform,
+    /// World matrix of the parent node, applied before `transform`.
+    parent: Matrix4<f32>,
+    is: bool,
+}
+
+impl LodRenderable {
+    /// Simplifies copies of `mesh` into `levels`, each from the one before. Levels should go from
+    /// the most to the least detailed; the first one normally keeps the full mesh. The indices must
+    /// be a triangle list. Levels whose simplification stops short of their triangle target, e.g.
+    /// because the mesh has many open borders, are kept with a warning.
+    ///
+    /// Levels that keep every triangle of the one before draw its vertex buffers, or those of
+    /// `mesh` if it is initialized, instead of uploading another copy.
+    /// # Errors
+    /// If `levels` is empty or a level's mesh cannot be initialized.
+    pub fn new(mesh: &Mesh, shader: &ShaderPtr, levels: &[LodLevel]) -> Result<Self, Box<dyn Error>> {
+        if levels.is_empty() {
+            return Err("A LodRenderable needs at least one level".into());
+        }
+        let local_bounds = mesh.bounds();
+        let triangles = mesh.indices.len() / 3;
+        let mut built: Vec<Level> = Vec::with_capacity(levels.len());
+        for (index, level) in levels.iter().enumerate() {
+            let target = (triangles as f32 * level.ratio.clamp(0.0, 1.0)).round() as usize;
+            let previous = built.last();
+            let source = previous.map_or(mesh, |previous| &previous.renderable.mesh_data);
+            let mut error = previous.map_or(0.0, |previous| previous.error);
+            let renderable = if target >= source.indices.len() / 3 {
+                match previous {
+                    Some(previous) => previous.renderable.share(),
+                    None => Renderable::from_shared_mesh(mesh, shader)?,
+                }
+            } else {
+                let (simplified, added) = source.simplified(target, f32::INFINITY);
+                let kept = simplified.indices.len() / 3;
+                if kept > target {
+                    warn!("LOD level {index} kept {kept} triangles, more than its target of {target}");
+                }
+                error = error.max(added);
+                Renderable::from_mesh(simplified, shader)?
+            };
+            built.push(Level {
+                renderable,
+                screen_size: level.screen_size,
+                error,
+            });
+        }
+        Ok(Self {
+            levels: built,
+            local_bounds,
+            current: 0,
+            bias: 1.0,
+            transform: Transform::default(),
+            parent: Matrix4::identity(),
+            is: true,
+        })
+    }
+
+    /// The index of the level chosen for the last frame.
+    #[must_use]
+    pub const fn current_level(&self) -> usize {
+        self.current
+    }
+
+    #[must_use]
+    pub const fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The renderable of level `index`, e.g. to change its draw type.
+    #[must_use]
+    pub fn level(&self, index: usize) -> &Renderable {
+        &self.levels[index].renderable
+    }
+
+    /// The triangle count and simplification error of level `index`.
+    #[must_use]
+    pub fn level_stats(&self, index: usize) -> (usize, f32) {
+        let level = &self.levels[index];
+        (level.renderable.mesh_data.indices.len() / 3, level.error)
+    }
+
+    /// Sets the world matrix of the node the object is attached to, e.g. by a `RenderableGroup`.
+    pub const fn set_parent_matrix(&mut self, parent: Matrix4<f32>) {
+        self.parent = parent;
+    }
+
+    fn world_matrix(&self) -> Matrix4<f32> {
+        self.parent * self.transform.mat()
+    }
+
+    /// The level chosen for the last frame, placed like the whole object.
+    fn current(&mut self) -> &mut Renderable {
+        let renderable = &mut self.levels[self.current].renderable;
+        renderable.transform.clone_from(&self.transform);
+        renderable.set_parent_matrix(self.parent);
+        renderable
+    }
+}
+
+impl Render for LodRenderable {
+    fn render(&mut self, shader_override: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
+        if !self.is {
+            return Ok(());
+        }
+        self.current().render(shader_override)
+    }
+
+    fn is(&self) -> bool {
+        self.is
+    }
+
+    fn set_is(&mut self, val: bool) {
+        self.is = val;
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn bounds(&mut self) -> Option<Bounds> {
+        (!self.local_bounds.is_empty()).then(|| self.local_bounds.transformed(&self.world_matrix()))
+    }
+
+    fn bounds_key(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        hash_placement(&mut hasher, &self.transform, &self.parent, &self.local_bounds.aabb);
+        Some(hasher.finish())
+    }
+
+    /// Hits the level chosen for the last frame; its `triangle` refers to that level's mesh.
+    fn raycast(&mut self, ray: &Ray) -> Option<RayHit> {
+        self.current().raycast(ray)
+    }
+
+    /// Picks the first level whose screen size the projected bounding sphere reaches, or the
+    /// last level if it reaches none.
+    fn prepare(&mut self, camera: &Camera) {
+        let sphere = self.local_bounds.sphere.transformed(&self.world_matrix());
+        let size = camera.projected_size(sphere.center, sphere.radius.max(0.0)) * self.bias;
+        self.current = select_level(self.levels.iter().map(|level| level.screen_size), size);
+    }
+}
+derive_transformable!(LodRenderable);
+
+/// The index of the first screen size `size` reaches, or of the last one if it reaches none.
+fn select_level(screen_sizes: impl ExactSizeIterator<Item = f32>, size: f32) -> usize {
+    let count = screen_sizes.len();
+    screen_sizes
+        .enumerate()
+        .find_map(|(index, screen_size)| (size >= screen_size).then_some(index))
+        .unwrap_or(count - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Vector3;
+
+    fn default_level(size: f32) -> usize {
+        select_level(DEFAULT_LEVELS.iter().map(|level| level.screen_size), size)
+    }
+
+    #[test]
+    fn levels_are_chosen_by_screen_size() {
+        assert_eq!(default_level(f32::INFINITY), 0);
+        assert_eq!(default_level(0.5), 0);
+        assert_eq!(default_level(0.3), 1);
+        assert_eq!(default_level(0.25), 1);
+        assert_eq!(default_level(0.1), 2);
+        assert_eq!(default_level(0.0), 3);
+        // Sizes below every level's draw the last one.
+        assert_eq!(select_level([0.5, 0.2].into_iter(), 0.1), 1);
+    }
+
+    #[test]
+    fn bias_scales_the_projected_size() {
+        let mut camera = Camera::default();
+        camera.pos = Vector3::new(0.0, 0.0, 3.0);
+        let size = camera.projected_size(Vector3::new(0.0, 0.0, 0.0), 1.0);
+        let level = |bias: f32| default_level(size * bias);
+        assert_eq!(level(1.0), 1, "size {size}");
+        assert_eq!(level(4.0), 0);
+        assert_eq!(level(0.5), 2);
+        assert_eq!(level(0.01), 3);
+    }
+}
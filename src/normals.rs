@@ -0,0 +1,314 @@
+//! Generating normals and tangents for indexed triangle lists.
+//!
+//! A vertex can only have one normal or tangent, so vertices whose triangles need different
+//! values are split. The functions therefore return new vertices: each copies the other
+//! attributes of one of the original vertices and is used by a new index list.
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+
+/// Crease angle used by loaders when a file has no normals, in radians.
+///
+/// Faces meeting at a sharper angle keep a hard edge.
+pub const DEFAULT_CREASE_ANGLE: f32 = std::f32::consts::FRAC_PI_3;
+
+/// Per-vertex values that may have needed some vertices to be split.
+#[derive(Clone, Debug, Default)]
+pub struct SplitAttribute<T> {
+    /// The value of each new vertex.
+    pub values: Vec<T>,
+    /// The original vertex each new vertex copies its other attributes from.
+    pub sources: Vec<u32>,
+    /// The triangle list using the new vertices.
+    pub indices: Vec<u32>,
+}
+
+impl<T: Copy> SplitAttribute<T> {
+    /// Creates new vertices from the value of each corner of `indices`, sharing one vertex
+    /// between corners with the same original vertex and the same `key`.
+    #[allow(clippy::cast_possible_truncation)]
+    fn from_corners<K: Eq + std::hash::Hash>(indices: &[u32], corners: &[T], key: impl Fn(&T) -> K) -> Self {
+        let mut ret = Self {
+            values: Vec::new(),
+            sources: Vec::new(),
+            indices: Vec::with_capacity(indices.len()),
+        };
+        let mut lookup = HashMap::new();
+        for (&vertex, value) in indices.iter().zip(corners) {
+            let id = *lookup.entry((vertex, key(value))).or_insert_with(|| {
+                ret.values.push(*value);
+                ret.sources.push(vertex);
+                ret.values.len() as u32 - 1
+            });
+            ret.indices.push(id);
+        }
+        ret
+    }
+}
+
+/// Normals facing away from each triangle, giving faceted shading.
+///
+/// Vertices stay shared between triangles in the same plane.
+#[must_use]
+pub fn flat_normals(vertices: &[Vector3<f32>], indices: &[u32]) -> SplitAttribute<Vector3<f32>> {
+    let corners: Vec<_> = triangles(vertices, indices)
+        .flat_map(|corners| [face_normal(corners); 3])
+        .collect();
+    SplitAttribute::from_corners(indices, &corners, |n| bits(n.extend(0.0)))
+}
+
+/// Normals averaged from the triangles around each position, weighted by the angle of each
+/// triangle's corner there.
+///
+/// Vertices at the same position are smoothed together even if they are separate, such as
+/// along texture seams. Triangles are only smoothed with triangles meeting them at less than
+/// `crease_angle` radians, so sharper edges stay hard.
+#[must_use]
+pub fn smooth_normals(vertices: &[Vector3<f32>], indices: &[u32], crease_angle: f32) -> SplitAttribute<Vector3<f32>> {
+    let faces: Vec<_> = triangles(vertices, indices).map(face_normal).collect();
+    let angles: Vec<_> = triangles(vertices, indices).flat_map(corner_angles).collect();
+    let mut at_position: HashMap<[u32; 4], Vec<usize>> = HashMap::new();
+    for (corner, &vertex) in indices.iter().enumerate().take(faces.len() * 3) {
+        at_position.entry(bits(vertices[vertex as usize].extend(0.0))).or_default().push(corner);
+    }
+    let min_cos = crease_angle.cos();
+    let mut corners = vec![Vector3::new(0.0, 0.0, 0.0); faces.len() * 3];
+    for group in at_position.values() {
+        for &corner in group {
+            let own = faces[corner / 3];
+            let sum = group
+                .iter()
+                .filter(|&&other| faces[other / 3].magnitude2() > 0.0 && faces[other / 3].dot(own) >= min_cos)
+                .fold(Vector3::new(0.0, 0.0, 0.0), |sum, &other| sum + faces[other / 3] * angles[other]);
+            corners[corner] = if sum.magnitude2() > 0.0 { sum.normalize() } else { own };
+        }
+    }
+    SplitAttribute::from_corners(&indices[..corners.len()], &corners, |n| bits(n.extend(0.0)))
+}
+
+/// `MikkTSpace` tangents for normal mapping, matching the tangent space most tools bake normal
+/// maps in.
+///
+/// The tangent points along increasing u; w is the sign of the bitangent, which is
+/// `cross(normal, tangent) * w`. Vertices whose triangles need different tangents, such as where
+/// mirrored texture coordinates meet, are split.
+#[must_use]
+pub fn tangents(
+    vertices: &[Vector3<f32>],
+    normals: &[Vector3<f32>],
+    tex_coords: &[Vector2<f32>],
+    indices: &[u32],
+) -> SplitAttribute<Vector4<f32>> {
+    let count = indices.len() / 3 * 3;
+    let mut geometry = TangentGeometry {
+        vertices,
+        normals,
+        tex_coords,
+        indices: &indices[..count],
+        // Corners are only left unset when there is nothing to generate tangents for.
+        corners: indices[..count]
+            .iter()
+            .map(|&i| perpendicular(normals[i as usize]).extend(1.0))
+            .collect(),
+    };
+    bevy_mikktspace::generate_tangents(&mut geometry);
+    SplitAttribute::from_corners(&indices[..count], &geometry.corners, |t| bits(*t))
+}
+
+/// A triangle list as seen by `bevy_mikktspace`, collecting the tangent of each corner.
+struct TangentGeometry<'a> {
+    vertices: &'a [Vector3<f32>],
+    normals: &'a [Vector3<f32>],
+    tex_coords: &'a [Vector2<f32>],
+    indices: &'a [u32],
+    corners: Vec<Vector4<f32>>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.vertices[self.vertex(face, vert)].into()
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.normals[self.vertex(face, vert)].into()
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.tex_coords[self.vertex(face, vert)].into()
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corners[face * 3 + vert] = tangent.into();
+    }
+}
+
+/// The corners of each triangle of a triangle list.
+fn triangles<'a>(vertices: &'a [Vector3<f32>], indices: &'a [u32]) -> impl Iterator<Item = [Vector3<f32>; 3]> + 'a {
+    indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]].map(|i| vertices[i as usize]))
+}
+
+/// The unit normal of a counter-clockwise triangle, or zero if it is degenerate.
+fn face_normal([a, b, c]: [Vector3<f32>; 3]) -> Vector3<f32> {
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+/// The angle of a triangle at each of its corners, in radians.
+fn corner_angles([a, b, c]: [Vector3<f32>; 3]) -> [f32; 3] {
+    let angle = |corner: Vector3<f32>, next: Vector3<f32>, previous: Vector3<f32>| {
+        let (to_next, to_previous) = (next - corner, previous - corner);
+        if to_next.magnitude2() == 0.0 || to_previous.magnitude2() == 0.0 {
+            0.0
+        } else {
+            to_next.normalize().dot(to_previous.normalize()).clamp(-1.0, 1.0).acos()
+        }
+    };
+    [angle(a, b, c), angle(b, c, a), angle(c, a, b)]
+}
+
+/// Some unit vector perpendicular to `normal`, for vertices whose tangent is undefined.
+fn perpendicular(normal: Vector3<f32>) -> Vector3<f32> {
+    let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let tangent = axis - normal * normal.dot(axis);
+    if tangent.magnitude2() > 0.0 {
+        tangent.normalize()
+    } else {
+        axis
+    }
+}
+
+/// The bits of `v`, with -0.0 turned into 0.0, for use as a key.
+fn bits(v: Vector4<f32>) -> [u32; 4] {
+    [v.x, v.y, v.z, v.w].map(|c| (c + 0.0).to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles sharing the edge from the origin to +x: one in the xy plane, the other
+    /// below it and lifted by `lift`, so they meet at `atan(lift)`.
+    fn hinge(lift: f32) -> (Vec<Vector3<f32>>, Vec<u32>) {
+        let vertices = vec![
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.5, 1.0, 0.0),
+            Vector3::new(0.5, -1.0, lift),
+        ];
+        (vertices, vec![0, 1, 2, 1, 0, 3])
+    }
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    #[test]
+    fn flat_normals_split_the_shared_edge() {
+        let (vertices, indices) = hinge(1.0);
+        let split = flat_normals(&vertices, &indices);
+        assert_eq!(split.values.len(), 6);
+        assert_eq!(split.indices.len(), 6);
+        let second = Vector3::new(0.0, 1.0, 1.0).normalize();
+        for (corner, &vertex) in split.indices.iter().enumerate() {
+            let expected = if corner < 3 { Vector3::unit_z() } else { second };
+            assert!(close(split.values[vertex as usize], expected));
+            assert_eq!(split.sources[vertex as usize], indices[corner]);
+        }
+    }
+
+    #[test]
+    fn flat_normals_share_coplanar_vertices() {
+        let (vertices, indices) = hinge(0.0);
+        let split = flat_normals(&vertices, &indices);
+        assert_eq!(split.values.len(), 4);
+        assert!(split.values.iter().all(|&n| close(n, Vector3::unit_z())));
+    }
+
+    #[test]
+    fn smooth_normals_average_under_the_crease_angle() {
+        let (vertices, indices) = hinge(0.2);
+        let split = smooth_normals(&vertices, &indices, DEFAULT_CREASE_ANGLE);
+        assert_eq!(split.values.len(), 4);
+        let edge = split.values[split.indices[0] as usize];
+        assert!(close(edge, split.values[split.indices[1] as usize]));
+        let faces: Vec<_> = triangles(&vertices, &indices).map(face_normal).collect();
+        let angles: Vec<_> = triangles(&vertices, &indices).flat_map(corner_angles).collect();
+        assert!(close(edge, (faces[0] * angles[0] + faces[1] * angles[4]).normalize()));
+        assert!(close(split.values[split.indices[2] as usize], Vector3::unit_z()));
+    }
+
+    #[test]
+    fn smooth_normals_keep_edges_over_the_crease_angle_hard() {
+        let (vertices, indices) = hinge(3.0);
+        let split = smooth_normals(&vertices, &indices, DEFAULT_CREASE_ANGLE);
+        assert_eq!(split.values.len(), 6);
+        assert!(split.indices[..3].iter().all(|&v| close(split.values[v as usize], Vector3::unit_z())));
+
+        let split = smooth_normals(&vertices, &indices, std::f32::consts::FRAC_PI_2);
+        assert_eq!(split.values.len(), 4);
+    }
+
+    #[test]
+    fn smooth_normals_join_separate_vertices_at_one_position() {
+        let (mut vertices, _) = hinge(0.2);
+        vertices.extend([vertices[0], vertices[1]]);
+        let indices = [0, 1, 2, 5, 4, 3];
+        let split = smooth_normals(&vertices, &indices, DEFAULT_CREASE_ANGLE);
+        assert_eq!(split.values.len(), 6);
+        let value = |corner: usize| split.values[split.indices[corner] as usize];
+        assert!(close(value(0), value(4)));
+        assert!(close(value(1), value(3)));
+    }
+
+    #[test]
+    fn tangents_follow_increasing_u() {
+        let vertices = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].map(|(x, y)| Vector3::new(x, y, 0.0));
+        let tex_coords = vertices.map(Vector3::truncate);
+        let normals = [Vector3::unit_z(); 4];
+        let split = tangents(&vertices, &normals, &tex_coords, &[0, 1, 2, 0, 2, 3]);
+        assert_eq!(split.values.len(), 4);
+        for tangent in &split.values {
+            assert!(close(tangent.truncate(), Vector3::unit_x()));
+            assert!((tangent.w - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn tangents_split_vertices_on_mirrored_seams() {
+        // Two quads meeting at x = 0, with u mirrored across the seam.
+        let vertices = [(-1.0, 0.0), (0.0, 0.0), (0.0, 1.0), (-1.0, 1.0), (1.0, 0.0), (1.0, 1.0)]
+            .map(|(x, y)| Vector3::new(x, y, 0.0));
+        let tex_coords = vertices.map(|v: Vector3<f32>| Vector2::new(v.x.abs(), v.y));
+        let normals = [Vector3::unit_z(); 6];
+        let indices = [0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2];
+        let split = tangents(&vertices, &normals, &tex_coords, &indices);
+        assert_eq!(split.values.len(), 8);
+        for (corner, &vertex) in split.indices.iter().enumerate() {
+            let tangent = split.values[vertex as usize];
+            let (x, w) = if corner < 6 { (-1.0, -1.0) } else { (1.0, 1.0) };
+            assert!(close(tangent.truncate(), Vector3::new(x, 0.0, 0.0)));
+            assert!((tangent.w - w).abs() < 1e-6);
+        }
+        let seam = split.sources.iter().filter(|&&s| s == 1 || s == 2).count();
+        assert_eq!(seam, 4);
+    }
+}
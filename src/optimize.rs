@@ -0,0 +1,452 @@
+//! Reordering and merging vertices and triangles so large meshes draw faster.
+//!
+//! All functions work on indexed triangle lists and are deterministic: the same input always
+//! gives the same output.
+use crate::renderable::Mesh;
+use cgmath::{InnerSpace, Vector2, Vector3};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// The post-transform cache size the metrics and the overdraw optimization assume.
+pub const CACHE_SIZE: usize = 16;
+
+/// Which optimizations `apply` runs on a mesh, in the order listed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshOptimizer {
+    /// Merges vertices within this distance whose other attributes match; see `weld`.
+    pub weld_tolerance: Option<f32>,
+    /// Reorders triangles so vertices are reused while still in the post-transform cache.
+    pub vertex_cache: bool,
+    /// Reorders clusters of triangles so front faces tend to be drawn first.
+    pub overdraw: bool,
+    /// Reorders vertices in the order they are used and drops unused ones.
+    pub vertex_fetch: bool,
+}
+
+impl Default for MeshOptimizer {
+    /// Welds exact duplicates and runs all reorderings.
+    fn default() -> Self {
+        Self {
+            weld_tolerance: Some(0.0),
+            vertex_cache: true,
+            overdraw: true,
+            vertex_fetch: true,
+        }
+    }
+}
+
+impl MeshOptimizer {
+    /// Optimizes `mesh`, which must be a triangle list and be initialized afterwards.
+    ///
+    /// Welded vertices must match in every attribute, including joints, morph targets and
+    /// custom attributes; floating point attributes may differ by up to the weld tolerance.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn apply(&self, mesh: &mut Mesh) {
+        if let Some(tolerance) = self.weld_tolerance {
+            let (sources, indices) = weld(&mesh.vertices, &mesh.indices, tolerance, |a, b| {
+                same_attributes(mesh, a as usize, b as usize, tolerance)
+            });
+            mesh.remap_vertices(&sources, indices);
+        }
+        if self.vertex_cache {
+            mesh.indices = optimize_vertex_cache(&mesh.indices, mesh.vertices.len());
+        }
+        if self.overdraw {
+            mesh.indices = optimize_overdraw(&mesh.indices, &mesh.vertices);
+        }
+        if self.vertex_fetch {
+            let (sources, indices) = optimize_vertex_fetch(&mesh.indices, mesh.vertices.len());
+            mesh.remap_vertices(&sources, indices);
+        }
+    }
+}
+
+/// Whether vertices `a` and `b` of `mesh` have the same attributes apart from their position.
+fn same_attributes(mesh: &Mesh, a: usize, b: usize, tolerance: f32) -> bool {
+    let close = |x: &[f32], y: &[f32]| x.iter().zip(y).all(|(x, y)| (x - y).abs() <= tolerance);
+    let close3 = |v: &[Vector3<f32>]| {
+        let (x, y): ([f32; 3], [f32; 3]) = (v[a].into(), v[b].into());
+        close(&x, &y)
+    };
+    let close2 = |v: &Vec<Vector2<f32>>| {
+        let (x, y): ([f32; 2], [f32; 2]) = (v[a].into(), v[b].into());
+        close(&x, &y)
+    };
+    mesh.normals.as_deref().is_none_or(close3)
+        && mesh.tex_coords.as_ref().is_none_or(close2)
+        && mesh.joints.as_ref().is_none_or(|v| v[a] == v[b])
+        && mesh.weights.as_ref().is_none_or(|v| close(&v[a], &v[b]))
+        && mesh.colors.as_ref().is_none_or(|v| close(&v[a], &v[b]))
+        && mesh.morph_targets.iter().all(|target| {
+            close3(&target.positions) && target.normals.as_deref().is_none_or(close3)
+        })
+        && mesh.custom_attributes.iter().all(|custom| custom.same_vertex(a, b, tolerance))
+}
+
+/// Merges vertices whose positions are within `tolerance` of each other and for which `same`
+/// holds, returning the original vertex each new vertex copies and the new indices.
+///
+/// Each vertex is merged into the first earlier vertex it matches. Triangles that collapse
+/// because two of their corners were merged are removed.
+#[allow(clippy::cast_possible_truncation)]
+pub fn weld(
+    positions: &[Vector3<f32>],
+    indices: &[u32],
+    tolerance: f32,
+    same: impl Fn(u32, u32) -> bool,
+) -> (Vec<u32>, Vec<u32>) {
+    // With a tolerance, neighbouring cells have to be searched as well.
+    let cell_of = |p: Vector3<f32>| -> [i64; 3] {
+        if tolerance > 0.0 {
+            [p.x, p.y, p.z].map(|c| (c / tolerance).floor() as i64)
+        } else {
+            [p.x, p.y, p.z].map(|c| i64::from((c + 0.0).to_bits()))
+        }
+    };
+    let reach = i64::from(tolerance > 0.0);
+    let mut cells: HashMap<[i64; 3], Vec<u32>> = HashMap::new();
+    let mut sources = Vec::new();
+    let mut remap = Vec::with_capacity(positions.len());
+    for (vertex, &position) in positions.iter().enumerate() {
+        let vertex = vertex as u32;
+        let cell = cell_of(position);
+        let mut found = None;
+        'search: for dx in -reach..=reach {
+            for dy in -reach..=reach {
+                for dz in -reach..=reach {
+                    let Some(candidates) = cells.get(&[cell[0] + dx, cell[1] + dy, cell[2] + dz]) else {
+                        continue;
+                    };
+                    for &id in candidates {
+                        let other = sources[id as usize];
+                        if (positions[other as usize] - position).magnitude() <= tolerance && same(other, vertex) {
+                            found = Some(id);
+                            break 'search;
+                        }
+                    }
+                }
+            }
+        }
+        remap.push(found.unwrap_or_else(|| {
+            sources.push(vertex);
+            let id = sources.len() as u32 - 1;
+            cells.entry(cell).or_default().push(id);
+            id
+        }));
+    }
+    let indices = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]].map(|i| remap[i as usize]))
+        .filter(|[a, b, c]| a != b && b != c && a != c)
+        .flatten()
+        .collect();
+    (sources, indices)
+}
+
+/// Reorders triangles for the post-transform vertex cache, using Tom Forsyth's linear-speed
+/// algorithm.
+#[must_use]
+#[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+pub fn optimize_vertex_cache(indices: &[u32], vertex_count: usize) -> Vec<u32> {
+    // The cache modelled by the scoring, larger than the one used by the metrics.
+    const MODEL_SIZE: usize = 32;
+    fn score(position: Option<usize>, remaining: usize) -> f32 {
+        if remaining == 0 {
+            return -1.0;
+        }
+        let cache = match position {
+            // The triangle just drawn; these vertices are about equally likely to be reused.
+            Some(p) if p < 3 => 0.75,
+            Some(p) => (1.0 - (p - 3) as f32 / (MODEL_SIZE - 3) as f32).powf(1.5),
+            None => 0.0,
+        };
+        // Vertices with few triangles left are preferred, so no lonely triangles are left over.
+        cache + 2.0 / (remaining as f32).sqrt()
+    }
+
+    let triangle_count = indices.len() / 3;
+    let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        for &v in corners {
+            adjacency[v as usize].push(triangle);
+        }
+    }
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_score: Vec<f32> = adjacency.iter().map(|a| score(None, a.len())).collect();
+    let corners = |t: usize| [indices[t * 3], indices[t * 3 + 1], indices[t * 3 + 2]];
+    let mut triangle_score: Vec<f32> = (0..triangle_count)
+        .map(|t| corners(t).iter().map(|&v| vertex_score[v as usize]).sum())
+        .collect();
+    let mut added = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(MODEL_SIZE + 3);
+    let mut ret = Vec::with_capacity(triangle_count * 3);
+    let mut best = None;
+    let mut cursor = 0;
+    for _ in 0..triangle_count {
+        // Without a candidate near the cache, continue with the next triangle in input order.
+        let triangle = best.unwrap_or_else(|| {
+            while added[cursor] {
+                cursor += 1;
+            }
+            cursor
+        });
+        added[triangle] = true;
+        ret.extend(corners(triangle));
+        for v in corners(triangle) {
+            let adjacent = &mut adjacency[v as usize];
+            if let Some(i) = adjacent.iter().position(|&t| t == triangle) {
+                adjacent.swap_remove(i);
+            }
+        }
+        let mut new_cache: Vec<u32> = corners(triangle).to_vec();
+        new_cache.extend(cache.iter().filter(|v| !corners(triangle).contains(v)));
+        for (i, &v) in new_cache.iter().enumerate() {
+            cache_position[v as usize] = (i < MODEL_SIZE).then_some(i);
+        }
+        best = None;
+        let mut best_score = f32::MIN;
+        for &v in &new_cache {
+            let v = v as usize;
+            let new_score = score(cache_position[v], adjacency[v].len());
+            let delta = new_score - vertex_score[v];
+            vertex_score[v] = new_score;
+            for &t in &adjacency[v] {
+                triangle_score[t] += delta;
+            }
+        }
+        for &v in &new_cache {
+            for &t in &adjacency[v as usize] {
+                let better = match triangle_score[t].total_cmp(&best_score) {
+                    Ordering::Greater => true,
+                    Ordering::Equal => best.is_none_or(|b| t < b),
+                    Ordering::Less => false,
+                };
+                if better {
+                    best_score = triangle_score[t];
+                    best = Some(t);
+                }
+            }
+        }
+        new_cache.truncate(MODEL_SIZE);
+        cache = new_cache;
+    }
+    ret
+}
+
+/// Reorders clusters of triangles so that, seen from outside, triangles facing the viewer tend to
+/// be drawn before the ones they hide, reducing overdraw.
+///
+/// The order inside each cluster is kept, so it should run after `optimize_vertex_cache`.
+/// Clusters end where a triangle misses the cache with all three vertices, so the cache
+/// efficiency barely changes.
+#[must_use]
+pub fn optimize_overdraw(indices: &[u32], positions: &[Vector3<f32>]) -> Vec<u32> {
+    let mut cache = FifoCache::new(CACHE_SIZE);
+    let mut starts = Vec::new();
+    for (triangle, corners) in indices.chunks_exact(3).enumerate() {
+        let misses = corners.iter().filter(|&&v| cache.access(v)).count();
+        if misses == 3 || triangle == 0 {
+            starts.push(triangle * 3);
+        }
+    }
+    starts.push(indices.len() / 3 * 3);
+
+    let triangle = |corners: &[u32]| {
+        let [a, b, c] = [corners[0], corners[1], corners[2]].map(|i| positions[i as usize]);
+        let normal = (b - a).cross(c - a);
+        // The area-weighted centroid and the normal, whose length is twice the area.
+        ((a + b + c) / 3.0 * normal.magnitude(), normal)
+    };
+    let summed = |corners: &[u32]| {
+        corners.chunks_exact(3).map(triangle).fold(
+            (Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0), 0.0),
+            |(c, n, area), (centroid, normal)| (c + centroid, n + normal, area + normal.magnitude()),
+        )
+    };
+    let (total, _, total_area) = summed(&indices[..starts[starts.len() - 1]]);
+    let center = if total_area > 0.0 { total / total_area } else { total };
+    let mut clusters: Vec<(f32, &[u32])> = starts
+        .windows(2)
+        .map(|w| {
+            let corners = &indices[w[0]..w[1]];
+            let (centroid, normal, area) = summed(corners);
+            let centroid = if area > 0.0 { centroid / area } else { center };
+            let normal = if normal.magnitude2() > 0.0 { normal.normalize() } else { normal };
+            // Clusters facing away from the center are the outside of the mesh.
+            ((centroid - center).dot(normal), corners)
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.0.total_cmp(&a.0));
+    clusters.into_iter().flat_map(|(_, corners)| corners.iter().copied()).collect()
+}
+
+/// Renumbers vertices in the order the indices first use them, so vertex data is read
+/// sequentially, dropping unused vertices. Returns the original vertex each new vertex copies
+/// and the new indices.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn optimize_vertex_fetch(indices: &[u32], vertex_count: usize) -> (Vec<u32>, Vec<u32>) {
+    let mut remap: Vec<Option<u32>> = vec![None; vertex_count];
+    let mut sources = Vec::new();
+    let indices = indices
+        .iter()
+        .map(|&v| {
+            *remap[v as usize].get_or_insert_with(|| {
+                sources.push(v);
+                sources.len() as u32 - 1
+            })
+        })
+        .collect();
+    (sources, indices)
+}
+
+/// Average cache miss ratio: the vertices transformed per triangle with a FIFO cache of
+/// `cache_size` entries. 3 is the worst case, around 0.5 to 0.7 is good for regular meshes.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangles = indices.len() / 3;
+    if triangles == 0 {
+        return 0.0;
+    }
+    transformed(indices, cache_size) as f32 / triangles as f32
+}
+
+/// Average transform to vertex ratio: the vertices transformed per vertex with a FIFO cache of
+/// `cache_size` entries. 1 is optimal.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn atvr(indices: &[u32], vertex_count: usize, cache_size: usize) -> f32 {
+    if vertex_count == 0 {
+        return 0.0;
+    }
+    transformed(indices, cache_size) as f32 / vertex_count as f32
+}
+
+/// The number of cache misses drawing `indices`.
+fn transformed(indices: &[u32], cache_size: usize) -> usize {
+    let mut cache = FifoCache::new(cache_size);
+    indices.iter().filter(|&&v| cache.access(v)).count()
+}
+
+/// A simulated FIFO post-transform cache, as found in most GPUs.
+struct FifoCache {
+    entries: Vec<u32>,
+    next: usize,
+}
+
+impl FifoCache {
+    fn new(size: usize) -> Self {
+        Self {
+            entries: vec![u32::MAX; size.max(1)],
+            next: 0,
+        }
+    }
+
+    /// Looks `vertex` up, adding it on a miss. Returns whether it missed.
+    fn access(&mut self, vertex: u32) -> bool {
+        if self.entries.contains(&vertex) {
+            return false;
+        }
+        self.entries[self.next] = vertex;
+        self.next = (self.next + 1) % self.entries.len();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+    use crate::vertex::{AttributeValues, ComponentType, CustomAttribute, VertexAttribute};
+
+    #[test]
+    fn vertex_cache_optimization_lowers_acmr() {
+        let grid = primitives::plane(1.0, 1.0, 32);
+        let before = acmr(&grid.indices, CACHE_SIZE);
+        let optimized = optimize_vertex_cache(&grid.indices, grid.vertices.len());
+        let after = acmr(&optimized, CACHE_SIZE);
+        assert!(after < before * 0.8, "ACMR went from {before} to {after}");
+        // Same triangles, only reordered.
+        let sorted = |indices: &[u32]| {
+            let mut triangles: Vec<_> = indices.chunks_exact(3).map(<[u32]>::to_vec).collect();
+            triangles.sort();
+            triangles
+        };
+        assert_eq!(sorted(&optimized), sorted(&grid.indices));
+    }
+
+    #[test]
+    fn optimizing_twice_changes_nothing() {
+        let optimizer = MeshOptimizer::default();
+        let mut once = primitives::uv_sphere(1.0, 16, 12).into_mesh();
+        optimizer.apply(&mut once);
+        let mut twice = primitives::uv_sphere(1.0, 16, 12).into_mesh();
+        optimizer.apply(&mut twice);
+        optimizer.apply(&mut twice);
+        assert_eq!(twice.indices, once.indices);
+        assert_eq!(twice.vertices, once.vertices);
+        assert_eq!(twice.normals, once.normals);
+        assert_eq!(twice.tex_coords, once.tex_coords);
+    }
+
+    #[test]
+    fn welding_merges_near_duplicates_and_drops_collapsed_triangles() {
+        let positions = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            // Near duplicates of the first two vertices.
+            Vector3::new(1.0, 0.0005, 0.0),
+            Vector3::new(0.0, 0.0, 0.0005),
+            Vector3::new(1.0, 1.0, 0.0),
+        ];
+        let indices = [0, 1, 2, 3, 5, 2, 0, 4, 1];
+        let (sources, welded) = weld(&positions, &indices, 0.001, |_, _| true);
+        assert_eq!(sources, vec![0, 1, 2, 5]);
+        // The last triangle collapsed as vertices 0 and 4 merged.
+        assert_eq!(welded, vec![0, 1, 2, 1, 3, 2]);
+
+        let (sources, welded) = weld(&positions, &indices, 0.0, |_, _| true);
+        assert_eq!(sources.len(), positions.len());
+        assert_eq!(welded, indices.to_vec());
+        // Vertices whose other attributes differ are kept apart.
+        let (sources, welded) = weld(&positions, &indices, 0.001, |a, b| (a, b) != (0, 4));
+        assert_eq!(sources, vec![0, 1, 2, 4, 5]);
+        assert_eq!(welded.len(), indices.len());
+    }
+
+    #[test]
+    fn welding_compares_float_attributes_within_the_tolerance() {
+        // A plane drawn twice from separate copies of its vertices, which only the custom attribute
+        // keeps apart.
+        let count = primitives::plane(1.0, 1.0, 1).vertices.len();
+        let doubled = || {
+            let mut mesh = primitives::plane(1.0, 1.0, 1).into_mesh();
+            let sources: Vec<_> = (0..count as u32).chain(0..count as u32).collect();
+            let indices = mesh.indices.iter().copied().chain(mesh.indices.iter().map(|&i| i + count as u32)).collect();
+            mesh.remap_vertices(&sources, indices);
+            mesh
+        };
+        let offsets = |offset: f32| (0..2 * count).map(|v| if v < count { 0.0 } else { offset }).collect();
+        let float = |values| CustomAttribute::new(VertexAttribute::float("aCustom", 1, 8), AttributeValues::F32(values)).unwrap();
+        let optimizer = MeshOptimizer { weld_tolerance: Some(0.001), ..MeshOptimizer::default() };
+
+        let mut near = doubled();
+        near.custom_attributes.push(float(offsets(0.0005)));
+        optimizer.apply(&mut near);
+        assert_eq!(near.vertices.len(), count);
+
+        let mut far = doubled();
+        far.custom_attributes.push(float(offsets(0.01)));
+        optimizer.apply(&mut far);
+        assert_eq!(far.vertices.len(), 2 * count);
+
+        let mut integers = doubled();
+        let ids = (0..2 * count).map(|v| u32::from(v >= count)).collect();
+        let attribute = VertexAttribute::new("aId", ComponentType::U32, 1, false, 8);
+        integers.custom_attributes.push(CustomAttribute::new(attribute, AttributeValues::U32(ids)).unwrap());
+        optimizer.apply(&mut integers);
+        assert_eq!(integers.vertices.len(), 2 * count);
+    }
+}
@@ -0,0 +1,444 @@
+//! A reader for PLY (Stanford polygon) files, as written by most scanning software.
+//!
+//! ASCII, binary little endian and binary big endian files are supported. Only the `vertex` and
+//! `face` elements are used; other elements are skipped.
+use cgmath::{Vector2, Vector3};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScalarType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ScalarType {
+    fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => return Err(format!("Unknown PLY property type {name}").into()),
+        })
+    }
+
+    const fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// The value integer colors of this type are divided by to map them onto 0..1.
+    const fn color_scale(self) -> f64 {
+        match self {
+            Self::U8 | Self::I8 => 255.0,
+            Self::U16 | Self::I16 => 65535.0,
+            Self::U32 | Self::I32 => 4_294_967_295.0,
+            Self::F32 | Self::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum PropertyKind {
+    Scalar(ScalarType),
+    List { count: ScalarType, item: ScalarType },
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    kind: PropertyKind,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+impl Element {
+    fn find(&self, names: &[&str]) -> Option<usize> {
+        self.properties.iter().position(|p| names.contains(&p.name.as_str()))
+    }
+}
+
+/// The data after the header, read one value at a time.
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, kind: ScalarType) -> Result<f64, Box<dyn Error>> {
+        match self {
+            Self::Ascii(tokens) => Ok(tokens.next().ok_or("Unexpected end of PLY data")?.parse()?),
+            Self::Binary { data, big_endian } => {
+                if data.len() < kind.size() {
+                    return Err("Unexpected end of PLY data".into());
+                }
+                let (bytes, rest) = data.split_at(kind.size());
+                *data = rest;
+                macro_rules! decode {
+                    ($t:ty) => {{
+                        let bytes = bytes.try_into()?;
+                        if *big_endian {
+                            <$t>::from_be_bytes(bytes)
+                        } else {
+                            <$t>::from_le_bytes(bytes)
+                        }
+                    }};
+                }
+                Ok(match kind {
+                    ScalarType::I8 => f64::from(decode!(i8)),
+                    ScalarType::U8 => f64::from(decode!(u8)),
+                    ScalarType::I16 => f64::from(decode!(i16)),
+                    ScalarType::U16 => f64::from(decode!(u16)),
+                    ScalarType::I32 => f64::from(decode!(i32)),
+                    ScalarType::U32 => f64::from(decode!(u32)),
+                    ScalarType::F32 => f64::from(decode!(f32)),
+                    ScalarType::F64 => decode!(f64),
+                })
+            }
+        }
+    }
+
+    /// Reads one element. `values` gets one value per property, NaN for lists, whose items go to `lists`.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn read_element(&mut self, element: &Element, values: &mut Vec<f64>, lists: &mut Vec<Vec<f64>>) -> Result<(), Box<dyn Error>> {
+        values.clear();
+        lists.clear();
+        for property in &element.properties {
+            match property.kind {
+                PropertyKind::Scalar(kind) => values.push(self.read(kind)?),
+                PropertyKind::List { count, item } => {
+                    let count = self.read(count)? as usize;
+                    let list = (0..count).map(|_| self.read(item)).collect::<Result<_, _>>()?;
+                    values.push(f64::NAN);
+                    lists.push(list);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Vertex data read from a PLY file. A file without faces is a point cloud.
+#[derive(Clone, Debug, Default)]
+pub struct PlyMesh {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Option<Vec<Vector3<f32>>>,
+    /// RGBA in 0..1.
+    pub colors: Option<Vec<[f32; 4]>>,
+    pub tex_coords: Option<Vec<Vector2<f32>>>,
+    /// Triangle indices; polygons are triangulated as fans.
+    pub indices: Vec<u32>,
+}
+
+impl PlyMesh {
+    /// Reads a PLY file from disk.
+    /// # Errors
+    /// If the file cannot be opened or isn't a valid PLY file.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("Couldn't open file {path}: {e}"))?;
+        Self::read(BufReader::new(file)).map_err(|e| format!("Couldn't parse ply {path}: {e}").into())
+    }
+
+    /// Reads PLY data.
+    /// # Errors
+    /// If the data isn't a valid PLY file or uses an unknown format or property type.
+    pub fn read(mut reader: impl BufRead) -> Result<Self, Box<dyn Error>> {
+        let (encoding, elements) = Self::read_header(&mut reader)?;
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut body = match encoding {
+            Encoding::Ascii => Body::Ascii(std::str::from_utf8(&data)?.split_ascii_whitespace()),
+            Encoding::BinaryLittleEndian => Body::Binary {
+                data: &data,
+                big_endian: false,
+            },
+            Encoding::BinaryBigEndian => Body::Binary {
+                data: &data,
+                big_endian: true,
+            },
+        };
+
+        let mut ret = Self::default();
+        let mut values = Vec::new();
+        let mut lists = Vec::new();
+        for element in &elements {
+            match element.name.as_str() {
+                "vertex" => ret.read_vertices(element, &mut body)?,
+                "face" => {
+                    let indices = element
+                        .properties
+                        .iter()
+                        .filter(|p| matches!(p.kind, PropertyKind::List { .. }))
+                        .position(|p| p.name == "vertex_indices" || p.name == "vertex_index")
+                        .ok_or("PLY faces have no vertex_indices")?;
+                    for _ in 0..element.count {
+                        body.read_element(element, &mut values, &mut lists)?;
+                        ret.push_face(&lists[indices])?;
+                    }
+                }
+                _ => {
+                    for _ in 0..element.count {
+                        body.read_element(element, &mut values, &mut lists)?;
+                    }
+                }
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Whether the file had no faces.
+    #[must_use]
+    pub const fn is_point_cloud(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    fn read_header(reader: &mut impl BufRead) -> Result<(Encoding, Vec<Element>), Box<dyn Error>> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim() != "ply" {
+            return Err("Not a PLY file".into());
+        }
+        let mut encoding = None;
+        let mut elements: Vec<Element> = Vec::new();
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err("PLY header has no end_header".into());
+            }
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["end_header"] => break,
+                ["format", format, _version] => {
+                    encoding = Some(match *format {
+                        "ascii" => Encoding::Ascii,
+                        "binary_little_endian" => Encoding::BinaryLittleEndian,
+                        "binary_big_endian" => Encoding::BinaryBigEndian,
+                        _ => return Err(format!("Unknown PLY format {format}").into()),
+                    });
+                }
+                ["element", name, count] => elements.push(Element {
+                    name: (*name).to_owned(),
+                    count: count.parse()?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count, item, name] => {
+                    elements.last_mut().ok_or("PLY property outside of an element")?.properties.push(Property {
+                        name: (*name).to_owned(),
+                        kind: PropertyKind::List {
+                            count: ScalarType::parse(count)?,
+                            item: ScalarType::parse(item)?,
+                        },
+                    });
+                }
+                ["property", kind, name] => {
+                    elements.last_mut().ok_or("PLY property outside of an element")?.properties.push(Property {
+                        name: (*name).to_owned(),
+                        kind: PropertyKind::Scalar(ScalarType::parse(kind)?),
+                    });
+                }
+                // Comments, obj_info and blank lines.
+                _ => {}
+            }
+        }
+        Ok((encoding.ok_or("PLY header has no format")?, elements))
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn read_vertices(&mut self, element: &Element, body: &mut Body) -> Result<(), Box<dyn Error>> {
+        let find3 = |names: [&[&str]; 3]| -> Option<[usize; 3]> {
+            Some([element.find(names[0])?, element.find(names[1])?, element.find(names[2])?])
+        };
+        let position = find3([&["x"], &["y"], &["z"]]).ok_or("PLY vertices have no position")?;
+        let normal = find3([&["nx"], &["ny"], &["nz"]]);
+        let color = find3([
+            &["red", "r", "diffuse_red"],
+            &["green", "g", "diffuse_green"],
+            &["blue", "b", "diffuse_blue"],
+        ]);
+        let alpha = element.find(&["alpha", "a", "diffuse_alpha"]);
+        let tex_coord = element
+            .find(&["u", "s", "texture_u", "texture_s"])
+            .zip(element.find(&["v", "t", "texture_v", "texture_t"]));
+        let scale = |index: usize| match element.properties[index].kind {
+            PropertyKind::Scalar(kind) => kind.color_scale(),
+            PropertyKind::List { .. } => 1.0,
+        };
+
+        let mut normals = Vec::new();
+        let mut colors = Vec::new();
+        let mut tex_coords = Vec::new();
+        let mut values = Vec::new();
+        let mut lists = Vec::new();
+        self.positions.reserve(element.count);
+        for _ in 0..element.count {
+            body.read_element(element, &mut values, &mut lists)?;
+            let vector = |[x, y, z]: [usize; 3]| Vector3::new(values[x] as f32, values[y] as f32, values[z] as f32);
+            self.positions.push(vector(position));
+            if let Some(normal) = normal {
+                normals.push(vector(normal));
+            }
+            if let Some(color) = color {
+                let channel = |i: usize| (values[i] / scale(i)) as f32;
+                colors.push([
+                    channel(color[0]),
+                    channel(color[1]),
+                    channel(color[2]),
+                    alpha.map_or(1.0, channel),
+                ]);
+            }
+            if let Some((u, v)) = tex_coord {
+                tex_coords.push(Vector2::new(values[u] as f32, values[v] as f32));
+            }
+        }
+        self.normals = normal.map(|_| normals);
+        self.colors = color.map(|_| colors);
+        self.tex_coords = tex_coord.map(|_| tex_coords);
+        Ok(())
+    }
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn push_face(&mut self, face: &[f64]) -> Result<(), Box<dyn Error>> {
+        let ids: Vec<u32> = face.iter().map(|i| *i as u32).collect();
+        if ids.iter().any(|i| *i as usize >= self.positions.len()) {
+            return Err("PLY face index out of range".into());
+        }
+        for i in 1..ids.len().saturating_sub(1) {
+            self.indices.extend([ids[0], ids[i], ids[i + 1]]);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUAD_HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+
+    /// A binary unit quad with one four-sided face, in the given byte order.
+    fn binary_quad(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let mut data = format!("ply\nformat {format} 1.0\n{QUAD_HEADER}").into_bytes();
+        let f32_bytes = |v: f32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        let i32_bytes = |v: i32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+        for [x, y] in [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]] {
+            data.extend(f32_bytes(x));
+            data.extend(f32_bytes(y));
+            data.extend(f32_bytes(0.5));
+            data.extend([255, 0, 51]);
+        }
+        data.push(4);
+        for i in 0..4 {
+            data.extend(i32_bytes(i));
+        }
+        data
+    }
+
+    fn assert_quad(mesh: &PlyMesh) {
+        assert_eq!(mesh.positions.len(), 4);
+        assert_eq!(mesh.positions[2], Vector3::new(1.0, 1.0, 0.5));
+        // The quad is triangulated as a fan around its first corner.
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        let colors = mesh.colors.as_ref().unwrap();
+        let expected = [1.0, 0.0, 0.2, 1.0];
+        assert!(colors.iter().flat_map(|c| c.iter().zip(expected)).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert!(mesh.normals.is_none() && mesh.tex_coords.is_none());
+        assert!(!mesh.is_point_cloud());
+    }
+
+    #[test]
+    fn reads_ascii() {
+        let source = format!(
+            "ply\nformat ascii 1.0\ncomment made by hand\n{QUAD_HEADER}\
+             0 0 0.5 255 0 51\n1 0 0.5 255 0 51\n1 1 0.5 255 0 51\n0 1 0.5 255 0 51\n4 0 1 2 3\n"
+        );
+        assert_quad(&PlyMesh::read(source.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn reads_binary_little_and_big_endian() {
+        assert_quad(&PlyMesh::read(binary_quad(false).as_slice()).unwrap());
+        assert_quad(&PlyMesh::read(binary_quad(true).as_slice()).unwrap());
+    }
+
+    #[test]
+    fn triangulates_polygons_as_fans() {
+        let source = "ply\nformat ascii 1.0\nelement vertex 5\nproperty float x\nproperty float y\nproperty float z\n\
+                      element face 2\nproperty list uchar uint vertex_index\nend_header\n\
+                      0 0 0\n1 0 0\n2 1 0\n1 2 0\n0 1 0\n5 0 1 2 3 4\n3 4 3 2\n";
+        let mesh = PlyMesh::read(source.as_bytes()).unwrap();
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3, 0, 3, 4, 4, 3, 2]);
+    }
+
+    #[test]
+    fn reads_point_clouds_and_skips_other_elements() {
+        let source = "ply\nformat ascii 1.0\nelement vertex 2\nproperty double x\nproperty double y\n\
+                      property double z\nproperty float nx\nproperty float ny\nproperty float nz\n\
+                      element camera 1\nproperty float view_px\nend_header\n\
+                      0 0 0 0 0 1\n1 2 3 0 1 0\n7\n";
+        let mesh = PlyMesh::read(source.as_bytes()).unwrap();
+        assert!(mesh.is_point_cloud());
+        assert_eq!(mesh.positions[1], Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(mesh.normals.unwrap()[1], Vector3::new(0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        let vertex = "element vertex 1\nproperty float x\nproperty float y\nproperty float z\n";
+        let cases = [
+            "obj\nformat ascii 1.0\nend_header\n".to_owned(),
+            format!("ply\nformat ascii 1.0\n{vertex}"),
+            format!("ply\nformat utf16 1.0\n{vertex}end_header\n0 0 0\n"),
+            format!("ply\n{vertex}end_header\n0 0 0\n"),
+            "ply\nformat ascii 1.0\nproperty float x\nend_header\n".to_owned(),
+            "ply\nformat ascii 1.0\nelement vertex 1\nproperty half x\nend_header\n0\n".to_owned(),
+            "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nend_header\n0\n".to_owned(),
+            format!("ply\nformat ascii 1.0\n{vertex}end_header\n0 0\n"),
+            format!("ply\nformat ascii 1.0\n{vertex}element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n"),
+        ];
+        for source in cases {
+            assert!(PlyMesh::read(source.as_bytes()).is_err(), "{source}");
+        }
+        let mut truncated = binary_quad(false);
+        truncated.truncate(truncated.len() - 2);
+        assert!(PlyMesh::read(truncated.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,308 @@
+//! Unindexed point data, such as raw scans, drawn as sprites.
+use crate::bounds::Bounds;
+use crate::derive_transformable;
+use crate::glutil::{GLBuffer, GLObject, VertexArrayObject};
+use crate::ply::PlyMesh;
+use crate::renderable::{hash_placement, Render};
+use crate::shader::{SetValue, Shader, ShaderManager, ShaderPtr};
+use crate::transformation::{Transform, Transformable};
+use crate::util::find_gl_error;
+use crate::vertex::{VertexAttribute, VertexLayout};
+use cgmath::{Matrix4, SquareMatrix, Vector3};
+use gl::types::GLint;
+use gl::STATIC_DRAW;
+use std::any::Any;
+use std::error::Error;
+use std::hash::{DefaultHasher, Hasher};
+
+/// How large points are drawn.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointSize {
+    /// Diameter in pixels, the same at every distance.
+    Screen(f32),
+    /// Diameter in world units, so points shrink with distance.
+    World(f32),
+}
+
+/// Shades points by the depth of their neighbours so the shape of unlit clouds stays readable.
+///
+/// The cloud is drawn into its own framebuffer and then composited onto the current one.
+pub struct EyeDomeLighting {
+    pub strength: f32,
+    /// Distance of the sampled neighbours in pixels.
+    pub radius: f32,
+    shader: ShaderPtr,
+    framebuffer: u32,
+    color: u32,
+    depth: u32,
+    /// An empty vertex array for the full screen triangle.
+    vertex_array: u32,
+    size: (GLint, GLint),
+}
+
+impl EyeDomeLighting {
+    /// # Errors
+    /// If the shader fails to compile.
+    pub fn new(manager: &mut ShaderManager) -> Result<Self, Box<dyn Error>> {
+        let shader = manager.register(Shader::from_source(
+            include_str!("../shaders/eye_dome.vert"),
+            include_str!("../shaders/eye_dome.frag"),
+            "",
+        )?);
+        let mut ret = Self {
+            strength: 1.0,
+            radius: 1.4,
+            shader,
+            framebuffer: 0,
+            color: 0,
+            depth: 0,
+            vertex_array: 0,
+            size: (0, 0),
+        };
+        unsafe {
+            gl::CreateFramebuffers(1, &mut ret.framebuffer);
+            gl::CreateVertexArrays(1, &mut ret.vertex_array);
+        }
+        find_gl_error()?;
+        Ok(ret)
+    }
+
+    /// Recreates the color and depth textures if the viewport changed size.
+    fn resize(&mut self, width: GLint, height: GLint) -> Result<(), Box<dyn Error>> {
+        if self.size == (width, height) {
+            return Ok(());
+        }
+        unsafe {
+            gl::DeleteTextures(1, &self.color);
+            gl::DeleteTextures(1, &self.depth);
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut self.color);
+            gl::TextureStorage2D(self.color, 1, gl::RGBA8, width, height);
+            gl::CreateTextures(gl::TEXTURE_2D, 1, &mut self.depth);
+            gl::TextureStorage2D(self.depth, 1, gl::DEPTH_COMPONENT32F, width, height);
+            for texture in [self.color, self.depth] {
+                gl::TextureParameteri(texture, gl::TEXTURE_MIN_FILTER, gl::NEAREST as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_MAG_FILTER, gl::NEAREST as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as GLint);
+                gl::TextureParameteri(texture, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as GLint);
+            }
+            gl::NamedFramebufferTexture(self.framebuffer, gl::COLOR_ATTACHMENT0, self.color, 0);
+            gl::NamedFramebufferTexture(self.framebuffer, gl::DEPTH_ATTACHMENT, self.depth, 0);
+            if gl::CheckNamedFramebufferStatus(self.framebuffer, gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+                return Err("Eye-dome lighting framebuffer is incomplete".into());
+            }
+        }
+        self.size = (width, height);
+        find_gl_error().map_err(Box::from)
+    }
+
+    /// Draws the points with `draw` into the offscreen targets and composites them with shading.
+    fn render(&mut self, draw: impl FnOnce() -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+        let mut viewport = [0; 4];
+        let mut previous = 0;
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::GetIntegerv(gl::DRAW_FRAMEBUFFER_BINDING, &mut previous);
+        }
+        self.resize(viewport[2], viewport[3])?;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.framebuffer);
+            gl::Viewport(0, 0, viewport[2], viewport[3]);
+            gl::ClearNamedFramebufferfv(self.framebuffer, gl::COLOR, 0, [0.0f32; 4].as_ptr());
+            gl::ClearNamedFramebufferfv(self.framebuffer, gl::DEPTH, 0, &1.0);
+        }
+        let drawn = draw();
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous.unsigned_abs());
+            gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+        }
+        drawn?;
+
+        let mut shader = self.shader.borrow_mut();
+        shader.use_();
+        shader.set(0, "colorTexture")?;
+        shader.set(1, "depthTexture")?;
+        shader.set(self.strength, "strength")?;
+        shader.set(self.radius, "radius")?;
+        unsafe {
+            gl::BindTextureUnit(0, self.color);
+            gl::BindTextureUnit(1, self.depth);
+            gl::BindVertexArray(self.vertex_array);
+            gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            gl::BindVertexArray(0);
+        }
+        Shader::clear_shader();
+        find_gl_error().map_err(Box::from)
+    }
+}
+
+impl Drop for EyeDomeLighting {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.color);
+            gl::DeleteTextures(1, &self.depth);
+            gl::DeleteFramebuffers(1, &self.framebuffer);
+            gl::DeleteVertexArrays(1, &self.vertex_array);
+        }
+    }
+}
+
+/// Points with a color each, drawn with `glDrawArrays` without an index buffer.
+pub struct PointCloud {
+    points: Vec<Vector3<f32>>,
+    /// Bounds of `points`, updated with them.
+    local_bounds: Bounds,
+    vertex_array: VertexArrayObject,
+    shader: ShaderPtr,
+    pub point_size: PointSize,
+    /// Draws points as discs instead of squares.
+    pub round: bool,
+    /// Eye-dome lighting, see `set_eye_dome_lighting`.
+    pub eye_dome_lighting: Option<EyeDomeLighting>,
+    transform: Transform,
+    is: bool,
+}
+
+impl PointCloud {
+    /// Uploads `points` with their `colors`, or white if there are none.
+    /// # Errors
+    /// If the shader fails to compile, the point data cannot be uploaded or the number of colors
+    /// doesn't match the number of points.
+    pub fn new(
+        points: Vec<Vector3<f32>>,
+        colors: Option<&[[f32; 4]]>,
+        manager: &mut ShaderManager,
+    ) -> Result<Self, Box<dyn Error>> {
+        let shader = manager.register(Shader::from_source(
+            include_str!("../shaders/point_cloud.vert"),
+            include_str!("../shaders/point_cloud.frag"),
+            "",
+        )?);
+        let mut ret = Self {
+            points: Vec::new(),
+            local_bounds: Bounds::EMPTY,
+            vertex_array: VertexArrayObject::new(),
+            shader,
+            point_size: PointSize::Screen(2.0),
+            round: true,
+            eye_dome_lighting: None,
+            transform: Transform::default(),
+            is: true,
+        };
+        ret.vertex_array.generate()?;
+        ret.set_points(points, colors)?;
+        Ok(ret)
+    }
+
+    /// Creates a point cloud from the vertices of a PLY file, ignoring any faces.
+    /// # Errors
+    /// If the file cannot be read or parsed, or the point cloud cannot be created.
+    pub fn from_ply(path: &str, manager: &mut ShaderManager) -> Result<Self, Box<dyn Error>> {
+        let ply = PlyMesh::open(path)?;
+        Self::new(ply.positions, ply.colors.as_deref(), manager)
+    }
+
+    /// Replaces the points and their colors.
+    /// # Errors
+    /// If the number of colors doesn't match the number of points, or the data cannot be uploaded.
+    pub fn set_points(&mut self, points: Vec<Vector3<f32>>, colors: Option<&[[f32; 4]]>) -> Result<(), Box<dyn Error>> {
+        if colors.is_some_and(|c| c.len() != points.len()) {
+            return Err("Point cloud needs one color per point".into());
+        }
+        let mut data = Vec::with_capacity(points.len() * 7);
+        for (i, point) in points.iter().enumerate() {
+            data.extend([point.x, point.y, point.z]);
+            data.extend(colors.map_or([1.0; 4], |c| c[i]));
+        }
+        self.vertex_array.vbos[0].buffer_data(data.as_slice(), STATIC_DRAW)?;
+        self.vertex_array
+            .configure(VertexLayout::new(vec![VertexAttribute::POSITION, VertexAttribute::COLOR]).vaas(0))?;
+        self.local_bounds = Bounds::from_points(&points);
+        self.points = points;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn points(&self) -> &[Vector3<f32>] {
+        &self.points
+    }
+
+    /// Turns eye-dome lighting on or off.
+    /// # Errors
+    /// If the eye-dome lighting shader fails to compile.
+    pub fn set_eye_dome_lighting(&mut self, enabled: bool, manager: &mut ShaderManager) -> Result<(), Box<dyn Error>> {
+        self.eye_dome_lighting = if enabled {
+            Some(EyeDomeLighting::new(manager)?)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn draw(&mut self, shader_override: Option<&ShaderPtr>) -> Result<(), Box<dyn Error>> {
+        let mut viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+            gl::Enable(gl::PROGRAM_POINT_SIZE);
+        }
+        let mut shader = shader_override.unwrap_or(&self.shader).borrow_mut();
+        shader.use_();
+        shader.set(self.transform.mat(), "model")?;
+        if shader_override.is_none() {
+            let (size, world_space) = match self.point_size {
+                PointSize::Screen(size) => (size, 0),
+                PointSize::World(size) => (size, 1),
+            };
+            shader.set(size, "pointSize")?;
+            shader.set(world_space, "worldSpace")?;
+            shader.set(viewport[3] as f32, "viewportHeight")?;
+            shader.set(i32::from(self.round), "roundPoints")?;
+        }
+        self.vertex_array.bind();
+        unsafe {
+            gl::DrawArrays(gl::POINTS, 0, i32::try_from(self.points.len())?);
+        }
+        self.vertex_array.unbind();
+        Shader::clear_shader();
+        find_gl_error().map_err(Box::from)
+    }
+}
+
+impl Render for PointCloud {
+    fn render(&mut self, shader_override: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
+        if !self.is {
+            return Ok(());
+        }
+        if shader_override.is_none() {
+            if let Some(mut edl) = self.eye_dome_lighting.take() {
+                let result = edl.render(|| self.draw(None));
+                self.eye_dome_lighting = Some(edl);
+                return result;
+            }
+        }
+        self.draw(shader_override.as_ref())
+    }
+
+    fn bounds(&mut self) -> Option<Bounds> {
+        (!self.local_bounds.is_empty()).then(|| self.local_bounds.transformed(&self.transform.mat()))
+    }
+
+    fn bounds_key(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        hash_placement(&mut hasher, &self.transform, &Matrix4::identity(), &self.local_bounds.aabb);
+        Some(hasher.finish())
+    }
+
+    fn is(&self) -> bool {
+        self.is
+    }
+
+    fn set_is(&mut self, val: bool) {
+        self.is = val;
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+derive_transformable!(PointCloud);
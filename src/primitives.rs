@@ -0,0 +1,452 @@
+//! Generators for common shapes.
+//!
+//! All shapes are centered on the origin with +Y up, wound counter-clockwise when seen from
+//! outside and have per-vertex normals, texture coordinates and tangents. Texture coordinates
+//! have v = 0 at the top, matching how images are uploaded.
+use crate::renderable::Mesh;
+use crate::vertex::{AttributeValues, CustomAttribute, VertexAttribute};
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4};
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+/// Indexed triangle-list data, usable with `Renderable::new` and `InstancedObject::new` or
+/// turned into a `Mesh` with all attributes.
+///
+/// `InstancedObject`s draw triangle fans by default, so they need
+/// `set_draw_type(gl::TRIANGLES)`.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub vertices: Vec<Vector3<f32>>,
+    pub indices: Vec<u32>,
+    pub normals: Vec<Vector3<f32>>,
+    pub tex_coords: Vec<Vector2<f32>>,
+    /// The direction of increasing u; w is the sign of the bitangent, glTF style.
+    pub tangents: Vec<Vector4<f32>>,
+}
+
+impl MeshData {
+    /// Creates an uninitialized mesh with the tangents as an `aTangent` attribute.
+    #[must_use]
+    pub fn into_mesh(self) -> Mesh {
+        let mut mesh = Mesh::new(self.vertices, self.indices, Some(self.normals), Some(self.tex_coords));
+        mesh.custom_attributes.push(CustomAttribute {
+            attribute: VertexAttribute::TANGENT,
+            values: AttributeValues::F32(self.tangents.iter().flat_map(|t| [t.x, t.y, t.z, t.w]).collect()),
+        });
+        mesh
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn push_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tex_coord: Vector2<f32>, tangent: Vector4<f32>) -> u32 {
+        self.vertices.push(position);
+        self.normals.push(normal);
+        self.tex_coords.push(tex_coord);
+        self.tangents.push(tangent);
+        self.vertices.len() as u32 - 1
+    }
+
+    /// Adds a rectangle around `center` facing `normal`, `right` being its u axis.
+    fn push_rect(&mut self, center: Vector3<f32>, normal: Vector3<f32>, right: Vector3<f32>, size: Vector2<f32>) {
+        let up = normal.cross(right);
+        let (r, u) = (right * size.x / 2.0, up * size.y / 2.0);
+        let tangent = right.extend(handedness(normal, right, -up));
+        let corners = [
+            (center - r - u, Vector2::new(0.0, 1.0)),
+            (center + r - u, Vector2::new(1.0, 1.0)),
+            (center + r + u, Vector2::new(1.0, 0.0)),
+            (center - r + u, Vector2::new(0.0, 0.0)),
+        ];
+        let ids = corners.map(|(p, t)| self.push_vertex(p, normal, t, tangent));
+        self.indices.extend([ids[0], ids[1], ids[2], ids[0], ids[2], ids[3]]);
+    }
+
+    /// Adds a disc in the XZ plane at height `y`, facing up or down.
+    fn push_disc(&mut self, radius: f32, y: f32, up: bool, segments: u32) {
+        let normal = Vector3::new(0.0, if up { 1.0 } else { -1.0 }, 0.0);
+        let right = Vector3::new(1.0, 0.0, 0.0);
+        // Looking at the disc, v runs towards +Z from above and towards -Z from below.
+        let v_axis = if up { 1.0 } else { -1.0 };
+        let tangent = right.extend(handedness(normal, right, Vector3::new(0.0, 0.0, v_axis)));
+        let center = self.push_vertex(Vector3::new(0.0, y, 0.0), normal, Vector2::new(0.5, 0.5), tangent);
+        let first = center + 1;
+        for j in 0..=segments {
+            let (sin, cos) = angle(j, segments).sin_cos();
+            let tex_coord = Vector2::new(0.5 + sin / 2.0, v_axis.mul_add(cos / 2.0, 0.5));
+            self.push_vertex(Vector3::new(radius * sin, y, radius * cos), normal, tex_coord, tangent);
+        }
+        for j in 0..segments {
+            let (a, b) = (first + j, first + j + 1);
+            self.indices.extend(if up { [center, a, b] } else { [center, b, a] });
+        }
+    }
+
+    /// Adds the surface swept by rotating `profile` around the Y axis.
+    ///
+    /// Each profile point is `(radius, y, normal)` with the normal in the (radial, y) plane; v
+    /// is proportional to the distance along the profile. Triangles at points with radius 0 are
+    /// left out as they would be degenerate.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+    fn push_lathe(&mut self, profile: &[(f32, f32, Vector2<f32>)], segments: u32) {
+        let mut lengths = vec![0.0];
+        for pair in profile.windows(2) {
+            let step = Vector2::new(pair[1].0 - pair[0].0, pair[1].1 - pair[0].1).magnitude();
+            lengths.push(lengths[lengths.len() - 1] + step);
+        }
+        let total = lengths[lengths.len() - 1];
+        let first = self.vertices.len() as u32;
+        for (k, &(radius, y, normal)) in profile.iter().enumerate() {
+            // The profile direction at this point, for the handedness of the tangent frame.
+            let (prev, next) = (profile[k.saturating_sub(1)], profile[(k + 1).min(profile.len() - 1)]);
+            let along = Vector2::new(next.0 - prev.0, next.1 - prev.1);
+            for j in 0..=segments {
+                let (sin, cos) = angle(j, segments).sin_cos();
+                let position = Vector3::new(radius * sin, y, radius * cos);
+                let normal = Vector3::new(normal.x * sin, normal.y, normal.x * cos).normalize();
+                let tangent = Vector3::new(cos, 0.0, -sin);
+                let bitangent = Vector3::new(along.x * sin, along.y, along.x * cos);
+                let tex_coord = Vector2::new(j as f32 / segments as f32, lengths[k] / total);
+                self.push_vertex(position, normal, tex_coord, tangent.extend(handedness(normal, tangent, bitangent)));
+            }
+        }
+        let row = segments + 1;
+        for k in 0..profile.len() as u32 - 1 {
+            for j in 0..segments {
+                let a = first + k * row + j;
+                let (b, c, d) = (a + row, a + row + 1, a + 1);
+                if profile[k as usize + 1].0 != 0.0 {
+                    self.indices.extend([a, b, c]);
+                }
+                if profile[k as usize].0 != 0.0 {
+                    self.indices.extend([a, c, d]);
+                }
+            }
+        }
+    }
+}
+
+/// The sign that makes `normal × tangent` point along `bitangent`.
+fn handedness(normal: Vector3<f32>, tangent: Vector3<f32>, bitangent: Vector3<f32>) -> f32 {
+    if normal.cross(tangent).dot(bitangent) < 0.0 {
+        -1.0
+    } else {
+        1.0
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn angle(step: u32, steps: u32) -> f32 {
+    step as f32 / steps as f32 * TAU
+}
+
+/// A cube with edges of length `size` and one texture per face; 24 vertices, 36 indices.
+#[must_use]
+pub fn cube(size: f32) -> MeshData {
+    let mut ret = MeshData::default();
+    let faces = [
+        (Vector3::unit_x(), -Vector3::unit_z()),
+        (-Vector3::unit_x(), Vector3::unit_z()),
+        (Vector3::unit_y(), Vector3::unit_x()),
+        (-Vector3::unit_y(), Vector3::unit_x()),
+        (Vector3::unit_z(), Vector3::unit_x()),
+        (-Vector3::unit_z(), -Vector3::unit_x()),
+    ];
+    for (normal, right) in faces {
+        ret.push_rect(normal * size / 2.0, normal, right, Vector2::new(size, size));
+    }
+    ret
+}
+
+/// A rectangle in the XY plane facing +Z.
+#[must_use]
+pub fn quad(width: f32, height: f32) -> MeshData {
+    let mut ret = MeshData::default();
+    ret.push_rect(Vector3::new(0.0, 0.0, 0.0), Vector3::unit_z(), Vector3::unit_x(), Vector2::new(width, height));
+    ret
+}
+
+/// A grid in the XZ plane facing +Y, split into `subdivisions` cells along each axis.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn plane(width: f32, depth: f32, subdivisions: u32) -> MeshData {
+    let mut ret = MeshData::default();
+    let cells = subdivisions.max(1);
+    let normal = Vector3::unit_y();
+    let tangent = Vector3::unit_x().extend(handedness(normal, Vector3::unit_x(), Vector3::unit_z()));
+    for i in 0..=cells {
+        for k in 0..=cells {
+            let (u, v) = (i as f32 / cells as f32, k as f32 / cells as f32);
+            let position = Vector3::new((u - 0.5) * width, 0.0, (v - 0.5) * depth);
+            ret.push_vertex(position, normal, Vector2::new(u, v), tangent);
+        }
+    }
+    let row = cells + 1;
+    for i in 0..cells {
+        for k in 0..cells {
+            let a = i * row + k;
+            let (b, c, d) = (a + 1, a + row + 1, a + row);
+            ret.indices.extend([a, b, c, a, c, d]);
+        }
+    }
+    ret
+}
+
+/// A sphere made of `rings` rows of `segments` quads, with triangles at the poles.
+///
+/// Has `(rings + 1) * (segments + 1)` vertices and `6 * segments * (rings - 1)` indices.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let (segments, rings) = (segments.max(3), rings.max(2));
+    let profile: Vec<_> = (0..=rings)
+        .map(|k| {
+            let (sin, cos) = (k as f32 / rings as f32 * PI).sin_cos();
+            // The poles are set exactly so the degenerate triangles are detected.
+            let sin = if k == 0 || k == rings { 0.0 } else { sin };
+            (radius * sin, radius * cos, Vector2::new(sin, cos))
+        })
+        .collect();
+    let mut ret = MeshData::default();
+    ret.push_lathe(&profile, segments);
+    ret
+}
+
+/// A sphere made by subdividing an icosahedron `subdivisions` times, giving evenly sized triangles.
+///
+/// Vertices along the texture seam and at the poles are duplicated so texture coordinates don't
+/// wrap around inside a triangle.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = f32::midpoint(1.0, 5.0_f32.sqrt());
+    let mut points: Vec<Vector3<f32>> = [
+        (-1.0, t, 0.0), (1.0, t, 0.0), (-1.0, -t, 0.0), (1.0, -t, 0.0),
+        (0.0, -1.0, t), (0.0, 1.0, t), (0.0, -1.0, -t), (0.0, 1.0, -t),
+        (t, 0.0, -1.0), (t, 0.0, 1.0), (-t, 0.0, -1.0), (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vector3::new(x, y, z).normalize())
+    .collect();
+    let mut faces: Vec<[u32; 3]> = vec![
+        [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+        [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+        [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+        [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+    ];
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                points.push((points[a as usize] + points[b as usize]).normalize());
+                points.len() as u32 - 1
+            })
+        };
+        faces = faces
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let mut ret = MeshData::default();
+    let u_of = |p: Vector3<f32>| p.x.atan2(p.z) / TAU + 0.5;
+    let mut shared = HashMap::new();
+    for face in faces {
+        let corners = face.map(|i| points[i as usize]);
+        let mut us = corners.map(u_of);
+        // Move corners that wrapped around to the other side of the seam.
+        if us.iter().copied().fold(f32::MIN, f32::max) - us.iter().copied().fold(f32::MAX, f32::min) > 0.5 {
+            us = us.map(|u| if u < 0.5 { u + 1.0 } else { u });
+        }
+        let is_pole = |p: Vector3<f32>| 1.0 - p.y.abs() < 1e-6;
+        for (k, corner) in corners.iter().enumerate() {
+            // At the poles u is undefined, so use the middle of the other two corners.
+            if is_pole(*corner) {
+                us[k] = f32::midpoint(us[(k + 1) % 3], us[(k + 2) % 3]);
+            }
+        }
+        for (k, &corner) in corners.iter().enumerate() {
+            let tex_coord = Vector2::new(us[k], corner.y.clamp(-1.0, 1.0).acos() / PI);
+            let key = (face[k], us[k].to_bits());
+            let id = *shared.entry(key).or_insert_with(|| {
+                // The derivatives along u and v, which stay defined at the poles since u is set there.
+                let (sin_u, cos_u) = ((us[k] - 0.5) * TAU).sin_cos();
+                let (sin_v, cos_v) = (tex_coord.y * PI).sin_cos();
+                let tangent = Vector3::new(cos_u, 0.0, -sin_u);
+                let bitangent = Vector3::new(cos_v * sin_u, -sin_v, cos_v * cos_u);
+                let w = handedness(corner, tangent, bitangent);
+                ret.push_vertex(corner * radius, corner, tex_coord, tangent.extend(w))
+            });
+            ret.indices.push(id);
+        }
+    }
+    ret
+}
+
+/// A closed cylinder along the Y axis.
+#[must_use]
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let half = height / 2.0;
+    let outward = Vector2::new(1.0, 0.0);
+    let mut ret = MeshData::default();
+    ret.push_lathe(&[(radius, half, outward), (radius, -half, outward)], segments);
+    ret.push_disc(radius, half, true, segments);
+    ret.push_disc(radius, -half, false, segments);
+    ret
+}
+
+/// A closed cone along the Y axis with its tip at `height / 2`.
+#[must_use]
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let segments = segments.max(3);
+    let half = height / 2.0;
+    let normal = Vector2::new(height, radius).normalize();
+    let mut ret = MeshData::default();
+    ret.push_lathe(&[(0.0, half, normal), (radius, -half, normal)], segments);
+    ret.push_disc(radius, -half, false, segments);
+    ret
+}
+
+/// A cylinder of `height` capped with hemispheres of `radius`, `rings` rows each.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let (segments, rings) = (segments.max(3), rings.max(1));
+    let half = height / 2.0;
+    let mut profile = Vec::new();
+    for k in 0..=rings {
+        let (sin, cos) = (k as f32 / rings as f32 * PI / 2.0).sin_cos();
+        let sin = if k == 0 { 0.0 } else { sin };
+        profile.push((radius * sin, radius.mul_add(cos, half), Vector2::new(sin, cos)));
+    }
+    for k in 0..=rings {
+        let (sin, cos) = (k as f32 / rings as f32 * PI / 2.0).sin_cos();
+        let cos = if k == rings { 0.0 } else { cos };
+        profile.push((radius * cos, (-radius).mul_add(sin, -half), Vector2::new(cos, -sin)));
+    }
+    let mut ret = MeshData::default();
+    ret.push_lathe(&profile, segments);
+    ret
+}
+
+/// A torus around the Y axis; `major_radius` is the distance from the center to the tube's
+/// center, `minor_radius` the tube's radius.
+#[must_use]
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> MeshData {
+    let (segments, sides) = (segments.max(3), sides.max(3));
+    // Around the tube, starting outside and heading down like the other profiles.
+    let profile: Vec<_> = (0..=sides)
+        .map(|k| {
+            let (sin, cos) = angle(k, sides).sin_cos();
+            (minor_radius.mul_add(cos, major_radius), -minor_radius * sin, Vector2::new(cos, -sin))
+        })
+        .collect();
+    let mut ret = MeshData::default();
+    ret.push_lathe(&profile, segments);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A shape's name, its data and the point its normals should point away from.
+    type Shape = (&'static str, MeshData, fn(Vector3<f32>) -> Vector3<f32>);
+
+    fn shapes() -> Vec<Shape> {
+        let origin = |_| Vector3::new(0.0, 0.0, 0.0);
+        vec![
+            ("cube", cube(2.0), origin),
+            ("uv_sphere", uv_sphere(1.5, 12, 7), origin),
+            ("icosphere", icosphere(1.0, 2), origin),
+            ("cylinder", cylinder(0.5, 2.0, 10), origin),
+            ("cone", cone(1.0, 2.0, 9), origin),
+            ("capsule", capsule(0.5, 1.0, 8, 3), origin),
+            // The closest point on the circle through the middle of the tube.
+            ("torus", torus(2.0, 0.5, 16, 8), |p| Vector3::new(p.x, 0.0, p.z).normalize() * 2.0),
+        ]
+    }
+
+    fn triangles(mesh: &MeshData) -> impl Iterator<Item = [usize; 3]> + '_ {
+        mesh.indices.chunks_exact(3).map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+    }
+
+    #[test]
+    fn shapes_have_the_documented_sizes() {
+        let sizes = [
+            ("cube", cube(1.0), 24, 36),
+            ("quad", quad(1.0, 2.0), 4, 6),
+            ("plane", plane(1.0, 1.0, 4), 5 * 5, 6 * 4 * 4),
+            ("uv_sphere", uv_sphere(1.0, 12, 7), (7 + 1) * (12 + 1), 6 * 12 * (7 - 1)),
+            ("cylinder", cylinder(1.0, 1.0, 10), 2 * 11 + 2 * 12, 12 * 10),
+            ("cone", cone(1.0, 1.0, 9), 2 * 10 + 11, 6 * 9),
+            ("capsule", capsule(1.0, 1.0, 8, 3), 2 * 4 * 9, 12 * 8 * 3),
+            ("torus", torus(2.0, 0.5, 16, 8), 9 * 17, 6 * 16 * 8),
+        ];
+        for (name, mesh, vertices, indices) in sizes {
+            assert_eq!(mesh.vertices.len(), vertices, "{name}");
+            assert_eq!(mesh.indices.len(), indices, "{name}");
+            for attribute in [mesh.normals.len(), mesh.tex_coords.len(), mesh.tangents.len()] {
+                assert_eq!(attribute, vertices, "{name}");
+            }
+        }
+        assert_eq!(icosphere(1.0, 2).indices.len(), 20 * 16 * 3);
+    }
+
+    #[test]
+    fn flat_shapes_face_their_axis() {
+        assert!(quad(1.0, 1.0).normals.iter().all(|n| *n == Vector3::unit_z()));
+        let plane = plane(2.0, 2.0, 3);
+        assert!(plane.normals.iter().all(|n| *n == Vector3::unit_y()));
+        for [a, b, c] in triangles(&plane) {
+            let (a, b, c) = (plane.vertices[a], plane.vertices[b], plane.vertices[c]);
+            assert!((b - a).cross(c - a).dot(Vector3::unit_y()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_length_and_point_outwards() {
+        for (name, mesh, center) in shapes() {
+            for (position, normal) in mesh.vertices.iter().zip(&mesh.normals) {
+                assert!((normal.magnitude() - 1.0).abs() < 1e-5, "{name}: {normal:?}");
+                assert!(normal.dot(position - center(*position)) > 0.0, "{name}: {normal:?} at {position:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_wind_counter_clockwise_from_outside() {
+        for (name, mesh, _) in shapes() {
+            for [a, b, c] in triangles(&mesh) {
+                let face = (mesh.vertices[b] - mesh.vertices[a]).cross(mesh.vertices[c] - mesh.vertices[a]);
+                let normal = mesh.normals[a] + mesh.normals[b] + mesh.normals[c];
+                assert!(face.magnitude() > 1e-7, "{name} has a degenerate triangle");
+                assert!(face.dot(normal) > 0.0, "{name}: triangle {a} {b} {c} is wound clockwise");
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_texture_coordinates() {
+        for (name, mesh, _) in shapes() {
+            for [a, b, c] in triangles(&mesh) {
+                let (e1, e2) = (mesh.vertices[b] - mesh.vertices[a], mesh.vertices[c] - mesh.vertices[a]);
+                let (d1, d2) = (mesh.tex_coords[b] - mesh.tex_coords[a], mesh.tex_coords[c] - mesh.tex_coords[a]);
+                let determinant = d1.x.mul_add(d2.y, -(d2.x * d1.y));
+                // The directions of increasing u and v across the triangle.
+                let u = (e1 * d2.y - e2 * d1.y) / determinant;
+                let v = (e2 * d1.x - e1 * d2.x) / determinant;
+                for i in [a, b, c] {
+                    let (normal, tangent) = (mesh.normals[i], mesh.tangents[i]);
+                    assert!((tangent.truncate().magnitude() - 1.0).abs() < 1e-5, "{name}: {tangent:?}");
+                    assert!(tangent.truncate().dot(normal).abs() < 1e-5, "{name}: {tangent:?}");
+                    assert!(tangent.truncate().dot(u) > 0.0, "{name}: tangent {tangent:?} against u {u:?}");
+                    let bitangent = normal.cross(tangent.truncate()) * tangent.w;
+                    assert!(bitangent.dot(v) > 0.0, "{name}: w of {tangent:?} against v {v:?}");
+                }
+            }
+        }
+    }
+}
@@ -1,22 +1,47 @@
+use crate::animation::{
+    Animation, AnimationPlayer, Channel, ChannelValues, Interpolation, Pose, Sampler, Skin, JOINTS_BINDING, MAX_JOINTS,
+};
+use crate::bounds::{Aabb, Bounds, Frustum, Obb, Ray};
+use crate::bvh::Bvh;
 use crate::derive_transformable;
+use crate::lighting::{Light, LightKind};
+use crate::lod::{LodLevel, LodRenderable};
+use crate::normals::{self, DEFAULT_CREASE_ANGLE};
+use crate::optimize::{optimize_vertex_fetch, MeshOptimizer};
+use crate::ply::PlyMesh;
+use crate::scene::{NodeHandle, SceneGraph};
+use crate::simplify;
 use crate::glutil::{BufferObject, GLBuffer, GLObject, Vaa, VertexArrayObject};
-use crate::shader::{FromVertex, NarrowingMaterial, SetValue, Shader, ShaderManager, ShaderPtr};
-use crate::transformation::{Transform, Transformable};
+use crate::stl::{StlFormat, StlMesh};
+use crate::shader::{
+    parse_mtl_extensions, MtlExtensions, NarrowingMaterial, SetValue, Shader, ShaderManager, ShaderPtr, NORMAL_MAP,
+};
+use crate::transformation::{Camera, CameraPreset, Projection, Transform, Transformable};
 use crate::util::find_gl_error;
+use crate::validate::{self, RepairOptions, ValidationReport};
+use crate::vertex::{AttributeValues, CustomAttribute, VertexAttribute, VertexLayout};
 use cgmath::num_traits::AsPrimitive;
-use cgmath::{Vector2, Vector3};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector2, Vector3};
 use gl::types::{GLenum, GLuint};
-use gl::{ARRAY_BUFFER, FLOAT, STATIC_DRAW, TRIANGLES, TRIANGLE_FAN, UNSIGNED_INT};
+use gl::{ARRAY_BUFFER, DYNAMIC_DRAW, FLOAT, POINTS, STATIC_DRAW, TRIANGLES, TRIANGLE_FAN, UNIFORM_BUFFER, UNSIGNED_INT};
 use itertools::Itertools;
-use obj::raw::{parse_mtl, parse_obj};
-use obj::{FromRawVertex, TexturedVertex};
+use log::warn;
+use obj::raw::material::Material;
+use obj::raw::object::{Group, Polygon};
+use obj::raw::{parse_mtl, parse_obj, RawObj};
 use std::any::Any;
+use std::cell::{Cell, OnceCell};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hasher};
 use std::error::Error;
 use std::ffi::{c_float, c_uint};
-use std::fs::File;
-use std::io::BufReader;
+use std::ops::AddAssign;
+use std::fs::{read_to_string, File};
+use std::io::{BufReader, BufWriter, Write};
 use std::mem::size_of;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::ptr::null;
 
 pub trait Render: Transformable {
@@ -27,6 +52,100 @@ pub trait Render: Transformable {
     fn is(&self) -> bool;
     fn set_is(&mut self, val: bool);
     fn as_any_mut(&mut self) -> &mut dyn Any;
+    /// Advances animations to the engine time `time`, in seconds. Called once per frame before rendering.
+    fn animate(&mut self, _time: f64) {}
+    /// Chooses what to draw for the view of `camera`, e.g. a level of detail. Called once per
+    /// frame before rendering.
+    fn prepare(&mut self, _camera: &Camera) {}
+    /// The world space bounds of the object with its current transform, or `None` if it has no
+    /// extent to speak of.
+    fn bounds(&mut self) -> Option<Bounds> {
+        None
+    }
+    /// A value that changes whenever `bounds` may have, e.g. a hash of the object's transforms,
+    /// so the engine only asks for the bounds of objects that moved. `None`, the default, makes it
+    /// ask every frame.
+    fn bounds_key(&self) -> Option<u64> {
+        None
+    }
+    /// Whether the object may be drawn outside its `bounds`, e.g. by skinning or morph targets, so
+    /// they can't be used to cull it.
+    fn deforms(&self) -> bool {
+        false
+    }
+    /// Whether the object is placed straight in clip space, e.g. 2D overlays drawn by shaders
+    /// without the camera's matrices, so the camera's frustum doesn't apply to it.
+    fn screen_space(&self) -> bool {
+        false
+    }
+    /// Decides what to draw this frame given the camera's `frustum`, which `render` then respects.
+    /// Called once per frame before rendering; objects that count nothing as drawn aren't rendered.
+    ///
+    /// By default, objects are culled when their `bounds` are outside the frustum, unless the
+    /// object `deforms` or is in `screen_space`.
+    fn cull(&mut self, frustum: &Frustum) -> CullStats {
+        if self.screen_space()
+            || self.deforms()
+            || self.bounds().is_none_or(|bounds| frustum.intersects(&bounds))
+        {
+            CullStats { drawn: 1, culled: 0 }
+        } else {
+            CullStats { drawn: 0, culled: 1 }
+        }
+    }
+    /// The nearest point where the world space `ray` hits the object's triangles, or `None` if it
+    /// misses, isn't drawn as triangles or is in `screen_space`.
+    ///
+    /// Skinned and morphing meshes are tested in their base shape, not the pose or blend they are
+    /// drawn with, so hits on them can be off where the shape is deformed.
+    fn raycast(&mut self, _ray: &Ray) -> Option<RayHit> {
+        None
+    }
+}
+
+/// Where a ray hit an object; see `Render::raycast`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// Distance along the ray.
+    pub distance: f32,
+    pub position: Vector3<f32>,
+    /// The index of the triangle in the mesh's triangle list.
+    pub triangle: usize,
+    /// The weights of the triangle's corners at the hit point, e.g. to interpolate attributes.
+    pub barycentric: Vector3<f32>,
+    /// The renderable of a group or the instance of an instanced object that was hit, otherwise 0.
+    pub part: usize,
+}
+
+/// How many objects frustum culling kept and skipped. Objects made of several parts, such as
+/// instances or the renderables of a group, count each part.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+impl AddAssign for CullStats {
+    fn add_assign(&mut self, other: Self) {
+        self.drawn += other.drawn;
+        self.culled += other.culled;
+    }
+}
+
+/// Feeds the bits of `values` to `hasher`, for `Render::bounds_key`.
+pub(crate) fn hash_floats(hasher: &mut impl Hasher, values: impl IntoIterator<Item = f32>) {
+    for value in values {
+        hasher.write_u32(value.to_bits());
+    }
+}
+
+/// Feeds what places an object's bounds in the world to `hasher`: its `transform`, its `parent`
+/// matrix and its model space box.
+pub(crate) fn hash_placement(hasher: &mut impl Hasher, transform: &Transform, parent: &Matrix4<f32>, local: &Aabb) {
+    let vectors = [transform.position, transform.rotation, transform.scale, local.min, local.max];
+    hash_floats(hasher, vectors.iter().flat_map(|v| [v.x, v.y, v.z]));
+    let parent: &[f32; 16] = parent.as_ref();
+    hash_floats(hasher, parent.iter().copied());
 }
 
 impl<T: AsPrimitive<f32> + Copy> Transformable<T> for InstancedObject {
@@ -77,7 +196,8 @@ impl<T: AsPrimitive<f32> + Copy> Transformable<T> for InstancedObject {
 
 impl Render for InstancedObject {
     fn render(&mut self, _: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
-        if self.transforms.is_empty() {
+        let count = self.visible.as_ref().map_or(self.transforms.len(), Vec::len);
+        if count == 0 {
             return Ok(());
         }
         self.buffer_data()?;
@@ -93,7 +213,7 @@ impl Render for InstancedObject {
                 i32::try_from(self.mesh.vertices.len())?,
                 UNSIGNED_INT,
                 null(),
-                i32::try_from(self.transforms.len())?,
+                i32::try_from(count)?,
             );
             self.mesh.vertex_array.unbind();
         }
@@ -101,6 +221,57 @@ impl Render for InstancedObject {
         Ok(())
     }
 
+    /// The bounds around every instance.
+    fn bounds(&mut self) -> Option<Bounds> {
+        let local = self.mesh.bounds();
+        let bounds = self
+            .transforms
+            .iter()
+            .fold(Bounds::EMPTY, |bounds, t| bounds.union(&local.transformed(&t.mat())));
+        (!bounds.is_empty()).then_some(bounds)
+    }
+
+    fn bounds_key(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        let local = self.mesh.bounds().aabb;
+        for transform in &self.transforms {
+            hash_placement(&mut hasher, transform, &Matrix4::identity(), &local);
+        }
+        Some(hasher.finish())
+    }
+
+    fn screen_space(&self) -> bool {
+        self.screen_space
+    }
+
+    /// Culls each instance on its own, unless the object is in screen space.
+    fn cull(&mut self, frustum: &Frustum) -> CullStats {
+        if self.screen_space {
+            self.visible = None;
+            return CullStats { drawn: self.transforms.len(), culled: 0 };
+        }
+        let local = self.mesh.bounds();
+        let visible: Vec<usize> = (0..self.transforms.len())
+            .filter(|&i| frustum.intersects(&local.transformed(&self.transforms[i].mat())))
+            .collect();
+        let stats = CullStats {
+            drawn: visible.len(),
+            culled: self.transforms.len() - visible.len(),
+        };
+        self.visible = Some(visible);
+        stats
+    }
+
+    /// Hits any instance, not only the visible ones.
+    fn raycast(&mut self, ray: &Ray) -> Option<RayHit> {
+        if self.draw_type != TRIANGLES || self.screen_space {
+            return None;
+        }
+        nearest_hit(self.transforms.iter().enumerate().filter_map(|(i, t)| {
+            self.mesh.raycast_world(ray, &t.mat()).map(|hit| RayHit { part: i, ..hit })
+        }))
+    }
+
     fn is(&self) -> bool {
         self.is
     }
@@ -117,9 +288,13 @@ pub struct InstancedObject {
     mesh: Mesh,
     transforms: Vec<Transform>,
     colors: Vec<[f32; 4]>,
+    /// The instances that passed the last `cull`, or `None` to draw all of them.
+    visible: Option<Vec<usize>>,
     shader: ShaderPtr,
     is: bool,
     draw_type: GLenum,
+    /// Whether the instances are placed in clip space; see `Render::screen_space`.
+    screen_space: bool,
 }
 impl InstancedObject {
     /// # Panics
@@ -137,8 +312,10 @@ impl InstancedObject {
             shader: shader.clone(),
             transforms,
             colors,
+            visible: None,
             is: true,
             draw_type: TRIANGLE_FAN,
+            screen_space: false,
         };
         ret.mesh
             .vertex_array
@@ -188,36 +365,89 @@ impl InstancedObject {
         ret
     }
 
+    /// Marks the instances as placed in clip space, so they are never culled; see
+    /// `Render::screen_space`.
+    pub const fn set_screen_space(&mut self, screen_space: bool) {
+        self.screen_space = screen_space;
+    }
+
     /// # Errors
     /// If the data cannot be buffered.
     fn buffer_data(&mut self) -> Result<(), Box<dyn Error>> {
+        // Instances without a color of their own are white, so colors stay aligned with transforms.
+        let color = |i: usize| self.colors.get(i).copied().unwrap_or([1.0; 4]);
         // Buffer transform matrices
-        let mats = self.transforms.iter().map(Transform::mat).collect_vec();
+        let (mats, colors) = match &self.visible {
+            Some(visible) => (
+                visible.iter().map(|&i| self.transforms[i].mat()).collect_vec(),
+                visible.iter().map(|&i| color(i)).collect_vec(),
+            ),
+            None => (
+                self.transforms.iter().map(Transform::mat).collect_vec(),
+                (0..self.transforms.len()).map(color).collect_vec(),
+            ),
+        };
         self.mesh.vertex_array.vbos[1]
             .buffer_data(mats.as_slice(), STATIC_DRAW)
             .map_err(|_| "Failed to buffer transform data")?;
 
         // Buffer colors
         self.mesh.vertex_array.vbos[2]
-            .buffer_data(self.colors.as_slice(), STATIC_DRAW)
+            .buffer_data(colors.as_slice(), STATIC_DRAW)
             .map_err(|_| "Failed to buffer color data")?;
         Ok(())
     }
     pub fn set_data(&mut self, transforms: Vec<Transform>, colors: Vec<[f32; 4]>) {
         self.transforms = transforms;
         self.colors = colors;
+        self.visible = None;
     }
 
     pub const fn set_draw_type(&mut self, draw_type: GLenum) {
         self.draw_type = draw_type;
     }
+
+    #[must_use]
+    pub const fn draw_type(&self) -> GLenum {
+        self.draw_type
+    }
+
+    #[must_use]
+    pub const fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    #[must_use]
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+}
+/// Per-vertex offsets of a blend shape, added to the base mesh scaled by the target's weight.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub positions: Vec<Vector3<f32>>,
+    pub normals: Option<Vec<Vector3<f32>>>,
 }
+
 pub struct Mesh {
     pub vertices: Vec<Vector3<f32>>,
     pub indices: Vec<u32>,
     pub vertex_array: VertexArrayObject,
-    pub tex_coords: Option<Vec<Vector2<f32>>>, // TODO: use general vertex attribs or some type of builder instead of explicitly supporting only these two.
+    pub tex_coords: Option<Vec<Vector2<f32>>>,
     pub normals: Option<Vec<Vector3<f32>>>,
+    /// Skinning joint indices; only used together with `weights`.
+    pub joints: Option<Vec<[u16; 4]>>,
+    pub weights: Option<Vec<[f32; 4]>>,
+    /// RGBA vertex colors, multiplied with the diffuse color by shaders compiled with `VERTEX_COLORS`.
+    pub colors: Option<Vec<[f32; 4]>>,
+    /// Blend shapes applied with `set_morph_weights`; `vertices` and `normals` stay the base shape.
+    pub morph_targets: Vec<MorphTarget>,
+    /// Attributes without a field of their own, interleaved after the standard ones.
+    pub custom_attributes: Vec<CustomAttribute>,
+    /// Bounds of `vertices`, computed on demand and when the mesh is initialized.
+    bounds: Cell<Option<Bounds>>,
+    /// Tree over the triangles, built when first needed.
+    triangle_bvh: OnceCell<Bvh>,
 }
 impl Mesh {
     #[must_use] pub fn new(
@@ -231,78 +461,421 @@ impl Mesh {
             indices,
             tex_coords,
             normals,
+            joints: None,
+            weights: None,
+            colors: None,
+            morph_targets: Vec::new(),
+            custom_attributes: Vec::new(),
             vertex_array: VertexArrayObject::new(),
+            bounds: Cell::new(None),
+            triangle_bvh: OnceCell::new(),
         }
     }
     /// Initializes the mesh data by generating the vertex array object and buffering the vertex data.
     /// # Errors
-    /// If the OpenGL function fails, it will return a `Box<dyn Error>`.
+    /// If a custom attribute has the wrong number of values, the layout is invalid (see
+    /// `VertexLayout::check`) or the OpenGL function fails, it will return a `Box<dyn Error>`.
     pub fn init(&mut self) -> Result<(), Box<dyn Error>> {
-        let vertex_data = self.build_vertex_data();
-        let mut structure = vec![Vaa::new(FLOAT, 3, 0)];
-        if self.normals.is_some() {
-            structure.push(Vaa::new(FLOAT, 3, 0));
-        }
-        if self.tex_coords.is_some() {
-            structure.push(Vaa::new(FLOAT, 3, 0));
+        for custom in &self.custom_attributes {
+            if custom.values.len() != self.vertices.len() * custom.attribute.count as usize {
+                return Err(format!("Attribute {} needs {} components per vertex", custom.attribute.name, custom.attribute.count).into());
+            }
         }
+        self.layout().check()?;
+        self.bounds.set(Some(Bounds::from_points(&self.vertices)));
+        self.triangle_bvh.take();
+        let vertex_data = self.build_vertex_data();
         self.vertex_array.generate()?;
-        self.vertex_array.vbos[0].buffer_data(vertex_data.as_slice(), STATIC_DRAW)?;
+        let usage = if self.morph_targets.is_empty() { STATIC_DRAW } else { DYNAMIC_DRAW };
+        self.vertex_array.vbos[0].buffer_data(vertex_data.as_slice(), usage)?;
         self.vertex_array
             .ebo
             .buffer_data(self.indices.as_slice(), STATIC_DRAW)?;
-        self.vertex_array.configure(structure)?;
+        self.vertex_array.configure(self.layout().vaas(0))?;
+        Ok(())
+    }
+
+    /// The layout of the vertex buffer: the standard attributes the mesh has, then the custom ones.
+    #[must_use]
+    pub fn layout(&self) -> VertexLayout {
+        let mut attributes = vec![VertexAttribute::POSITION];
+        if self.normals.is_some() {
+            attributes.push(VertexAttribute::NORMAL);
+        }
+        if self.tex_coords.is_some() {
+            attributes.push(VertexAttribute::TEX_COORD);
+        }
+        if self.joints.is_some() && self.weights.is_some() {
+            attributes.push(VertexAttribute::JOINTS);
+            attributes.push(VertexAttribute::WEIGHTS);
+        }
+        if self.colors.is_some() {
+            attributes.push(VertexAttribute::COLOR);
+        }
+        attributes.extend(self.custom_attributes.iter().map(|c| c.attribute.clone()));
+        VertexLayout::new(attributes)
+    }
+
+    /// Blends the morph targets into the base shape with `weights` and re-uploads the vertex data.
+    ///
+    /// Missing weights count as zero.
+    /// # Errors
+    /// If the OpenGL function fails, it will return a `Box<dyn Error>`.
+    pub fn set_morph_weights(&mut self, weights: &[f32]) -> Result<(), Box<dyn Error>> {
+        let mut vertices = self.vertices.clone();
+        let mut normals = self.normals.clone();
+        for (target, weight) in self.morph_targets.iter().zip(weights) {
+            if *weight == 0.0 {
+                continue;
+            }
+            for (v, d) in vertices.iter_mut().zip(&target.positions) {
+                *v += d * *weight;
+            }
+            if let (Some(normals), Some(deltas)) = (normals.as_mut(), &target.normals) {
+                for (n, d) in normals.iter_mut().zip(deltas) {
+                    *n += d * *weight;
+                }
+            }
+        }
+        if let Some(normals) = normals.as_mut() {
+            for n in normals.iter_mut().filter(|n| n.magnitude2() > 0.0) {
+                *n = n.normalize();
+            }
+        }
+        let vertex_data = self.build_vertex_data_from(&vertices, normals.as_deref());
+        self.vertex_array.vbos[0].buffer_data(vertex_data.as_slice(), DYNAMIC_DRAW)?;
+        Ok(())
+    }
+
+    /// Replaces the normals with face normals for faceted shading. The indices must be a
+    /// triangle list, and the mesh must be initialized afterwards.
+    pub fn compute_flat_normals(&mut self) {
+        let split = normals::flat_normals(&self.vertices, &self.indices);
+        self.remap_vertices(&split.sources, split.indices);
+        self.normals = Some(split.values);
+    }
+
+    /// Replaces the normals with angle-weighted smooth normals, keeping edges sharper than
+    /// `crease_angle` radians hard; see `normals::smooth_normals`. The indices must be a triangle
+    /// list, and the mesh must be initialized afterwards.
+    pub fn compute_smooth_normals(&mut self, crease_angle: f32) {
+        let split = normals::smooth_normals(&self.vertices, &self.indices, crease_angle);
+        self.remap_vertices(&split.sources, split.indices);
+        self.normals = Some(split.values);
+    }
+
+    /// Computes tangents for normal mapping into an `aTangent` attribute, replacing any
+    /// existing one; see `normals::tangents`. The indices must be a triangle list, and the mesh
+    /// must be initialized afterwards.
+    /// # Errors
+    /// If the mesh has no normals or texture coordinates.
+    pub fn compute_tangents(&mut self) -> Result<(), String> {
+        let (Some(normals), Some(tex_coords)) = (&self.normals, &self.tex_coords) else {
+            return Err("Tangents need normals and texture coordinates".to_owned());
+        };
+        let split = normals::tangents(&self.vertices, normals, tex_coords, &self.indices);
+        self.custom_attributes.retain(|c| c.attribute.location != VertexAttribute::TANGENT.location);
+        self.remap_vertices(&split.sources, split.indices);
+        self.custom_attributes.push(CustomAttribute {
+            attribute: VertexAttribute::TANGENT,
+            values: AttributeValues::F32(split.values.iter().flat_map(|t| [t.x, t.y, t.z, t.w]).collect()),
+        });
+        Ok(())
+    }
+
+    /// Whether the mesh has an `aTangent` attribute.
+    #[must_use]
+    pub fn has_tangents(&self) -> bool {
+        self.custom_attributes.iter().any(|c| c.attribute.location == VertexAttribute::TANGENT.location)
+    }
+
+    /// The model space bounds of the base shape, ignoring morph targets and skinning.
+    ///
+    /// They are cached when first needed and when the mesh is initialized; after changing the
+    /// vertices or indices of an initialized mesh, call `invalidate_bounds`.
+    pub fn bounds(&self) -> Bounds {
+        if let Some(bounds) = self.bounds.get() {
+            return bounds;
+        }
+        let bounds = Bounds::from_points(&self.vertices);
+        self.bounds.set(Some(bounds));
+        bounds
+    }
+
+    /// Drops the cached bounds and triangle tree so `bounds` and `triangle_bvh` recompute them.
+    pub fn invalidate_bounds(&mut self) {
+        self.bounds.set(None);
+        self.triangle_bvh.take();
+    }
+
+    /// A tree over the triangles of the base shape, numbered by their position in `indices`,
+    /// which must be a triangle list. It is cached like `bounds`.
+    pub fn triangle_bvh(&self) -> &Bvh {
+        self.triangle_bvh.get_or_init(|| Bvh::from_triangles(&self.vertices, &self.indices))
+    }
+
+    /// The first triangle `ray` hits in model space, with the distance in multiples of the ray's
+    /// direction, which need not be normalized.
+    #[must_use]
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        let corners = |triangle: usize| {
+            let corners = &self.indices[triangle * 3..triangle * 3 + 3];
+            [0, 1, 2].map(|i| self.vertices[corners[i] as usize])
+        };
+        let (triangle, _) = self
+            .triangle_bvh()
+            .raycast(ray, f32::INFINITY, |triangle| ray.intersect_triangle(corners(triangle)))?;
+        let (distance, barycentric) = ray.intersect_triangle_barycentric(corners(triangle))?;
+        Some(RayHit {
+            distance,
+            position: ray.at(distance),
+            triangle,
+            barycentric,
+            part: 0,
+        })
+    }
+
+    /// Like `raycast`, for a world space `ray` and the mesh placed by `model`.
+    #[must_use]
+    pub fn raycast_world(&self, ray: &Ray, model: &Matrix4<f32>) -> Option<RayHit> {
+        // Transforming the ray keeps distances along it, so only the position changes.
+        let hit = self.raycast(&ray.transformed(&model.invert()?))?;
+        Some(RayHit {
+            position: ray.at(hit.distance),
+            ..hit
+        })
+    }
+
+    /// Checks the mesh for out-of-range indices, degenerate triangles, non-manifold edges,
+    /// non-finite values and attributes of the wrong length. The indices must be a triangle list.
+    #[must_use]
+    pub fn validate(&self) -> ValidationReport {
+        validate::validate(self)
+    }
+
+    /// Repairs the problems `options` enables and returns the ones left; see `RepairOptions`.
+    /// The mesh must be initialized afterwards.
+    pub fn repair(&mut self, options: &RepairOptions) -> ValidationReport {
+        options.apply(self)
+    }
+
+    /// Validates a mesh read from `source`, repairing it and logging what was wrong if needed.
+    /// # Errors
+    /// The `ValidationReport` of the issues left after repairing, if any of them is an error.
+    fn check_loaded(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        let report = self.validate();
+        if report.is_empty() {
+            return Ok(());
+        }
+        let remaining = self.repair(&RepairOptions::default());
+        if remaining.has_errors() {
+            warn!("{source} has invalid mesh data: {remaining}");
+            return Err(Box::new(remaining));
+        }
+        warn!("{source}: {report}; repaired, {} remain", remaining.issues.len());
+        Ok(())
+    }
+
+    /// A copy of the mesh reduced to at most `target_triangles` triangles, or fewer triangles
+    /// moved by at most `target_error`; see `simplify::simplify`. Unused vertices are dropped, and
+    /// the new mesh must be initialized. The indices must be a triangle list.
+    ///
+    /// Returns the mesh and the error the simplification made, in model units.
+    #[must_use]
+    #[allow(clippy::float_cmp)]
+    pub fn simplified(&self, target_triangles: usize, target_error: f32) -> (Self, f32) {
+        // Corners may only switch to vertices that differ in shading, so flat shaded meshes
+        // simplify while texture seams and color borders stay in place.
+        let compatible = |a: u32, b: u32| {
+            let (a, b) = (a as usize, b as usize);
+            self.tex_coords.as_ref().is_none_or(|v| v[a] == v[b])
+                && self.colors.as_ref().is_none_or(|v| v[a] == v[b])
+                && self.joints.as_ref().is_none_or(|v| v[a] == v[b])
+                && self.weights.as_ref().is_none_or(|v| v[a] == v[b])
+        };
+        let (indices, error) = simplify::simplify(&self.vertices, &self.indices, target_triangles, target_error, compatible);
+        let mut mesh = Self::new(self.vertices.clone(), Vec::new(), self.normals.clone(), self.tex_coords.clone());
+        mesh.joints.clone_from(&self.joints);
+        mesh.weights.clone_from(&self.weights);
+        mesh.colors.clone_from(&self.colors);
+        mesh.morph_targets.clone_from(&self.morph_targets);
+        mesh.custom_attributes.clone_from(&self.custom_attributes);
+        let (sources, indices) = optimize_vertex_fetch(&indices, self.vertices.len());
+        mesh.remap_vertices(&sources, indices);
+        (mesh, error)
+    }
+
+    /// A copy of the mesh drawing from the same vertex buffers, which it must not upload to, e.g.
+    /// to place an initialized mesh several times without storing it on the GPU again.
+    pub(crate) fn share(&self) -> Self {
+        let mut mesh = Self::new(self.vertices.clone(), self.indices.clone(), self.normals.clone(), self.tex_coords.clone());
+        mesh.joints.clone_from(&self.joints);
+        mesh.weights.clone_from(&self.weights);
+        mesh.colors.clone_from(&self.colors);
+        mesh.morph_targets.clone_from(&self.morph_targets);
+        mesh.custom_attributes.clone_from(&self.custom_attributes);
+        mesh.vertex_array = self.vertex_array.share();
+        mesh.bounds.set(self.bounds.get());
+        mesh
+    }
+
+    /// Rebuilds the vertices so vertex `i` has all attributes of vertex `sources[i]`.
+    pub(crate) fn remap_vertices(&mut self, sources: &[u32], indices: Vec<u32>) {
+        fn pick<T: Copy>(values: &[T], sources: &[u32]) -> Vec<T> {
+            sources.iter().map(|&s| values[s as usize]).collect()
+        }
+        self.vertices = pick(&self.vertices, sources);
+        self.indices = indices;
+        self.invalidate_bounds();
+        if let Some(values) = &mut self.normals {
+            *values = pick(values, sources);
+        }
+        if let Some(values) = &mut self.tex_coords {
+            *values = pick(values, sources);
+        }
+        if let Some(values) = &mut self.joints {
+            *values = pick(values, sources);
+        }
+        for values in [&mut self.weights, &mut self.colors].into_iter().flatten() {
+            *values = pick(values, sources);
+        }
+        for target in &mut self.morph_targets {
+            target.positions = pick(&target.positions, sources);
+            if let Some(normals) = &mut target.normals {
+                *normals = pick(normals, sources);
+            }
+        }
+        for custom in &mut self.custom_attributes {
+            custom.values = custom.values.remap(custom.attribute.count as usize, sources);
+        }
+    }
+
+    /// Writes the mesh to an STL file. The indices must be a triangle list.
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save_stl(&self, path: &str, format: StlFormat) -> Result<(), Box<dyn Error>> {
+        StlMesh {
+            positions: self.vertices.clone(),
+            normals: self.normals.clone().unwrap_or_default(),
+            indices: self.indices.clone(),
+        }
+        .save(path, format)
+    }
+
+    /// Writes the mesh to an OBJ file without materials. The indices must be a triangle list.
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn write_obj(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| format!("Couldn't create file {path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+        self.write_obj_elements(&mut writer, Matrix4::identity(), [0; 3])?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the vertices moved by `matrix`, then the faces. `offsets` are the numbers of
+    /// positions, texture coordinates and normals written before, as OBJ indices are global.
+    fn write_obj_elements(&self, writer: &mut impl Write, matrix: Matrix4<f32>, offsets: [usize; 3]) -> Result<(), Box<dyn Error>> {
+        for v in &self.vertices {
+            let v = (matrix * v.extend(1.0)).truncate();
+            writeln!(writer, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        if let Some(tex_coords) = &self.tex_coords {
+            for t in tex_coords {
+                writeln!(writer, "vt {} {}", t.x, t.y)?;
+            }
+        }
+        if let Some(normals) = &self.normals {
+            let normal_matrix = Matrix3::from_cols(matrix.x.truncate(), matrix.y.truncate(), matrix.z.truncate())
+                .invert()
+                .unwrap_or_else(Matrix3::identity)
+                .transpose();
+            for n in normals {
+                let n = normal_matrix * n;
+                let n = if n.magnitude2() > 0.0 { n.normalize() } else { n };
+                writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+            }
+        }
+        let (has_tex, has_normals) = (self.tex_coords.is_some(), self.normals.is_some());
+        for face in self.indices.chunks_exact(3) {
+            write!(writer, "f")?;
+            for i in face.iter().map(|i| *i as usize + 1) {
+                let (v, t, n) = (i + offsets[0], i + offsets[1], i + offsets[2]);
+                match (has_tex, has_normals) {
+                    (true, true) => write!(writer, " {v}/{t}/{n}")?,
+                    (true, false) => write!(writer, " {v}/{t}")?,
+                    (false, true) => write!(writer, " {v}//{n}")?,
+                    (false, false) => write!(writer, " {v}")?,
+                }
+            }
+            writeln!(writer)?;
+        }
         Ok(())
     }
 
-    fn build_vertex_data(&self) -> Vec<f32> {
-        let mut vertex_data = Vec::new();
-        for i in 0..self.vertices.len() {
-            vertex_data.push(self.vertices[i].x);
-            vertex_data.push(self.vertices[i].y);
-            vertex_data.push(self.vertices[i].z);
-            if let Some(d) = &self.normals {
-                vertex_data.push(d[i].x);
-                vertex_data.push(d[i].y);
-                vertex_data.push(d[i].z);
+    fn build_vertex_data(&self) -> Vec<u8> {
+        self.build_vertex_data_from(&self.vertices, self.normals.as_deref())
+    }
+
+    /// Interleaves the vertex data in the order of `layout`, with `vertices` and `normals` in
+    /// place of the base shape.
+    fn build_vertex_data_from(&self, vertices: &[Vector3<f32>], normals: Option<&[Vector3<f32>]>) -> Vec<u8> {
+        let mut vertex_data = Vec::with_capacity(vertices.len() * self.layout().stride());
+        for i in 0..vertices.len() {
+            push_floats(&mut vertex_data, &[vertices[i].x, vertices[i].y, vertices[i].z]);
+            if let Some(d) = normals {
+                push_floats(&mut vertex_data, &[d[i].x, d[i].y, d[i].z]);
             }
             if let Some(d) = &self.tex_coords {
-                vertex_data.push(d[i].x);
-                vertex_data.push(d[i].y);
-                vertex_data.push(0.0);
+                push_floats(&mut vertex_data, &[d[i].x, d[i].y]);
+            }
+            if let (Some(joints), Some(weights)) = (&self.joints, &self.weights) {
+                push_floats(&mut vertex_data, &joints[i].map(f32::from));
+                push_floats(&mut vertex_data, &weights[i]);
+            }
+            if let Some(colors) = &self.colors {
+                push_floats(&mut vertex_data, &colors[i]);
+            }
+            for custom in &self.custom_attributes {
+                custom.write_vertex(i, &mut vertex_data);
             }
         }
         vertex_data
     }
 }
 
+/// The hit with the smallest distance.
+fn nearest_hit(hits: impl IntoIterator<Item = RayHit>) -> Option<RayHit> {
+    hits.into_iter().min_by(|a, b| a.distance.total_cmp(&b.distance))
+}
+
+fn push_floats(out: &mut Vec<u8>, values: &[f32]) {
+    out.extend_from_slice(bytemuck::cast_slice(values));
+}
+
 pub struct Renderable {
     pub mesh_data: Mesh,
     pub transform: Transform,
     pub shader: ShaderPtr,
     pub draw_type: GLenum,
+    /// Whether the mesh is placed in clip space, so it is never culled; see `Render::screen_space`.
+    pub screen_space: bool,
+    /// World matrix of the parent node, applied before `transform`.
+    parent: Matrix4<f32>,
+    /// Skinning matrices uploaded to `jointMatrices`, if the mesh is skinned.
+    joint_matrices: Option<Vec<Matrix4<f32>>>,
+    /// Uniform buffer for the `Joints` block, created on the first skinned render.
+    joint_buffer: Option<BufferObject>,
+    /// Morph target weights currently blended into the vertex buffer.
+    morph_weights: Vec<f32>,
+    /// The last world space bounds and the `bounds_key` they were computed for.
+    world_bounds: Option<(u64, Option<Bounds>)>,
     is: bool,
 }
 impl Renderable {
     /// Creates a new Renderable with the given vertices, indices, normals and shader.
     /// # Panics
-    /// If the mesh data cannot be initialized.
-    pub(crate) fn new_with_tex(
-        vertices: Vec<Vector3<f32>>,
-        indices: Vec<u32>,
-        normals: Vec<Vector3<f32>>,
-        tex_coords: Vec<Vector2<f32>>,
-        shader: &ShaderPtr,
-    ) -> Self {
-        let mut ret = Self::only_data(vertices, indices, Some(normals), shader);
-        ret.mesh_data.tex_coords = Some(tex_coords);
-        ret.mesh_data
-            .init()
-            .expect("Failed to initialize mesh data.");
-        ret
-    }
-    /// # Panics
-    /// If the mesh data cannot be initialized.
+    /// If the mesh data cannot be initialized or doesn't have the shader's inputs.
     pub fn new(
         vertices: Vec<Vector3<f32>>,
         indices: Vec<u32>,
@@ -310,11 +883,40 @@ impl Renderable {
         shader: &ShaderPtr,
     ) -> Self {
         let mut ret = Self::only_data(vertices, indices, normals, shader);
-        ret.mesh_data
-            .init()
-            .expect("Failed to initialize mesh data.");
+        ret.init_mesh().expect("Failed to initialize mesh data.");
         ret
     }
+    /// Creates a renderable drawing `mesh` as triangles, e.g. from `primitives::MeshData::into_mesh`.
+    /// # Errors
+    /// If the mesh data cannot be initialized or doesn't have the shader's inputs.
+    pub fn from_mesh(mesh: Mesh, shader: &ShaderPtr) -> Result<Self, Box<dyn Error>> {
+        let mut ret = Self::only_data(Vec::new(), Vec::new(), None, shader);
+        ret.mesh_data = mesh;
+        ret.init_mesh()?;
+        Ok(ret)
+    }
+    /// Creates a renderable drawing the vertex buffers of `mesh`, if it is initialized, instead of
+    /// uploading a copy; otherwise the copy is initialized.
+    /// # Errors
+    /// If the copy cannot be initialized or the mesh doesn't have the shader's inputs.
+    pub(crate) fn from_shared_mesh(mesh: &Mesh, shader: &ShaderPtr) -> Result<Self, Box<dyn Error>> {
+        let mut ret = Self::only_data(Vec::new(), Vec::new(), None, shader);
+        ret.mesh_data = mesh.share();
+        if mesh.vertex_array.is_generated() {
+            ret.mesh_data.layout().validate(&ret.shader.borrow())?;
+        } else {
+            ret.init_mesh()?;
+        }
+        Ok(ret)
+    }
+    /// Initializes the mesh and checks that its layout feeds every input of the shader.
+    /// # Errors
+    /// If the mesh cannot be initialized or its layout doesn't match the shader.
+    fn init_mesh(&mut self) -> Result<(), Box<dyn Error>> {
+        self.mesh_data.init()?;
+        self.mesh_data.layout().validate(&self.shader.borrow())?;
+        Ok(())
+    }
     fn only_data(
         vertices: Vec<Vector3<f32>>,
         indices: Vec<u32>,
@@ -326,161 +928,1222 @@ impl Renderable {
             shader: shader.clone(),
             transform: Transform::default(),
             draw_type: TRIANGLES,
+            screen_space: false,
+            parent: Matrix4::identity(),
+            joint_matrices: None,
+            joint_buffer: None,
+            morph_weights: Vec::new(),
+            world_bounds: None,
             is: true,
         }
     }
 
-    /// # Errors
-    /// If the object cannot be created from the given path or shader path.
-    pub fn from_obj(
-        path: &str,
-        shaderpath: &str,
-        manager: &mut ShaderManager,
-    ) -> Result<Self, Box<dyn Error>> {
-        let path_dir = Path::new(path).parent().ok_or("Invalid path")?;
-        let input = BufReader::new(File::open(path).map_err(|e| format!("Couldn't open file {path}: {e}"))?);
-        let obj = parse_obj(input).map_err(|_| "Couldn't parse obj!")?;
-        // let parsed_obj: Obj<TexturedVertex> = Obj::new(obj).expect("Jimbo jones the fourth");
-        let (vertices, indices) = FromRawVertex::<u32>::process(
-            obj.positions,
-            obj.normals,
-            obj.tex_coords.clone(),
-            obj.polygons,
-        )
-        .map_err(|_| "Couldn't process vertices")?;
-        let path_str = path_dir.to_str().ok_or("Invalid path")?;
-        let raw_mtl = parse_mtl(BufReader::new(
-            File::open((path_str.to_owned()) + "/" + &obj.material_libraries[0])
-                .map_err(|_| {
-                    format!(
-                        "Cannot find file {}",
-                        path_str.to_owned()
-                            + "/"
-                            + &obj.material_libraries[0]
-                    )
-                })?,
-        ))
-        .map_err(|_| "Couldn't parse mtl!")?;
-        let mat =
-            NarrowingMaterial::from_obj_mtl(&raw_mtl.materials.get("Material.001").ok_or("Couldn't get material")?.clone());
-        let new_shader = mat.with_path(shaderpath)?;
-
-        // let new_shader = Shader::load_from_path("shaders/comp_base_shader");
-        Ok(Self::new_with_tex(
-            vertices.iter().map(Vector3::from_vertex).collect(),
-            indices,
-            vertices.iter().map(Vector3::from_vertex).collect(),
-            vertices
-                .iter()
-                .map(|x: &TexturedVertex| Vector2::new(x.texture[0], x.texture[1]))
-                .collect(),
-            &manager.register(new_shader),
-        ))
+    /// Another renderable drawing the same GPU mesh with the same shader, at the same transform.
+    /// Morph weights aren't shared, since they are blended into the vertex buffer.
+    pub(crate) fn share(&self) -> Self {
+        Self {
+            mesh_data: self.mesh_data.share(),
+            transform: self.transform.clone(),
+            shader: self.shader.clone(),
+            draw_type: self.draw_type,
+            screen_space: self.screen_space,
+            parent: self.parent,
+            joint_matrices: None,
+            joint_buffer: None,
+            morph_weights: Vec::new(),
+            world_bounds: self.world_bounds,
+            is: self.is,
+        }
     }
-}
-impl Render for Renderable {
-    fn render(&mut self, shader_override: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
-        if !self.is {
+
+    /// Uploads the skinning matrices, if any, to the `Joints` uniform block.
+    fn bind_joint_matrices(&mut self) -> Result<(), Box<dyn Error>> {
+        let Some(joints) = &self.joint_matrices else {
             return Ok(());
+        };
+        let buffer = if let Some(buffer) = &mut self.joint_buffer {
+            buffer
+        } else {
+            let mut buffer = BufferObject::new(UNIFORM_BUFFER);
+            buffer.generate()?;
+            self.joint_buffer.insert(buffer)
+        };
+        // The whole block is uploaded, since drivers may read all of it.
+        let mut padded = joints.clone();
+        padded.resize(MAX_JOINTS, Matrix4::identity());
+        buffer.buffer_data(&padded, DYNAMIC_DRAW)?;
+        unsafe {
+            gl::BindBufferBase(UNIFORM_BUFFER, JOINTS_BINDING, buffer.id);
         }
-        let model = self.transform.mat();
-        let mut shader = shader_override
-            .as_ref()
-            .unwrap_or(&self.shader)
-            .borrow_mut();
-        shader.use_();
-        shader.update().expect("Shader failed to update.");
-        shader.set(model, "model").expect("Couldn't set shader");
+        Ok(())
+    }
 
-        unsafe {
-            // gl::BindVertexArray(self.mesh_data.vertex_array);
-            self.mesh_data.vertex_array.bind();
-            find_gl_error()?;
-            gl::DrawElements(
-                self.draw_type,
-                i32::try_from(self.mesh_data.indices.len() * size_of::<GLuint>())?,
-                UNSIGNED_INT,
-                null(),
-            );
-            self.mesh_data.vertex_array.unbind();
-            // gl::BindVertexArray(0); // Cleanup
+    /// Sets the skinning matrices for a mesh with joints and weights; see `Skin::joint_matrices`.
+    pub fn set_joint_matrices(&mut self, joint_matrices: Option<Vec<Matrix4<f32>>>) {
+        self.joint_matrices = joint_matrices;
+    }
+
+    /// Blends the mesh's morph targets with `weights`; the vertex data is only re-uploaded if they changed.
+    /// # Errors
+    /// If the vertex data cannot be uploaded.
+    pub fn set_morph_weights(&mut self, weights: &[f32]) -> Result<(), Box<dyn Error>> {
+        if self.mesh_data.morph_targets.is_empty() || self.morph_weights == weights {
+            return Ok(());
         }
-        Shader::clear_shader();
+        self.mesh_data.set_morph_weights(weights)?;
+        self.morph_weights = weights.to_vec();
         Ok(())
     }
 
-    fn is(&self) -> bool {
-        self.is
+    #[must_use]
+    pub fn morph_weights(&self) -> &[f32] {
+        &self.morph_weights
     }
 
-    fn set_is(&mut self, val: bool) {
-        self.is = val;
+    /// Sets the matrix of the parent node, e.g. from a `SceneGraph`.
+    pub const fn set_parent_matrix(&mut self, parent: Matrix4<f32>) {
+        self.parent = parent;
     }
 
-    fn as_any_mut(&mut self) -> &mut dyn Any {
-        self
+    /// The model matrix: the parent matrix followed by the local transform.
+    #[must_use]
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.parent * self.transform.mat()
     }
-}
-derive_transformable!(Renderable);
 
-pub struct RenderableGroup {
-    renderables: Vec<Renderable>,
-    is: bool,
-}
-impl RenderableGroup {
-    /// Creates a new `RenderableGroup` with the given renderables.
+    /// Hashes the transform, the parent matrix and the mesh's bounds; see `Render::bounds_key`.
+    fn placement_key(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        hash_placement(&mut hasher, &self.transform, &self.parent, &self.mesh_data.bounds().aabb);
+        hasher.finish()
+    }
+
+    /// The mesh's bounding box in world space, following its rotation; see `Render::bounds` for
+    /// the axis-aligned box.
+    #[must_use]
+    pub fn oriented_bounds(&self) -> Option<Obb> {
+        let bounds = self.mesh_data.bounds();
+        (!bounds.is_empty()).then(|| Obb::new(&bounds.aabb, &self.world_matrix()))
+    }
+
+    /// Creates a renderable from a PLY file.
+    ///
+    /// Files without faces are drawn as `POINTS`. Vertex colors are used when present. Missing
+    /// normals are computed for meshes and filled with zeros for point clouds; missing texture
+    /// coordinates are filled with zeros.
     /// # Errors
-    /// If the renderables cannot be created from the given path or shader path.
-    pub fn from_gltf(
+    /// If the file cannot be read or parsed, or the shader fails to compile.
+    pub fn from_ply(
         path: &str,
         shaderpath: &str,
-        shader_manager: &mut ShaderManager,
+        manager: &mut ShaderManager,
     ) -> Result<Self, Box<dyn Error>> {
-        let mut ancestors = Path::new(path).ancestors();
-        let mut base = "";
-        ancestors.next();
-        if let Some(root) = ancestors.next() {
-            base = root.to_str().ok_or("Should be a string.")?;
+        let ply = PlyMesh::open(path)?;
+        let count = ply.positions.len();
+        let defines: &[&str] = if ply.colors.is_some() { &["VERTEX_COLORS"] } else { &[] };
+        let shader = manager.register(NarrowingMaterial::default().with_path_defines(shaderpath, defines)?);
+        let (indices, draw_type) = if ply.is_point_cloud() {
+            ((0..u32::try_from(count)?).collect(), POINTS)
+        } else {
+            (ply.indices, TRIANGLES)
+        };
+        let computed_normals = ply.normals.is_none() && draw_type == TRIANGLES;
+        let mut ret = Self::only_data(
+            ply.positions,
+            indices,
+            Some(ply.normals.unwrap_or_else(|| vec![Vector3::new(0.0, 0.0, 0.0); count])),
+            &shader,
+        );
+        ret.draw_type = draw_type;
+        ret.mesh_data.tex_coords = Some(ply.tex_coords.unwrap_or_else(|| vec![Vector2::new(0.0, 0.0); count]));
+        ret.mesh_data.colors = ply.colors;
+        if draw_type == TRIANGLES {
+            ret.mesh_data.check_loaded(path)?;
         }
-
-        let (document, buffers, images) = gltf::import(path)?;
-
-        let mut renderables: Vec<Renderable> = Vec::new();
-        let mut materials: Vec<ShaderPtr> = Vec::new();
-        for i in document.materials() {
-            let mat = NarrowingMaterial::from_gltf_mtl(&i, &images, &buffers, base)?;
-            materials.push(shader_manager.register(mat.with_path(shaderpath)?));
-        }
-        for mesh in document.meshes() {
-            for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
-                let vertices: Vec<Vector3<c_float>> =
-                    reader.read_positions().ok_or("Couldn't read positions")?.map_into().collect();
-                let indices: Vec<c_uint> = reader.read_indices().ok_or("Couldn't read indices")?.into_u32().collect();
-                let tex_coords: Vec<Vector2<c_float>> = reader
-                    .read_tex_coords(0)
-                    .ok_or("Couldn't read texture coordinates")?
-                    .into_f32()
-                    .map_into()
-                    .collect(); //TODO: add multiple sets
-                let normals: Vec<Vector3<c_float>> =
-                    reader.read_normals().ok_or("Couldn't read normals")?.map_into().collect();
-                let material = materials[primitive.material().index().ok_or("couldn't read index")?].clone();
-
-                renderables.push(Renderable::new_with_tex(
-                    vertices, indices, normals, tex_coords, &material,
-                ));
-            }
+        if computed_normals {
+            ret.mesh_data.compute_smooth_normals(DEFAULT_CREASE_ANGLE);
         }
-        Ok(Self {
-            renderables,
-            is: true,
-        })
+        ret.init_mesh()?;
+        Ok(ret)
     }
-    #[allow(clippy::cast_precision_loss)]
-    #[must_use] pub fn create_grid(
-        width: u32,
-        length: u32,
+
+    /// Creates a renderable from a binary or ASCII STL file, with flat facet normals.
+    /// # Errors
+    /// If the file cannot be read or parsed, or the shader fails to compile.
+    pub fn from_stl(
+        path: &str,
+        shaderpath: &str,
+        manager: &mut ShaderManager,
+    ) -> Result<Self, Box<dyn Error>> {
+        let stl = StlMesh::open(path)?;
+        let shader = manager.register(NarrowingMaterial::default().with_path(shaderpath)?);
+        let mut ret = Self::only_data(stl.positions, stl.indices, Some(stl.normals), &shader);
+        ret.mesh_data.check_loaded(path)?;
+        ret.init_mesh()?;
+        Ok(ret)
+    }
+
+    /// Creates a single renderable from all polygons of an OBJ file.
+    ///
+    /// The material of the first polygon is used for the whole mesh; use
+    /// `RenderableGroup::from_obj` to keep a material per group. Files without a material
+    /// library get the default material.
+    /// # Errors
+    /// If the object cannot be created from the given path or shader path.
+    pub fn from_obj(
+        path: &str,
+        shaderpath: &str,
+        manager: &mut ShaderManager,
+    ) -> Result<Self, Box<dyn Error>> {
+        let obj = ObjFile::load(path)?;
+        let material = obj.material_of.first().copied().flatten();
+        let part = obj
+            .split(|_| (None, material))?
+            .pop()
+            .ok_or_else(|| format!("{path} has no polygons"))?;
+        let shader = manager.register(obj.material(material).with_path(shaderpath)?);
+        part.into_renderable(&shader, None)
+    }
+}
+
+/// An OBJ file and the materials of all of its material libraries.
+struct ObjFile {
+    raw: RawObj,
+    /// Directory texture maps are resolved against.
+    base_dir: PathBuf,
+    materials: HashMap<String, Material>,
+    extensions: HashMap<String, MtlExtensions>,
+    /// The `g` group each polygon belongs to.
+    group_of: Vec<Option<String>>,
+    /// The `usemtl` material of each polygon.
+    material_of: Vec<Option<usize>>,
+    /// Material names used by `material_of`.
+    material_names: Vec<String>,
+}
+impl ObjFile {
+    /// Parses an OBJ file. Missing material libraries are skipped with a warning.
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let input = BufReader::new(File::open(path).map_err(|e| format!("Couldn't open file {path}: {e}"))?);
+        let raw = parse_obj(input).map_err(|e| format!("Couldn't parse obj {path}: {e}"))?;
+        let dir = Path::new(path).parent().ok_or("Invalid path")?;
+        let mut materials = HashMap::new();
+        let mut extensions = HashMap::new();
+        for library in &raw.material_libraries {
+            let library_path = dir.join(library);
+            let source = match read_to_string(&library_path) {
+                Ok(source) => source,
+                Err(e) => {
+                    warn!("Couldn't open material library {}: {e}", library_path.display());
+                    continue;
+                }
+            };
+            let mtl = parse_mtl(source.as_bytes())
+                .map_err(|e| format!("Couldn't parse mtl {}: {e}", library_path.display()))?;
+            materials.extend(mtl.materials);
+            extensions.extend(parse_mtl_extensions(&source));
+        }
+        let group_of = Self::label_polygons(&raw.groups, raw.polygons.len());
+        let material_labels = Self::label_polygons(&raw.meshes, raw.polygons.len());
+        let material_names: Vec<String> = material_labels.iter().flatten().unique().cloned().collect();
+        let material_of = material_labels
+            .iter()
+            .map(|m| m.as_ref().and_then(|m| material_names.iter().position(|n| n == m)))
+            .collect();
+        Ok(Self {
+            raw,
+            base_dir: dir.to_path_buf(),
+            materials,
+            extensions,
+            group_of,
+            material_of,
+            material_names,
+        })
+    }
+
+    /// Names each polygon after the group whose ranges contain it.
+    ///
+    /// Polygons in several groups get the alphabetically first name.
+    fn label_polygons(groups: &HashMap<String, Group>, count: usize) -> Vec<Option<String>> {
+        let mut labels = vec![None; count];
+        for (name, group) in groups.iter().sorted_by(|a, b| b.0.cmp(a.0)) {
+            for range in &group.polygons {
+                for label in labels.iter_mut().take(range.end).skip(range.start) {
+                    *label = Some(name.clone());
+                }
+            }
+        }
+        labels
+    }
+
+    /// The material with index `index` in `material_names`, or the default material if it's `None` or missing.
+    fn material(&self, index: Option<usize>) -> NarrowingMaterial {
+        let name = index.map(|i| self.material_names[i].as_str());
+        let mtl = name.and_then(|name| self.materials.get(name)).cloned().unwrap_or_default();
+        let extensions = name.and_then(|name| self.extensions.get(name));
+        NarrowingMaterial::from_obj_mtl(&mtl, extensions, &self.base_dir)
+    }
+
+    /// Triangulates the polygons into one part per distinct `key`, in order of first use.
+    ///
+    /// `key` maps a polygon index to its group name and material.
+    fn split<'a>(
+        &'a self,
+        key: impl Fn(usize) -> (Option<&'a str>, Option<usize>),
+    ) -> Result<Vec<ObjPart>, Box<dyn Error>> {
+        let mut parts: Vec<ObjPart> = Vec::new();
+        let mut part_of: HashMap<(Option<&str>, Option<usize>), usize> = HashMap::new();
+        for (i, polygon) in self.raw.polygons.iter().enumerate() {
+            let (group, material) = key(i);
+            let part = *part_of.entry((group, material)).or_insert_with(|| {
+                parts.push(ObjPart {
+                    group: group.map(str::to_owned),
+                    material,
+                    ..ObjPart::default()
+                });
+                parts.len() - 1
+            });
+            parts[part].push_polygon(i, polygon, &self.raw)?;
+        }
+        Ok(parts)
+    }
+}
+
+/// Position, texture coordinate and normal indices of an OBJ vertex, plus the polygon index for
+/// polygons without normals, since those get their own face normal.
+type ObjVertexKey = (usize, Option<usize>, Option<usize>, Option<usize>);
+
+/// Triangulated vertex data for the polygons of an OBJ file that share a group and material.
+#[derive(Default)]
+struct ObjPart {
+    group: Option<String>,
+    material: Option<usize>,
+    vertices: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<f32>>,
+    tex_coords: Vec<Vector2<f32>>,
+    indices: Vec<u32>,
+    lookup: HashMap<ObjVertexKey, u32>,
+    /// Whether any polygon had normals in the file.
+    has_normals: bool,
+}
+impl ObjPart {
+    /// Adds a polygon as a triangle fan. Polygons without normals get their face normal.
+    fn push_polygon(&mut self, index: usize, polygon: &Polygon, obj: &RawObj) -> Result<(), Box<dyn Error>> {
+        let corners: Vec<(usize, Option<usize>, Option<usize>)> = match polygon {
+            Polygon::P(p) => p.iter().map(|p| (*p, None, None)).collect(),
+            Polygon::PT(p) => p.iter().map(|(p, t)| (*p, Some(*t), None)).collect(),
+            Polygon::PN(p) => p.iter().map(|(p, n)| (*p, None, Some(*n))).collect(),
+            Polygon::PTN(p) => p.iter().map(|(p, t, n)| (*p, Some(*t), Some(*n))).collect(),
+        };
+        if corners.len() < 3 {
+            return Ok(());
+        }
+        let position = |i: usize| -> Result<Vector3<f32>, Box<dyn Error>> {
+            let (x, y, z, _) = obj.positions.get(i).ok_or("Position index out of range")?;
+            Ok(Vector3::new(*x, *y, *z))
+        };
+        let face_normal = {
+            let a = position(corners[0].0)?;
+            let b = position(corners[1].0)?;
+            let c = position(corners[2].0)?;
+            let n = (b - a).cross(c - a);
+            if n.magnitude2() > 0.0 { n.normalize() } else { Vector3::unit_y() }
+        };
+        let mut ids = Vec::with_capacity(corners.len());
+        for (p, t, n) in corners {
+            let key = (p, t, n, n.is_none().then_some(index));
+            if let Some(id) = self.lookup.get(&key) {
+                ids.push(*id);
+                continue;
+            }
+            let id = u32::try_from(self.vertices.len())?;
+            self.vertices.push(position(p)?);
+            self.has_normals |= n.is_some();
+            self.normals.push(match n {
+                Some(n) => {
+                    let (x, y, z) = obj.normals.get(n).ok_or("Normal index out of range")?;
+                    Vector3::new(*x, *y, *z)
+                }
+                None => face_normal,
+            });
+            self.tex_coords.push(match t {
+                Some(t) => {
+                    let (u, v, _) = obj.tex_coords.get(t).ok_or("Texture coordinate index out of range")?;
+                    Vector2::new(*u, *v)
+                }
+                None => Vector2::new(0.0, 0.0),
+            });
+            self.lookup.insert(key, id);
+            ids.push(id);
+        }
+        for i in 1..ids.len() - 1 {
+            self.indices.extend([ids[0], ids[i], ids[i + 1]]);
+        }
+        Ok(())
+    }
+
+    /// Creates the renderable, with smooth normals if the file had none and tangents if the
+    /// shader uses a normal map.
+    fn into_renderable(self, shader: &ShaderPtr, optimizer: Option<&MeshOptimizer>) -> Result<Renderable, Box<dyn Error>> {
+        let source = self.group.as_ref().map_or_else(|| "OBJ mesh".to_owned(), |g| format!("OBJ group {g}"));
+        let mut mesh = Mesh::new(self.vertices, self.indices, Some(self.normals), Some(self.tex_coords));
+        mesh.check_loaded(&source)?;
+        if !self.has_normals {
+            mesh.compute_smooth_normals(DEFAULT_CREASE_ANGLE);
+        }
+        if shader.borrow().textures.contains_key(NORMAL_MAP) {
+            mesh.compute_tangents()?;
+        }
+        if let Some(optimizer) = optimizer {
+            optimizer.apply(&mut mesh);
+        }
+        Renderable::from_mesh(mesh, shader)
+    }
+}
+impl Render for Renderable {
+    fn render(&mut self, shader_override: Option<ShaderPtr>) -> Result<(), Box<dyn Error>> {
+        if !self.is {
+            return Ok(());
+        }
+        let model = self.world_matrix();
+        if shader_override.is_none() {
+            self.bind_joint_matrices()?;
+        }
+        let mut shader = shader_override
+            .as_ref()
+            .unwrap_or(&self.shader)
+            .borrow_mut();
+        shader.use_();
+        shader.update().expect("Shader failed to update.");
+        shader.set(model, "model").expect("Couldn't set shader");
+
+        unsafe {
+            // gl::BindVertexArray(self.mesh_data.vertex_array);
+            self.mesh_data.vertex_array.bind();
+            find_gl_error()?;
+            gl::DrawElements(
+                self.draw_type,
+                i32::try_from(self.mesh_data.indices.len() * size_of::<GLuint>())?,
+                UNSIGNED_INT,
+                null(),
+            );
+            self.mesh_data.vertex_array.unbind();
+            // gl::BindVertexArray(0); // Cleanup
+        }
+        Shader::clear_shader();
+        Ok(())
+    }
+
+    /// Cached until `bounds_key` changes.
+    fn bounds(&mut self) -> Option<Bounds> {
+        let key = self.placement_key();
+        if let Some((_, bounds)) = self.world_bounds.filter(|(cached, _)| *cached == key) {
+            return bounds;
+        }
+        let local = self.mesh_data.bounds();
+        let bounds = (!local.is_empty()).then(|| local.transformed(&self.world_matrix()));
+        self.world_bounds = Some((key, bounds));
+        bounds
+    }
+
+    fn bounds_key(&self) -> Option<u64> {
+        Some(self.placement_key())
+    }
+
+    /// Skinned and morphing renderables, as their bounds only cover the base shape.
+    fn deforms(&self) -> bool {
+        self.joint_matrices.is_some() || !self.mesh_data.morph_targets.is_empty()
+    }
+
+    fn screen_space(&self) -> bool {
+        self.screen_space
+    }
+
+    /// Tests the base shape of skinned and morphing renderables; see `Render::raycast`.
+    fn raycast(&mut self, ray: &Ray) -> Option<RayHit> {
+        if self.draw_type != TRIANGLES || self.screen_space {
+            return None;
+        }
+        self.mesh_data.raycast_world(ray, &self.world_matrix())
+    }
+
+    fn is(&self) -> bool {
+        self.is
+    }
+
+    fn set_is(&mut self, val: bool) {
+        self.is = val;
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+derive_transformable!(Renderable);
+
+/// Selects which scene of a glTF file to load.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum GltfScene<'a> {
+    /// The file's default scene, or its first scene if none is marked as default.
+    #[default]
+    Default,
+    Index(usize),
+    Name(&'a str),
+}
+
+/// Vertex data read from a single glTF primitive.
+#[derive(Clone)]
+struct GltfPrimitive {
+    vertices: Vec<Vector3<c_float>>,
+    indices: Vec<c_uint>,
+    /// Missing normals are computed flat, as the glTF specification requires.
+    normals: Option<Vec<Vector3<c_float>>>,
+    tangents: Option<Vec<[f32; 4]>>,
+    /// Filled with zeros when missing if the material has textures.
+    tex_coords: Option<Vec<Vector2<c_float>>>,
+    joints: Option<Vec<[u16; 4]>>,
+    weights: Option<Vec<[f32; 4]>>,
+    morph_targets: Vec<MorphTarget>,
+    material: Option<usize>,
+    /// Names the mesh and primitive in messages about invalid data.
+    source: String,
+}
+impl GltfPrimitive {
+    fn read(mesh: &gltf::Mesh, primitive: &gltf::Primitive, buffers: &[gltf::buffer::Data]) -> Result<Self, Box<dyn Error>> {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let vertices: Vec<Vector3<c_float>> =
+            reader.read_positions().ok_or("Couldn't read positions")?.map_into().collect();
+        let morph_targets = reader
+            .read_morph_targets()
+            .map(|(positions, normals, _)| MorphTarget {
+                positions: positions.map_or_else(
+                    || vec![Vector3::new(0.0, 0.0, 0.0); vertices.len()],
+                    |p| p.map_into().collect(),
+                ),
+                normals: normals.map(|n| n.map_into().collect()),
+            })
+            .collect();
+        let indices = reader
+            .read_indices()
+            .map_or_else(|| (0..vertices.len() as u32).collect(), |i| i.into_u32().collect());
+        Ok(Self {
+            vertices,
+            indices,
+            tex_coords: reader.read_tex_coords(0).map(|t| t.into_f32().map_into().collect()), //TODO: add multiple sets
+            normals: reader.read_normals().map(|n| n.map_into().collect()),
+            tangents: reader.read_tangents().map(Iterator::collect),
+            joints: reader.read_joints(0).map(|j| j.into_u16().collect()),
+            weights: reader.read_weights(0).map(|w| w.into_f32().collect()),
+            morph_targets,
+            material: primitive.material().index(),
+            source: mesh.name().map_or_else(
+                || format!("glTF mesh {} primitive {}", mesh.index(), primitive.index()),
+                |name| format!("glTF mesh \"{name}\" primitive {}", primitive.index()),
+            ),
+        })
+    }
+
+    const fn is_skinned(&self) -> bool {
+        self.joints.is_some() && self.weights.is_some()
+    }
+
+    /// Creates the renderable, with the skinning shader if the primitive has joints and weights
+    /// and `has_skin`, i.e. its node has a skin whose joint matrices will be set.
+    fn into_renderable(
+        self,
+        has_skin: bool,
+        materials: &mut GltfMaterials,
+        manager: &mut ShaderManager,
+        optimizer: Option<&MeshOptimizer>,
+    ) -> Result<Renderable, Box<dyn Error>> {
+        let index = self.material.ok_or("couldn't read index")?;
+        let material = materials.shader(index, has_skin && self.is_skinned(), manager)?;
+        let mut ret = Renderable::only_data(self.vertices, self.indices, self.normals, &material);
+        let textured = !material.borrow().textures.is_empty();
+        ret.mesh_data.tex_coords = self
+            .tex_coords
+            .or_else(|| textured.then(|| vec![Vector2::new(0.0, 0.0); ret.mesh_data.vertices.len()]));
+        ret.mesh_data.joints = self.joints;
+        ret.mesh_data.weights = self.weights;
+        ret.mesh_data.morph_targets = self.morph_targets;
+        if let Some(tangents) = &self.tangents {
+            ret.mesh_data.custom_attributes.push(CustomAttribute {
+                attribute: VertexAttribute::TANGENT,
+                values: AttributeValues::F32(tangents.iter().flatten().copied().collect()),
+            });
+        }
+        ret.mesh_data.check_loaded(&self.source)?;
+        // Flat normals split the vertices, so the file's tangents must be added before.
+        if ret.mesh_data.normals.is_none() {
+            ret.mesh_data.compute_flat_normals();
+        }
+        if self.tangents.is_none() && material.borrow().textures.contains_key(NORMAL_MAP) {
+            ret.mesh_data.compute_tangents()?;
+        }
+        if let Some(optimizer) = optimizer {
+            optimizer.apply(&mut ret.mesh_data);
+        }
+        ret.init_mesh()?;
+        Ok(ret)
+    }
+}
+
+/// The shaders for a glTF file's materials, with skinned variants compiled on first use.
+struct GltfMaterials<'a> {
+    materials: Vec<NarrowingMaterial>,
+    shaders: Vec<ShaderPtr>,
+    skinned: HashMap<usize, ShaderPtr>,
+    shaderpath: &'a str,
+}
+impl GltfMaterials<'_> {
+    fn shader(
+        &mut self,
+        index: usize,
+        skinned: bool,
+        manager: &mut ShaderManager,
+    ) -> Result<ShaderPtr, Box<dyn Error>> {
+        if !skinned {
+            return Ok(self.shaders.get(index).ok_or("Invalid material index")?.clone());
+        }
+        Ok(match self.skinned.entry(index) {
+            Entry::Occupied(e) => e.get().clone(),
+            Entry::Vacant(e) => {
+                let material = self.materials.get(index).ok_or("Invalid material index")?.clone();
+                let shader = manager.register(material.with_path_defines(self.shaderpath, &["SKINNING"])?);
+                e.insert(shader).clone()
+            }
+        })
+    }
+}
+
+/// A set of renderables placed in a transform hierarchy.
+///
+/// Every renderable is attached to a node of the group's `SceneGraph`; transforming the group
+/// transforms its root node, which is applied to the children as a parent matrix.
+/// Animations move the nodes of the graph, and skinned renderables follow their skin's joints.
+pub struct RenderableGroup {
+    renderables: Vec<Renderable>,
+    /// Whether each renderable passed the last `cull`, parallel to `renderables` once culled.
+    visible: Vec<bool>,
+    /// The node each renderable is attached to, parallel to `renderables`.
+    nodes: Vec<NodeHandle>,
+    /// The skin deforming each renderable, parallel to `renderables`.
+    skin_of: Vec<Option<usize>>,
+    /// Simplified levels drawn instead of each renderable, parallel to `renderables`.
+    lods: Vec<Option<LodRenderable>>,
+    skins: Vec<Skin>,
+    animations: Vec<Animation>,
+    /// Poses restored for nodes an animation doesn't fully cover.
+    rest_poses: HashMap<NodeHandle, Pose>,
+    /// Morph target weights by node, blended into the node's renderables when rendering.
+    morph_weights: HashMap<NodeHandle, Vec<f32>>,
+    /// Authored cameras with the node they're attached to; they look down the node's -Z axis.
+    cameras: Vec<(NodeHandle, Option<String>, Projection)>,
+    /// Lights in the local space of the node they're attached to.
+    lights: Vec<(NodeHandle, Light)>,
+    player: AnimationPlayer,
+    graph: SceneGraph,
+    root: NodeHandle,
+    is: bool,
+}
+impl Default for RenderableGroup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl RenderableGroup {
+    #[must_use]
+    pub fn new() -> Self {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::default());
+        Self {
+            renderables: Vec::new(),
+            visible: Vec::new(),
+            nodes: Vec::new(),
+            skin_of: Vec::new(),
+            lods: Vec::new(),
+            skins: Vec::new(),
+            animations: Vec::new(),
+            rest_poses: HashMap::new(),
+            morph_weights: HashMap::new(),
+            cameras: Vec::new(),
+            lights: Vec::new(),
+            player: AnimationPlayer::default(),
+            graph,
+            root,
+            is: true,
+        }
+    }
+
+    /// Adds a renderable attached to `node`, or to the group's root if `node` is `None`.
+    pub fn push(&mut self, renderable: Renderable, node: Option<NodeHandle>) {
+        self.renderables.push(renderable);
+        self.visible.push(true);
+        self.nodes.push(node.unwrap_or(self.root));
+        self.skin_of.push(None);
+        self.lods.push(None);
+    }
+
+    /// Simplifies every static triangle renderable into `levels`, one of which is drawn each
+    /// frame; see `LodRenderable::new`. Skinned and morphing renderables keep their full mesh.
+    /// # Errors
+    /// If a level's mesh cannot be initialized.
+    pub fn generate_lods(&mut self, levels: &[LodLevel]) -> Result<(), Box<dyn Error>> {
+        for (i, r) in self.renderables.iter().enumerate() {
+            if !self.part_deforms(i, r) && r.draw_type == TRIANGLES {
+                self.lods[i] = Some(LodRenderable::new(&r.mesh_data, &r.shader, levels)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the renderable at `index` is skinned or has morph targets; its joint matrices are
+    /// only set once it is rendered.
+    fn part_deforms(&self, index: usize, renderable: &Renderable) -> bool {
+        self.skin_of[index].is_some() || !renderable.mesh_data.morph_targets.is_empty()
+    }
+
+    /// The levels of detail of the renderable at `index`, if `generate_lods` made any.
+    #[must_use]
+    pub fn lod_of(&self, index: usize) -> Option<&LodRenderable> {
+        self.lods[index].as_ref()
+    }
+
+    /// The node all other nodes of the group descend from.
+    #[must_use]
+    pub const fn root(&self) -> NodeHandle {
+        self.root
+    }
+
+    #[must_use]
+    pub const fn graph(&self) -> &SceneGraph {
+        &self.graph
+    }
+
+    pub const fn graph_mut(&mut self) -> &mut SceneGraph {
+        &mut self.graph
+    }
+
+    #[must_use]
+    pub fn renderables(&self) -> &[Renderable] {
+        &self.renderables
+    }
+
+    pub fn renderables_mut(&mut self) -> &mut [Renderable] {
+        &mut self.renderables
+    }
+
+    /// The node the renderable at `index` is attached to.
+    #[must_use]
+    pub fn node_of(&self, index: usize) -> NodeHandle {
+        self.nodes[index]
+    }
+
+    #[must_use]
+    pub fn animations(&self) -> &[Animation] {
+        &self.animations
+    }
+
+    #[must_use]
+    pub const fn player(&self) -> &AnimationPlayer {
+        &self.player
+    }
+
+    /// The player for the group's animations, e.g. to change its speed or looping.
+    pub const fn player_mut(&mut self) -> &mut AnimationPlayer {
+        &mut self.player
+    }
+
+    /// Plays the animation at `index` from the start.
+    /// # Errors
+    /// If there is no animation with that index.
+    pub fn play(&mut self, index: usize) -> Result<(), Box<dyn Error>> {
+        if index >= self.animations.len() {
+            return Err(format!("No animation with index {index}").into());
+        }
+        self.player.play(index);
+        Ok(())
+    }
+
+    /// Plays the first animation called `name` from the start.
+    /// # Errors
+    /// If there is no animation with that name.
+    pub fn play_named(&mut self, name: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .animations
+            .iter()
+            .position(|a| a.name.as_deref() == Some(name))
+            .ok_or_else(|| format!("No animation named {name}"))?;
+        self.player.play(index);
+        Ok(())
+    }
+
+    /// The group's cameras in world space, in the order they were loaded.
+    ///
+    /// Apply one with `Camera::apply_preset`.
+    pub fn cameras(&mut self) -> Vec<CameraPreset> {
+        let mut presets = Vec::with_capacity(self.cameras.len());
+        for (node, name, projection) in &self.cameras {
+            let world = self.graph.world_matrix(*node);
+            presets.push(CameraPreset {
+                name: name.clone(),
+                position: world.w.truncate(),
+                direction: (-world.z.truncate()).normalize(),
+                projection: *projection,
+            });
+        }
+        presets
+    }
+
+    /// The group's lights in world space, e.g. to assign to `ShaderManager::lights`.
+    pub fn lights(&mut self) -> Vec<Light> {
+        let mut lights = Vec::with_capacity(self.lights.len());
+        for (node, light) in &self.lights {
+            lights.push(light.transformed(self.graph.world_matrix(*node)));
+        }
+        lights
+    }
+
+    /// Attaches `light` to `node`, or to the group's root if `node` is `None`.
+    pub fn push_light(&mut self, light: Light, node: Option<NodeHandle>) {
+        self.lights.push((node.unwrap_or(self.root), light));
+    }
+
+    /// Sets the morph target weights of the renderables attached to `node`.
+    pub fn set_morph_weights(&mut self, node: NodeHandle, weights: Vec<f32>) {
+        self.morph_weights.insert(node, weights);
+    }
+
+    /// Stops the playing animation, leaving the nodes in their current pose.
+    pub const fn stop(&mut self) {
+        self.player.stop();
+    }
+
+    /// Creates a new `RenderableGroup` from an OBJ file.
+    ///
+    /// Polygons are split by `g` group and `usemtl` material. Every group gets a named node below
+    /// the root, and every material of every `mtllib` gets its own shader; polygons without a
+    /// material, or whose material library is missing, use the default material.
+    /// # Errors
+    /// If the file cannot be read or parsed, or a shader fails to compile.
+    pub fn from_obj(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::load_obj(path, shaderpath, shader_manager, None)
+    }
+
+    /// Like `from_obj`, but runs `optimizer` on every mesh before it is uploaded.
+    /// # Errors
+    /// If the file cannot be read or parsed, or a shader fails to compile.
+    pub fn from_obj_optimized(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+        optimizer: &MeshOptimizer,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::load_obj(path, shaderpath, shader_manager, Some(optimizer))
+    }
+
+    fn load_obj(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+        optimizer: Option<&MeshOptimizer>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let obj = ObjFile::load(path)?;
+        let mut ret = Self::new();
+        let mut shaders: HashMap<Option<usize>, ShaderPtr> = HashMap::new();
+        for part in obj.split(|i| (obj.group_of[i].as_deref(), obj.material_of[i]))? {
+            let node = match &part.group {
+                Some(name) => match ret.graph.find(name) {
+                    Some(node) => node,
+                    None => ret.graph.add_matrix_node(Some(ret.root), Some(name.clone()), Matrix4::identity()),
+                },
+                None => ret.root,
+            };
+            let shader = match shaders.entry(part.material) {
+                Entry::Occupied(e) => e.get().clone(),
+                Entry::Vacant(e) => e
+                    .insert(shader_manager.register(obj.material(part.material).with_path(shaderpath)?))
+                    .clone(),
+            };
+            ret.push(part.into_renderable(&shader, optimizer)?, Some(node));
+        }
+        Ok(ret)
+    }
+
+    /// Creates a new `RenderableGroup` from the default scene of a glTF file.
+    /// # Errors
+    /// If the renderables cannot be created from the given path or shader path.
+    pub fn from_gltf(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::from_gltf_scene(path, shaderpath, shader_manager, GltfScene::Default)
+    }
+
+    /// Creates a new `RenderableGroup` from one scene of a glTF file.
+    ///
+    /// The scene's node tree is mirrored in the group's `SceneGraph` with each node's transform,
+    /// and every node that references a mesh gets its own renderables, so meshes used by several
+    /// nodes are drawn once per node. Files without scenes fall back to drawing every mesh at the origin.
+    /// Skins, morph targets and animations are loaded as well; see `play`. Cameras and
+    /// `KHR_lights_punctual` lights are available from `cameras` and `lights`.
+    ///
+    /// Primitive data is validated and repaired where possible, e.g. by dropping degenerate
+    /// triangles; see `Mesh::validate`.
+    /// # Errors
+    /// If the selected scene doesn't exist, a primitive has data that cannot be repaired, or the
+    /// renderables cannot be created from the given path or shader path.
+    pub fn from_gltf_scene(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+        scene: GltfScene,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::load_gltf(path, shaderpath, shader_manager, scene, None)
+    }
+
+    /// Like `from_gltf_scene`, but runs `optimizer` on every mesh before it is uploaded.
+    /// # Errors
+    /// If the selected scene doesn't exist, or the renderables cannot be created from the given
+    /// path or shader path.
+    pub fn from_gltf_optimized(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+        scene: GltfScene,
+        optimizer: &MeshOptimizer,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::load_gltf(path, shaderpath, shader_manager, scene, Some(optimizer))
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn load_gltf(
+        path: &str,
+        shaderpath: &str,
+        shader_manager: &mut ShaderManager,
+        scene: GltfScene,
+        optimizer: Option<&MeshOptimizer>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut ancestors = Path::new(path).ancestors();
+        let mut base = "";
+        ancestors.next();
+        if let Some(root) = ancestors.next() {
+            base = root.to_str().ok_or("Should be a string.")?;
+        }
+
+        let (document, buffers, images) = gltf::import(path)?;
+
+        let mut ret = Self::new();
+        let mut materials = GltfMaterials {
+            materials: Vec::new(),
+            shaders: Vec::new(),
+            skinned: HashMap::new(),
+            shaderpath,
+        };
+        for i in document.materials() {
+            let mat = NarrowingMaterial::from_gltf_mtl(&i, &images, &buffers, base)?;
+            materials.shaders.push(shader_manager.register(mat.clone().with_path(shaderpath)?));
+            materials.materials.push(mat);
+        }
+        let Some(scene) = Self::select_gltf_scene(&document, scene, path)? else {
+            for mesh in document.meshes() {
+                for primitive in mesh.primitives() {
+                    let data = GltfPrimitive::read(&mesh, &primitive, &buffers)?;
+                    // Without nodes there are no skins to pose the joints.
+                    ret.push(data.into_renderable(false, &mut materials, shader_manager, optimizer)?, None);
+                }
+            }
+            return Ok(ret);
+        };
+
+        // Primitive data by mesh index, so meshes referenced by several nodes are only read once.
+        let mut meshes: HashMap<usize, Vec<GltfPrimitive>> = HashMap::new();
+        // The first renderable of each uploaded primitive by mesh and primitive index and whether
+        // it's skinned, which later nodes with the same mesh draw from instead of uploading it again.
+        let mut uploaded: HashMap<(usize, usize, bool), usize> = HashMap::new();
+        // Scene graph nodes by glTF node index.
+        let mut handles: HashMap<usize, NodeHandle> = HashMap::new();
+        // Renderables waiting for their skin, with the glTF skin index.
+        let mut skinned: Vec<(usize, usize)> = Vec::new();
+        let mut stack: Vec<(gltf::Node, NodeHandle)> = scene.nodes().map(|n| (n, ret.root)).collect();
+        while let Some((node, parent)) = stack.pop() {
+            let handle = ret.graph.add_matrix_node(
+                Some(parent),
+                node.name().map(str::to_owned),
+                Matrix4::from(node.transform().matrix()),
+            );
+            handles.insert(node.index(), handle);
+            if let Some(camera) = node.camera() {
+                ret.cameras.push((handle, camera.name().map(str::to_owned), Self::read_gltf_camera(&camera)));
+            }
+            if let Some(light) = node.light() {
+                ret.push_light(Self::read_gltf_light(&light), Some(handle));
+            }
+            let (translation, rotation, scale) = node.transform().decomposed();
+            ret.rest_poses.insert(
+                handle,
+                Pose {
+                    translation: translation.into(),
+                    // glTF stores quaternions as [x, y, z, w].
+                    rotation: Quaternion::from_sv(rotation[3], Vector3::new(rotation[0], rotation[1], rotation[2])),
+                    scale: scale.into(),
+                },
+            );
+            if let Some(mesh) = node.mesh() {
+                if let Some(weights) = node.weights().or_else(|| mesh.weights()) {
+                    ret.morph_weights.insert(handle, weights.to_vec());
+                }
+                let primitives = match meshes.entry(mesh.index()) {
+                    Entry::Occupied(e) => e.into_mut(),
+                    Entry::Vacant(e) => e.insert(
+                        mesh.primitives()
+                            .map(|p| GltfPrimitive::read(&mesh, &p, &buffers))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                };
+                for (primitive, data) in primitives.iter().enumerate() {
+                    if let (true, Some(skin)) = (data.is_skinned(), node.skin()) {
+                        skinned.push((ret.renderables.len(), skin.index()));
+                    }
+                    let key = (mesh.index(), primitive, node.skin().is_some());
+                    let renderable = if let Some(&first) = uploaded.get(&key) {
+                        ret.renderables[first].share()
+                    } else {
+                        let renderable =
+                            data.clone().into_renderable(key.2, &mut materials, shader_manager, optimizer)?;
+                        // Morphing meshes blend their own weights into the vertex buffer.
+                        if renderable.mesh_data.morph_targets.is_empty() {
+                            uploaded.insert(key, ret.renderables.len());
+                        }
+                        renderable
+                    };
+                    ret.push(renderable, Some(handle));
+                }
+            }
+            stack.extend(node.children().map(|c| (c, handle)));
+        }
+
+        // Skins by glTF skin index.
+        let mut skins: HashMap<usize, usize> = HashMap::new();
+        for (renderable, skin_index) in skinned {
+            let skin = match skins.entry(skin_index) {
+                Entry::Occupied(e) => *e.get(),
+                Entry::Vacant(e) => {
+                    let skin = document.skins().nth(skin_index).ok_or("Invalid skin index")?;
+                    ret.skins.push(Self::read_gltf_skin(&skin, &buffers, &handles)?);
+                    *e.insert(ret.skins.len() - 1)
+                }
+            };
+            ret.skin_of[renderable] = Some(skin);
+        }
+        for animation in document.animations() {
+            ret.animations.push(Self::read_gltf_animation(&animation, &buffers, &handles)?);
+        }
+        Ok(ret)
+    }
+
+    fn select_gltf_scene<'a>(
+        document: &'a gltf::Document,
+        scene: GltfScene,
+        path: &str,
+    ) -> Result<Option<gltf::Scene<'a>>, Box<dyn Error>> {
+        Ok(match scene {
+            GltfScene::Default => document.default_scene().or_else(|| document.scenes().next()),
+            GltfScene::Index(index) => Some(
+                document
+                    .scenes()
+                    .nth(index)
+                    .ok_or_else(|| format!("No scene with index {index} in {path}"))?,
+            ),
+            GltfScene::Name(name) => Some(
+                document
+                    .scenes()
+                    .find(|s| s.name() == Some(name))
+                    .ok_or_else(|| format!("No scene named {name} in {path}"))?,
+            ),
+        })
+    }
+
+    fn read_gltf_camera(camera: &gltf::Camera) -> Projection {
+        match camera.projection() {
+            gltf::camera::Projection::Perspective(p) => Projection::Perspective {
+                yfov: p.yfov(),
+                aspect_ratio: p.aspect_ratio(),
+                znear: p.znear(),
+                zfar: p.zfar(),
+            },
+            gltf::camera::Projection::Orthographic(o) => Projection::Orthographic {
+                xmag: o.xmag(),
+                ymag: o.ymag(),
+                znear: o.znear(),
+                zfar: o.zfar(),
+            },
+        }
+    }
+
+    fn read_gltf_light(light: &gltf::khr_lights_punctual::Light) -> Light {
+        let kind = match light.kind() {
+            gltf::khr_lights_punctual::Kind::Directional => LightKind::Directional,
+            gltf::khr_lights_punctual::Kind::Point => LightKind::Point,
+            gltf::khr_lights_punctual::Kind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            } => LightKind::Spot {
+                inner_cone_angle,
+                outer_cone_angle,
+            },
+        };
+        let mut ret = Light::new(kind, light.color().into(), light.intensity());
+        ret.name = light.name().map(str::to_owned);
+        ret.range = light.range();
+        ret
+    }
+
+    fn read_gltf_skin(
+        skin: &gltf::Skin,
+        buffers: &[gltf::buffer::Data],
+        handles: &HashMap<usize, NodeHandle>,
+    ) -> Result<Skin, Box<dyn Error>> {
+        let joints: Vec<NodeHandle> = skin
+            .joints()
+            .map(|j| handles.get(&j.index()).copied().ok_or("Skin joint is not part of the loaded scene"))
+            .collect::<Result<_, _>>()?;
+        if joints.len() > MAX_JOINTS {
+            return Err(format!("Skin has {} joints, at most {MAX_JOINTS} are supported", joints.len()).into());
+        }
+        let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+        let inverse_bind_matrices = reader.read_inverse_bind_matrices().map_or_else(
+            || vec![Matrix4::identity(); joints.len()],
+            |m| m.map(Matrix4::from).collect(),
+        );
+        if inverse_bind_matrices.len() != joints.len() {
+            return Err("Skin has a different number of joints and inverse bind matrices".into());
+        }
+        Ok(Skin {
+            joints,
+            inverse_bind_matrices,
+        })
+    }
+
+    /// Reads the channels of `animation` that target nodes of the loaded scene.
+    fn read_gltf_animation(
+        animation: &gltf::Animation,
+        buffers: &[gltf::buffer::Data],
+        handles: &HashMap<usize, NodeHandle>,
+    ) -> Result<Animation, Box<dyn Error>> {
+        use gltf::animation::util::ReadOutputs;
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let Some(&target) = handles.get(&channel.target().node().index()) else {
+                continue;
+            };
+            let interpolation = Interpolation::from(channel.sampler().interpolation());
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader.read_inputs().ok_or("Couldn't read keyframe times")?.collect();
+            let values = match reader.read_outputs().ok_or("Couldn't read keyframe values")? {
+                ReadOutputs::Translations(t) => {
+                    ChannelValues::Translation(Sampler::new(interpolation, times, t.map_into().collect())?)
+                }
+                ReadOutputs::Rotations(r) => ChannelValues::Rotation(Sampler::new(
+                    interpolation,
+                    times,
+                    r.into_f32().map(|[x, y, z, w]| Quaternion::new(w, x, y, z)).collect(),
+                )?),
+                ReadOutputs::Scales(s) => {
+                    ChannelValues::Scale(Sampler::new(interpolation, times, s.map_into().collect())?)
+                }
+                ReadOutputs::MorphTargetWeights(w) => {
+                    let w: Vec<f32> = w.into_f32().collect();
+                    let per_key = if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+                    let count = w.len() / (times.len() * per_key).max(1);
+                    ChannelValues::Weights(Sampler::split_weights(interpolation, &times, &w, count)?)
+                }
+            };
+            channels.push(Channel { target, values });
+        }
+        Ok(Animation {
+            name: animation.name().map(str::to_owned),
+            channels,
+        })
+    }
+    /// Writes all triangle renderables to an OBJ file in world space, one group per renderable,
+    /// with a companion MTL file next to it.
+    ///
+    /// Materials are rebuilt from the values each renderable's shader was compiled with; texture
+    /// maps aren't written. Renderables that aren't triangle lists are skipped with a warning.
+    /// # Errors
+    /// If either file cannot be written.
+    pub fn write_obj(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mtl_path = Path::new(path).with_extension("mtl");
+        let mut materials: Vec<ShaderPtr> = Vec::new();
+        let file = File::create(path).map_err(|e| format!("Couldn't create file {path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+        if let Some(name) = mtl_path.file_name() {
+            writeln!(writer, "mtllib {}", name.to_string_lossy())?;
+        }
+        self.graph.update();
+        let mut offsets = [0; 3];
+        for (i, (renderable, node)) in self.renderables.iter().zip(&self.nodes).enumerate() {
+            if renderable.draw_type != TRIANGLES {
+                warn!("Skipping renderable {i}, OBJ export only supports triangles");
+                continue;
+            }
+            let material = materials.iter().position(|m| Rc::ptr_eq(m, &renderable.shader)).unwrap_or_else(|| {
+                materials.push(renderable.shader.clone());
+                materials.len() - 1
+            });
+            let name = self.graph.node(*node).name.clone().unwrap_or_else(|| format!("renderable{i}"));
+            writeln!(writer, "g {name}")?;
+            writeln!(writer, "usemtl material{material}")?;
+            let mesh = &renderable.mesh_data;
+            let matrix = self.graph.world_matrix(*node) * renderable.transform.mat();
+            mesh.write_obj_elements(&mut writer, matrix, offsets)?;
+            offsets[0] += mesh.vertices.len();
+            offsets[1] += mesh.tex_coords.as_ref().map_or(0, Vec::len);
+            offsets[2] += mesh.normals.as_ref().map_or(0, Vec::len);
+        }
+        writer.flush()?;
+
+        let file = File::create(&mtl_path).map_err(|e| format!("Couldn't create file {}: {e}", mtl_path.display()))?;
+        let mut writer = BufWriter::new(file);
+        for (i, shader) in materials.iter().enumerate() {
+            let shader = shader.borrow();
+            writeln!(writer, "newmtl material{i}")?;
+            if let Some([r, g, b, ..]) = shader.vector_value("diffuse") {
+                writeln!(writer, "Kd {r} {g} {b}")?;
+            }
+            if let Some(specular) = shader.value("specular") {
+                writeln!(writer, "Ks {specular} {specular} {specular}")?;
+            }
+            if let Some([r, g, b, ..]) = shader.vector_value("emissive") {
+                writeln!(writer, "Ke {r} {g} {b}")?;
+            }
+            if let Some(shininess) = shader.value("specular_exponent") {
+                writeln!(writer, "Ns {shininess}")?;
+            }
+            if let Some(opacity) = shader.value("opacity") {
+                writeln!(writer, "d {opacity}")?;
+            }
+            if let Some(metallic) = shader.value("metallic") {
+                writeln!(writer, "Pm {metallic}")?;
+            }
+            if let Some(roughness) = shader.value("roughness") {
+                writeln!(writer, "Pr {roughness}")?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    #[must_use] pub fn create_grid(
+        width: u32,
+        length: u32,
         scale: f32,
         pos: Vector2<f32>,
     ) -> (Vec<Vector3<f32>>, Vec<u32>, Vec<Vector3<f32>>) {
@@ -517,10 +2180,117 @@ impl Render for RenderableGroup {
         if !self.is {
             return Ok(());
         }
-        self.renderables.iter_mut().try_for_each(|r| {
-            let shader = shader_override.as_ref().map(std::clone::Clone::clone);
-            r.render(shader)
-        })
+        self.graph.update();
+        let parts = self.renderables.iter_mut().zip(&self.nodes).zip(&self.skin_of).zip(&mut self.lods).zip(&self.visible);
+        for ((((r, node), skin), lod), _) in parts.filter(|(_, visible)| **visible) {
+            if let Some(lod) = lod.as_mut().filter(|_| r.is) {
+                lod.transform.clone_from(&r.transform);
+                lod.set_parent_matrix(self.graph.world_matrix(*node));
+                lod.render(shader_override.clone())?;
+                continue;
+            }
+            r.set_parent_matrix(self.graph.world_matrix(*node));
+            if let Some(skin) = skin {
+                r.set_joint_matrices(Some(self.skins[*skin].joint_matrices(&mut self.graph, *node)));
+            }
+            if let Some(weights) = self.morph_weights.get(node) {
+                r.set_morph_weights(weights)?;
+            }
+            r.render(shader_override.clone())?;
+        }
+        Ok(())
+    }
+
+    fn animate(&mut self, time: f64) {
+        let Some(animation) = self.player.playing() else {
+            return;
+        };
+        let Some(animation) = self.animations.get(animation) else {
+            return;
+        };
+        let Some((_, local_time)) = self.player.tick(time, animation.duration()) else {
+            return;
+        };
+        for (node, pose) in animation.sample(local_time, &self.rest_poses) {
+            self.graph.set_local_matrix(node, pose.mat());
+        }
+        self.morph_weights.extend(animation.sample_weights(local_time));
+    }
+
+    /// Lets each renderable's levels of detail pick one for the camera.
+    fn prepare(&mut self, camera: &Camera) {
+        self.graph.update();
+        for (i, r) in self.renderables.iter_mut().enumerate() {
+            r.set_parent_matrix(self.graph.world_matrix(self.nodes[i]));
+            r.prepare(camera);
+            if let Some(lod) = &mut self.lods[i] {
+                lod.transform.clone_from(&r.transform);
+                lod.set_parent_matrix(self.graph.world_matrix(self.nodes[i]));
+                lod.prepare(camera);
+            }
+        }
+    }
+
+    /// The bounds around every visible renderable, placed by the scene graph.
+    fn bounds(&mut self) -> Option<Bounds> {
+        let mut bounds = Bounds::EMPTY;
+        for (r, node) in self.renderables.iter().zip(&self.nodes) {
+            let local = r.mesh_data.bounds();
+            if r.is && !local.is_empty() {
+                let world = self.graph.world_matrix(*node) * r.transform.mat();
+                bounds = bounds.union(&local.transformed(&world));
+            }
+        }
+        (!bounds.is_empty()).then_some(bounds)
+    }
+
+    /// Hashes the scene graph's generation and the placement of every visible renderable.
+    fn bounds_key(&self) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.graph.generation());
+        for r in &self.renderables {
+            hasher.write_u8(u8::from(r.is));
+            hasher.write_u64(r.placement_key());
+        }
+        Some(hasher.finish())
+    }
+
+    fn deforms(&self) -> bool {
+        self.renderables.iter().enumerate().any(|(i, r)| self.part_deforms(i, r))
+    }
+
+    /// Culls each renderable on its own. Skinned and morphing renderables are always drawn, since
+    /// their bounds only cover the base shape, and so are renderables in screen space.
+    fn cull(&mut self, frustum: &Frustum) -> CullStats {
+        let mut stats = CullStats::default();
+        for (i, r) in self.renderables.iter().enumerate() {
+            let deforms = self.part_deforms(i, r) || r.screen_space;
+            let local = r.mesh_data.bounds();
+            let visible = r.is
+                && (deforms || {
+                    let world = self.graph.world_matrix(self.nodes[i]) * r.transform.mat();
+                    frustum.intersects(&local.transformed(&world))
+                });
+            if visible {
+                stats.drawn += 1;
+            } else if r.is {
+                stats.culled += 1;
+            }
+            self.visible[i] = visible;
+        }
+        stats
+    }
+
+    /// Skips renderables in screen space and tests skinned and morphing ones in their base shape.
+    fn raycast(&mut self, ray: &Ray) -> Option<RayHit> {
+        let mut hits = Vec::new();
+        for (i, r) in self.renderables.iter().enumerate() {
+            if r.is && r.draw_type == TRIANGLES && !r.screen_space {
+                let world = self.graph.world_matrix(self.nodes[i]) * r.transform.mat();
+                hits.extend(r.mesh_data.raycast_world(ray, &world).map(|hit| RayHit { part: i, ..hit }));
+            }
+        }
+        nearest_hit(hits)
     }
 
     fn is(&self) -> bool {
@@ -537,47 +2307,224 @@ impl Render for RenderableGroup {
 }
 impl<T: AsPrimitive<f32> + Copy> Transformable<T> for RenderableGroup {
     fn scale(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].scale(x, y, z);
-        }
+        self.graph.local_mut(self.root).scale(x.as_(), y.as_(), z.as_());
     }
     fn uniform_scale(&mut self, scale: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].uniform_scale(scale);
-        }
+        self.graph.local_mut(self.root).uniform_scale(scale.as_());
     }
     fn rotate(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].rotate(x, y, z);
-        }
+        self.graph.local_mut(self.root).rotate(x.as_(), y.as_(), z.as_());
     }
     fn translate(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].translate(x, y, z);
-        }
+        self.graph.local_mut(self.root).translate(x.as_(), y.as_(), z.as_());
     }
 
     fn set_scale(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].set_scale(x, y, z);
-        }
+        self.graph.local_mut(self.root).set_scale(x.as_(), y.as_(), z.as_());
     }
 
     fn set_uniform_scale(&mut self, scale: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].set_uniform_scale(scale);
-        }
+        self.graph.local_mut(self.root).set_uniform_scale(scale.as_());
     }
 
     fn set_rotation(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].set_rotation(x, y, z);
-        }
+        self.graph.local_mut(self.root).set_rotation(x.as_(), y.as_(), z.as_());
     }
 
     fn set_translation(&mut self, x: T, y: T, z: T) {
-        for i in 0..self.renderables.len() {
-            self.renderables[i].set_translation(x, y, z);
-        }
+        self.graph.local_mut(self.root).set_translation(x.as_(), y.as_(), z.as_());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{ortho, Deg};
+    use std::cell::RefCell;
+
+    fn shader() -> ShaderPtr {
+        Rc::new(RefCell::new(Shader::default()))
+    }
+
+    fn quad_vertices() -> Vec<Vector3<f32>> {
+        vec![
+            Vector3::new(-0.5, -0.5, 0.0),
+            Vector3::new(0.5, -0.5, 0.0),
+            Vector3::new(0.5, 0.5, 0.0),
+            Vector3::new(-0.5, 0.5, 0.0),
+        ]
+    }
+
+    /// A unit quad around the origin, which needs no OpenGL context as long as it isn't drawn.
+    fn quad(shader: &ShaderPtr) -> Renderable {
+        Renderable::only_data(quad_vertices(), vec![0, 1, 2, 0, 2, 3], None, shader)
+    }
+
+    /// The box from -1 to 1 along every axis.
+    fn unit_frustum() -> Frustum {
+        Frustum::from_matrix(&ortho(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0))
+    }
+
+    const DRAWN: CullStats = CullStats { drawn: 1, culled: 0 };
+    const CULLED: CullStats = CullStats { drawn: 0, culled: 1 };
+
+    #[test]
+    fn cull_stats_add_up() {
+        let mut stats = CullStats::default();
+        stats += DRAWN;
+        stats += CullStats { drawn: 2, culled: 3 };
+        stats += CULLED;
+        assert_eq!(stats, CullStats { drawn: 3, culled: 4 });
+    }
+
+    #[test]
+    fn renderables_outside_the_frustum_are_culled() {
+        let frustum = unit_frustum();
+        let mut renderable = quad(&shader());
+        assert_eq!(renderable.cull(&frustum), DRAWN);
+        renderable.transform.set_translation(5.0, 0.0, 0.0);
+        assert_eq!(renderable.cull(&frustum), CULLED);
+        // Parent nodes move renderables too.
+        renderable.transform.set_translation(0.0, 0.0, 0.0);
+        renderable.set_parent_matrix(Matrix4::from_translation(Vector3::new(0.0, -5.0, 0.0)));
+        assert_eq!(renderable.cull(&frustum), CULLED);
+
+        renderable.screen_space = true;
+        assert_eq!(renderable.cull(&frustum), DRAWN);
+        renderable.screen_space = false;
+        renderable.mesh_data.morph_targets.push(MorphTarget { positions: quad_vertices(), normals: None });
+        assert_eq!(renderable.cull(&frustum), DRAWN);
+    }
+
+    #[test]
+    fn groups_cull_each_renderable() {
+        let shader = shader();
+        let mut group = RenderableGroup::new();
+        let far = group.graph.add_node(Some(group.root), Transform::default());
+        group.graph.set_local_matrix(far, Matrix4::from_translation(Vector3::new(0.0, 0.0, 5.0)));
+        group.push(quad(&shader), None);
+        group.push(quad(&shader), Some(far));
+        let mut hidden = quad(&shader);
+        hidden.is = false;
+        group.push(hidden, None);
+        let mut overlay = quad(&shader);
+        overlay.screen_space = true;
+        group.push(overlay, Some(far));
+
+        assert_eq!(group.cull(&unit_frustum()), CullStats { drawn: 2, culled: 1 });
+        assert_eq!(group.visible, vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn instances_are_culled_one_by_one() {
+        let shader = shader();
+        let placed = |x: f32| {
+            let mut transform = Transform::default();
+            transform.set_translation(x, 0.0, 0.0);
+            transform
+        };
+        let mut instances = InstancedObject {
+            mesh: Mesh::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3], None, None),
+            transforms: vec![placed(0.0), placed(5.0), placed(1.25), placed(-3.0)],
+            colors: Vec::new(),
+            visible: None,
+            shader,
+            is: true,
+            draw_type: TRIANGLES,
+            screen_space: false,
+        };
+        let frustum = unit_frustum();
+        assert_eq!(instances.cull(&frustum), CullStats { drawn: 2, culled: 2 });
+        // The instance at 1.25 reaches into the frustum.
+        assert_eq!(instances.visible, Some(vec![0, 2]));
+
+        instances.set_screen_space(true);
+        assert_eq!(instances.cull(&frustum), CullStats { drawn: 4, culled: 0 });
+        assert_eq!(instances.visible, None);
+    }
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-5
+    }
+
+    /// Scales the quad by 2, turns it a quarter around z and moves it to (1, 2, -3).
+    fn placement() -> Matrix4<f32> {
+        Matrix4::from_translation(Vector3::new(1.0, 2.0, -3.0))
+            * Matrix4::from_angle_z(Deg(90.0))
+            * Matrix4::from_scale(2.0)
+    }
+
+    /// A ray straight down onto the quad's model space point (0.25, -0.25) once it is placed.
+    fn placed_ray() -> Ray {
+        Ray::new(Vector3::new(1.5, 2.5, 5.0), Vector3::new(0.0, 0.0, -1.0))
+    }
+
+    #[test]
+    fn rays_hit_quads_in_model_space() {
+        let mesh = Mesh::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3], None, None);
+        let down = Vector3::new(0.0, 0.0, -1.0);
+
+        let hit = mesh.raycast(&Ray::new(Vector3::new(0.25, -0.25, 2.0), down)).unwrap();
+        assert_eq!(hit.triangle, 0);
+        assert!((hit.distance - 2.0).abs() < 1e-6);
+        assert!(close(hit.position, Vector3::new(0.25, -0.25, 0.0)));
+        assert!(close(hit.barycentric, Vector3::new(0.25, 0.5, 0.25)));
+
+        let hit = mesh.raycast(&Ray::new(Vector3::new(-0.25, 0.25, 2.0), down)).unwrap();
+        assert_eq!(hit.triangle, 1);
+        assert!(close(hit.barycentric, Vector3::new(0.25, 0.25, 0.5)));
+
+        assert_eq!(mesh.raycast(&Ray::new(Vector3::new(1.0, 0.0, 2.0), down)), None);
+        assert_eq!(mesh.raycast(&Ray::new(Vector3::new(0.0, 0.0, 2.0), -down)), None);
+    }
+
+    #[test]
+    fn rays_hit_placed_quads_in_world_space() {
+        let mesh = Mesh::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3], None, None);
+        let hit = mesh.raycast_world(&placed_ray(), &placement()).unwrap();
+        assert_eq!(hit.triangle, 0);
+        assert!((hit.distance - 8.0).abs() < 1e-5);
+        assert!(close(hit.position, Vector3::new(1.5, 2.5, -3.0)));
+        assert!(close(hit.barycentric, Vector3::new(0.25, 0.5, 0.25)));
+
+        // The quad spans x from -1 to 3 once placed, but y only from 1 to 3.
+        let beside = Ray::new(Vector3::new(1.5, 0.5, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(mesh.raycast_world(&beside, &placement()), None);
+
+        let mut renderable = quad(&shader());
+        renderable.set_parent_matrix(placement());
+        assert_eq!(renderable.raycast(&placed_ray()), Some(hit));
+    }
+
+    #[test]
+    fn screen_space_objects_are_never_hit() {
+        let shader = shader();
+        let mut renderable = quad(&shader);
+        renderable.set_parent_matrix(placement());
+        renderable.screen_space = true;
+        assert_eq!(renderable.raycast(&placed_ray()), None);
+
+        let mut group = RenderableGroup::new();
+        let placed = group.graph.add_node(Some(group.root), Transform::default());
+        group.graph.set_local_matrix(placed, placement());
+        group.push(renderable, Some(placed));
+        assert_eq!(group.raycast(&placed_ray()), None);
+        group.push(quad(&shader), Some(placed));
+        assert_eq!(group.raycast(&placed_ray()).map(|hit| hit.part), Some(1));
+
+        let mut instances = InstancedObject {
+            mesh: Mesh::new(quad_vertices(), vec![0, 1, 2, 0, 2, 3], None, None),
+            transforms: vec![Transform::default()],
+            colors: Vec::new(),
+            visible: None,
+            shader,
+            is: true,
+            draw_type: TRIANGLES,
+            screen_space: false,
+        };
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(instances.raycast(&ray).is_some());
+        instances.set_screen_space(true);
+        assert_eq!(instances.raycast(&ray), None);
     }
 }
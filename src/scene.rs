@@ -0,0 +1,306 @@
+//! A parent/child transform hierarchy.
+//!
+//! Nodes are stored in a flat arena and addressed by [`NodeHandle`]s. Each node has a local
+//! [`Transform`]; its world matrix is the parent's world matrix times its local matrix and is
+//! only recomputed after the node or one of its ancestors changed.
+use crate::transformation::Transform;
+use cgmath::{Matrix4, SquareMatrix};
+use std::error::Error;
+
+/// Identifies a node inside a [`SceneGraph`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeHandle(usize);
+
+impl NodeHandle {
+    #[must_use]
+    pub const fn index(self) -> usize {
+        self.0
+    }
+}
+
+pub struct Node {
+    pub name: Option<String>,
+    local: Transform,
+    /// Used instead of `local` for transforms that can't be expressed as a `Transform`, e.g. glTF matrices.
+    local_matrix: Option<Matrix4<f32>>,
+    world: Matrix4<f32>,
+    dirty: bool,
+    parent: Option<NodeHandle>,
+    children: Vec<NodeHandle>,
+}
+
+impl Node {
+    #[must_use]
+    pub const fn local(&self) -> &Transform {
+        &self.local
+    }
+    #[must_use]
+    pub fn local_matrix(&self) -> Matrix4<f32> {
+        self.local_matrix.unwrap_or_else(|| self.local.mat())
+    }
+    #[must_use]
+    pub const fn parent(&self) -> Option<NodeHandle> {
+        self.parent
+    }
+    #[must_use]
+    pub fn children(&self) -> &[NodeHandle] {
+        &self.children
+    }
+}
+
+#[derive(Default)]
+pub struct SceneGraph {
+    nodes: Vec<Node>,
+    /// Incremented whenever a node's local transform or parent changes.
+    generation: u64,
+}
+
+impl SceneGraph {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a node below `parent`, or as a root if `parent` is `None`.
+    pub fn add_node(&mut self, parent: Option<NodeHandle>, local: Transform) -> NodeHandle {
+        let handle = NodeHandle(self.nodes.len());
+        self.nodes.push(Node {
+            name: None,
+            local,
+            local_matrix: None,
+            world: Matrix4::identity(),
+            dirty: true,
+            parent,
+            children: Vec::new(),
+        });
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(handle);
+        }
+        handle
+    }
+
+    /// Adds a named node whose local transform is an arbitrary matrix.
+    pub fn add_matrix_node(
+        &mut self,
+        parent: Option<NodeHandle>,
+        name: Option<String>,
+        local: Matrix4<f32>,
+    ) -> NodeHandle {
+        let handle = self.add_node(parent, Transform::default());
+        self.nodes[handle.0].name = name;
+        self.nodes[handle.0].local_matrix = Some(local);
+        handle
+    }
+
+    #[must_use]
+    pub fn node(&self, handle: NodeHandle) -> &Node {
+        &self.nodes[handle.0]
+    }
+
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Finds the first node with the given name.
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<NodeHandle> {
+        self.nodes
+            .iter()
+            .position(|n| n.name.as_deref() == Some(name))
+            .map(NodeHandle)
+    }
+
+    /// Counts the changes to local transforms and parents, so callers can notice that world
+    /// matrices may have moved without comparing them.
+    #[must_use]
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Nodes without a parent.
+    pub fn roots(&self) -> impl Iterator<Item = NodeHandle> + '_ {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| n.parent.is_none())
+            .map(|(i, _)| NodeHandle(i))
+    }
+
+    /// Returns the local transform for editing and marks the node and its descendants dirty.
+    ///
+    /// A matrix set with [`Self::set_local_matrix`] is discarded in favour of the transform.
+    pub fn local_mut(&mut self, handle: NodeHandle) -> &mut Transform {
+        self.mark_dirty(handle);
+        let node = &mut self.nodes[handle.0];
+        node.local_matrix = None;
+        &mut node.local
+    }
+
+    /// Replaces the local transform with an arbitrary matrix.
+    pub fn set_local_matrix(&mut self, handle: NodeHandle, local: Matrix4<f32>) {
+        self.mark_dirty(handle);
+        self.nodes[handle.0].local_matrix = Some(local);
+    }
+
+    /// Moves `handle` below `parent`, or makes it a root if `parent` is `None`.
+    /// # Errors
+    /// If `parent` is `handle` itself or one of its descendants.
+    pub fn set_parent(
+        &mut self,
+        handle: NodeHandle,
+        parent: Option<NodeHandle>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = parent {
+            if self.is_ancestor_or_self(handle, parent) {
+                return Err("Can't parent a node to itself or one of its descendants".into());
+            }
+        }
+        if let Some(old) = self.nodes[handle.0].parent {
+            self.nodes[old.0].children.retain(|c| *c != handle);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent.0].children.push(handle);
+        }
+        self.nodes[handle.0].parent = parent;
+        self.mark_dirty(handle);
+        Ok(())
+    }
+
+    fn is_ancestor_or_self(&self, ancestor: NodeHandle, mut node: NodeHandle) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.nodes[node.0].parent {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    fn mark_dirty(&mut self, handle: NodeHandle) {
+        self.generation += 1;
+        let mut stack = vec![handle];
+        while let Some(h) = stack.pop() {
+            let node = &mut self.nodes[h.0];
+            // Descendants of a dirty node are already dirty.
+            if node.dirty && h != handle {
+                continue;
+            }
+            node.dirty = true;
+            stack.extend_from_slice(&node.children);
+        }
+    }
+
+    /// Returns the node's world matrix, recomputing it and its ancestors if they are dirty.
+    pub fn world_matrix(&mut self, handle: NodeHandle) -> Matrix4<f32> {
+        let node = &self.nodes[handle.0];
+        if !node.dirty {
+            return node.world;
+        }
+        let parent = node
+            .parent
+            .map_or_else(Matrix4::identity, |p| self.world_matrix(p));
+        let node = &mut self.nodes[handle.0];
+        node.world = parent * node.local_matrix.unwrap_or_else(|| node.local.mat());
+        node.dirty = false;
+        node.world
+    }
+
+    /// Recomputes the world matrices of all dirty nodes.
+    pub fn update(&mut self) {
+        for i in 0..self.nodes.len() {
+            self.world_matrix(NodeHandle(i));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Vector3, Vector4};
+
+    fn translated(x: f32, y: f32, z: f32) -> Transform {
+        Transform { position: Vector3::new(x, y, z), ..Transform::default() }
+    }
+
+    fn origin_of(matrix: Matrix4<f32>) -> Vector3<f32> {
+        (matrix * Vector4::new(0.0, 0.0, 0.0, 1.0)).truncate()
+    }
+
+    #[test]
+    fn world_matrices_compose_parent_first() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform { scale: Vector3::new(2.0, 2.0, 2.0), ..Transform::default() });
+        let child = graph.add_node(Some(root), translated(1.0, 0.0, 0.0));
+        let grandchild = graph.add_matrix_node(Some(child), None, Matrix4::from_translation(Vector3::new(0.0, 3.0, 0.0)));
+        // The root's scale applies to the translations below it.
+        assert_eq!(origin_of(graph.world_matrix(child)), Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(origin_of(graph.world_matrix(grandchild)), Vector3::new(2.0, 6.0, 0.0));
+    }
+
+    #[test]
+    fn changes_propagate_to_descendants() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::default());
+        let child = graph.add_node(Some(root), translated(1.0, 0.0, 0.0));
+        let grandchild = graph.add_node(Some(child), translated(0.0, 1.0, 0.0));
+        let other = graph.add_node(None, Transform::default());
+        graph.update();
+        assert!(graph.nodes.iter().all(|n| !n.dirty));
+        let generation = graph.generation();
+        graph.update();
+        assert_eq!(graph.generation(), generation);
+
+        graph.local_mut(root).position = Vector3::new(0.0, 0.0, 5.0);
+        assert!(graph.generation() > generation);
+        assert!(graph.node(root).dirty && graph.node(child).dirty && graph.node(grandchild).dirty);
+        assert!(!graph.node(other).dirty);
+        assert_eq!(origin_of(graph.world_matrix(grandchild)), Vector3::new(1.0, 1.0, 5.0));
+
+        // Marking a node whose subtree is already dirty still reaches every descendant.
+        graph.local_mut(child).position = Vector3::new(2.0, 0.0, 0.0);
+        graph.set_local_matrix(root, Matrix4::identity());
+        assert!(graph.node(grandchild).dirty);
+        assert_eq!(origin_of(graph.world_matrix(grandchild)), Vector3::new(2.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn reparenting_moves_the_subtree() {
+        let mut graph = SceneGraph::new();
+        let a = graph.add_node(None, translated(1.0, 0.0, 0.0));
+        let b = graph.add_node(None, translated(0.0, 2.0, 0.0));
+        let child = graph.add_node(Some(a), translated(0.0, 0.0, 3.0));
+        graph.update();
+
+        graph.set_parent(child, Some(b)).unwrap();
+        assert!(graph.node(a).children().is_empty());
+        assert_eq!(graph.node(b).children(), &[child]);
+        assert_eq!(origin_of(graph.world_matrix(child)), Vector3::new(0.0, 2.0, 3.0));
+
+        graph.set_parent(child, None).unwrap();
+        assert_eq!(graph.roots().count(), 3);
+        assert_eq!(origin_of(graph.world_matrix(child)), Vector3::new(0.0, 0.0, 3.0));
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut graph = SceneGraph::new();
+        let root = graph.add_node(None, Transform::default());
+        let child = graph.add_node(Some(root), Transform::default());
+        let grandchild = graph.add_node(Some(child), Transform::default());
+        assert!(graph.set_parent(root, Some(root)).is_err());
+        assert!(graph.set_parent(root, Some(grandchild)).is_err());
+        assert!(graph.set_parent(child, Some(grandchild)).is_err());
+        // The failed calls left the hierarchy untouched.
+        assert_eq!(graph.node(root).parent(), None);
+        assert_eq!(graph.node(child).parent(), Some(root));
+        assert_eq!(graph.node(grandchild).children(), &[] as &[NodeHandle]);
+    }
+}
@@ -1,10 +1,11 @@
 use cgmath::num_traits::AsPrimitive;
 use paste::paste;
 use crate::glutil::GLType;
+use crate::lighting::{world_block, Light};
 use crate::util::{find_gl_error, load_file, GLFunctionError};
 use alloc::rc::Rc;
 use bytemuck::{bytes_of, cast_slice, from_bytes, try_cast_slice};
-use cgmath::{Array, Matrix, Matrix2, Matrix3, Matrix4, Vector3, Vector4};
+use cgmath::{Matrix, Matrix2, Matrix3, Matrix4, Vector3, Vector4};
 use core::slice::Iter;
 use gl::types::{GLenum, GLint, GLsizei, GLuint};
 use gl::{
@@ -13,7 +14,7 @@ use gl::{
 };
 use glfw::ffi::glfwGetTime;
 use image::{load_from_memory, open, DynamicImage};
-use log::{debug, trace};
+use log::{debug, trace, warn};
 use obj::raw::material::{Material, MtlColor};
 use obj::{TexturedVertex, Vertex};
 use std::cell::RefCell;
@@ -21,7 +22,7 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::ffi::{CStr, CString};
 use std::os::raw::c_char;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::ptr::null;
 
@@ -51,10 +52,82 @@ fn from_color(color: &Option<MtlColor>) -> Vec<f32> {
     }
     Vec::new()
 }
+
+/// MTL keys `obj-rs` doesn't read: the PBR extension, `norm` normal maps and `Tr` transparency.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MtlExtensions {
+    roughness: Option<f32>,
+    metallic: Option<f32>,
+    roughness_map: Option<String>,
+    metallic_map: Option<String>,
+    normal_map: Option<String>,
+    transparency: Option<f32>,
+}
+
+/// Reads the `MtlExtensions` of every material in an MTL file, by material name.
+pub(crate) fn parse_mtl_extensions(source: &str) -> HashMap<String, MtlExtensions> {
+    let mut ret: HashMap<String, MtlExtensions> = HashMap::new();
+    let mut current = None;
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((key, value)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let value = value.trim();
+        if key == "newmtl" {
+            current = Some(value.to_owned());
+            continue;
+        }
+        let Some(material) = current.as_ref().map(|name| ret.entry(name.clone()).or_default()) else {
+            continue;
+        };
+        match key {
+            "Pr" => material.roughness = value.parse().ok(),
+            "Pm" => material.metallic = value.parse().ok(),
+            "Tr" => material.transparency = value.parse().ok(),
+            "map_Pr" => material.roughness_map = Some(value.to_owned()),
+            "map_Pm" => material.metallic_map = Some(value.to_owned()),
+            "norm" | "map_Bump" | "map_bump" | "bump" => material.normal_map = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    ret
+}
+
+/// Resolves the file of an MTL texture map statement relative to `base_dir`.
+///
+/// Options such as `-bm 1.0` are skipped by taking the last word, so file names with spaces
+/// only work without options.
+fn mtl_map_path(base_dir: &Path, map: &str) -> PathBuf {
+    let file = if map.starts_with('-') {
+        map.split_whitespace().last().unwrap_or(map)
+    } else {
+        map.trim()
+    };
+    base_dir.join(file)
+}
+
+/// A texture if there is one, otherwise the value.
+fn texture_or<T>(texture: Option<DynamicImage>, value: Option<T>) -> Option<TextureOr<T>> {
+    texture.map(TextureOr::Texture).or_else(|| value.map(TextureOr::Value))
+}
+
+/// Loads an MTL texture map, logging a warning and returning `None` if it can't be read.
+fn load_mtl_map(base_dir: &Path, map: Option<&str>) -> Option<DynamicImage> {
+    let path = mtl_map_path(base_dir, map?);
+    open(&path)
+        .map_err(|e| warn!("Couldn't load texture {}: {e}", path.display()))
+        .ok()
+}
 // TODO: create a general structure for things like world buffers.
 pub struct ShaderManager {
     pub shaders: Vec<ShaderPtr>,
     pub world_buffer: u32,
+    pub ambient: Vector4<f32>,
+    /// Lights uploaded to the `World` block before each frame; at most `MAX_LIGHTS` are used.
+    pub lights: Vec<Light>,
+    /// The `World` block as last uploaded, to skip uploads when nothing changed.
+    uploaded_world: Vec<Vector4<f32>>,
 }
 impl Default for ShaderManager {
     fn default() -> Self {
@@ -67,42 +140,50 @@ impl ShaderManager {
     /// If a usize cannot be converted to an isize.
     #[must_use]
     pub fn new() -> Self {
+        let ambient = Vector4::new(0.0, 0.0, 0.0, 1.0);
         let mut ret = Self {
             shaders: Vec::default(),
             world_buffer: 0,
+            ambient,
+            lights: Vec::new(),
+            uploaded_world: world_block(ambient, &[]),
         };
+        let size = isize::try_from(size_of_val(ret.uploaded_world.as_slice())).unwrap();
         unsafe {
             gl::GenBuffers(1, &mut ret.world_buffer);
             gl::BindBuffer(UNIFORM_BUFFER, ret.world_buffer);
-            gl::BufferData(UNIFORM_BUFFER, 16, null(), gl::DYNAMIC_DRAW); // 1 * vec4
+            // ambient, light count, lights
+            gl::BufferData(UNIFORM_BUFFER, size, ret.uploaded_world.as_ptr().cast(), gl::DYNAMIC_DRAW);
             gl::BindBuffer(UNIFORM_BUFFER, 0); // release the buffer
 
-            gl::BindBufferRange(
-                UNIFORM_BUFFER,
-                1,
-                ret.world_buffer,
-                0,
-                isize::try_from(size_of::<Vector4<f32>>()).unwrap(),
-            );
+            gl::BindBufferRange(UNIFORM_BUFFER, 1, ret.world_buffer, 0, size);
         }
         ret
     }
-    /// # Errors
-    /// If the OpenGL function fails, it will return a `GLFunctionError`.
-    /// If the shader cannot be borrowed mutably, it will return a `Box<dyn Error>`.
+    /// Uploads `ambient` and `lights` to the `World` block if they changed since the last upload.
+    /// The engine calls this before rendering each frame.
     #[allow(clippy::cast_possible_wrap)]
-    pub fn update(&mut self) -> Result<(), Box<dyn Error>> {
-        let ambient: Vector4<f32> = Vector4::new(0.0, 0.0, 0.0, 1.0);
+    pub fn upload_world(&mut self) {
+        let block = world_block(self.ambient, &self.lights);
+        if block == self.uploaded_world {
+            return;
+        }
         unsafe {
             gl::BindBuffer(UNIFORM_BUFFER, self.world_buffer);
             gl::BufferSubData(
                 UNIFORM_BUFFER,
                 0,
-                size_of::<Vector4<f32>>() as isize,
-                ambient.as_ptr().cast(),
+                size_of_val(block.as_slice()) as isize,
+                block.as_ptr().cast(),
             );
             gl::BindBuffer(UNIFORM_BUFFER, 0);
         }
+        self.uploaded_world = block;
+    }
+    /// Recompiles the shaders whose sources changed on disk.
+    /// # Errors
+    /// If the shader cannot be borrowed mutably, it will return a `Box<dyn Error>`.
+    pub fn update(&mut self) -> Result<(), Box<dyn Error>> {
         for shader_ptr in self.iter() {
             shader_ptr.try_borrow_mut()?.try_runtime_recompile();
         }
@@ -475,6 +556,41 @@ impl Shader {
         }
     }
 
+    /// The active vertex inputs of the program as name, location and GLSL type.
+    #[allow(clippy::cast_sign_loss)]
+    pub(crate) fn active_attributes(&self) -> Vec<(String, GLint, GLenum)> {
+        let Some(program) = self.program else {
+            return Vec::new();
+        };
+        let mut count = 0;
+        unsafe {
+            gl::GetProgramiv(program, gl::ACTIVE_ATTRIBUTES, &mut count);
+        }
+        (0..count as u32)
+            .filter_map(|i| {
+                let mut name = [0u8; 256];
+                let (mut length, mut size, mut kind) = (0, 0, 0);
+                unsafe {
+                    gl::GetActiveAttrib(program, i, 256, &mut length, &mut size, &mut kind, name.as_mut_ptr().cast());
+                }
+                let name = String::from_utf8_lossy(&name[..length as usize]).into_owned();
+                let c_name = CString::new(name.clone()).ok()?;
+                let location = unsafe { gl::GetAttribLocation(program, c_name.as_ptr()) };
+                Some((name, location, kind))
+            })
+            .collect()
+    }
+    /// The scalar material value `name` the shader was compiled with, e.g. `specular`.
+    #[must_use]
+    pub fn value(&self, name: &str) -> Option<f32> {
+        self.values.get(name).copied()
+    }
+    /// The vector material value `name` the shader was compiled with, e.g. `diffuse`.
+    #[must_use]
+    pub fn vector_value(&self, name: &str) -> Option<&[f32]> {
+        self.vector_values.get(name).map(Vec::as_slice)
+    }
+
     fn load_cached_uniforms(&self) -> Result<(), String> {
         println!("{:?}", self.cache);
         for (k, v) in &self.cache {
@@ -506,6 +622,7 @@ impl Shader {
         }
     }
 }
+#[derive(Clone)]
 pub enum TextureOr<T> {
     Texture(DynamicImage),
     Value(T)
@@ -513,48 +630,60 @@ pub enum TextureOr<T> {
 pub type TextureOrColor = TextureOr<[f32; 4]>;
 pub type TextureOrScalar = TextureOr<f32>;
 
+/// Name of the tangent-space normal map texture, sampled by shaders compiled with
+/// `NORMAL_MAP_TEXTURE`. Meshes drawn with it need tangents.
+pub const NORMAL_MAP: &str = "normal_map";
+
+#[derive(Clone, Default)]
 pub struct NarrowingMaterial {
     pub diffuse: Option<TextureOrColor>,
     pub emissive: Option<TextureOrColor>,
     pub specular: Option<TextureOrScalar>,
+    /// Phong specular exponent.
+    pub shininess: Option<f32>,
+    /// 1 for opaque, 0 for fully transparent.
+    pub opacity: Option<TextureOrScalar>,
+    /// 0 for dielectrics, 1 for metals.
     pub metallic: Option<TextureOrScalar>,
+    /// Perceptual roughness from 0 to 1; replaces `shininess` when set.
     pub roughness: Option<TextureOrScalar>,
     pub ambient_scaling: Option<TextureOrScalar>,
     pub normal: Option<TextureOrScalar>,
 }
 
 impl NarrowingMaterial {
-    /// # Panics
-    /// If the material cannot be created from the obj material.
-    pub(crate) fn from_obj_mtl(mtl: &Material) -> Self {
-        let mut ret = Self {
-            diffuse: None,
-            emissive: None,
-            specular: None,
-            metallic: None,
-            roughness: None,
-            ambient_scaling: None,
-            normal: None,
+    /// Creates a material from an MTL material, with texture maps resolved relative to `base_dir`.
+    ///
+    /// Texture maps take precedence over the matching constant. Maps that can't be loaded are
+    /// skipped with a warning.
+    pub(crate) fn from_obj_mtl(mtl: &Material, extensions: Option<&MtlExtensions>, base_dir: &Path) -> Self {
+        let extensions = extensions.cloned().unwrap_or_default();
+        let map = |map: Option<&String>| load_mtl_map(base_dir, map.map(String::as_str));
+        let color = |color: &Option<MtlColor>| {
+            let c = from_color(color);
+            (c.len() == 3).then(|| [c[0], c[1], c[2], 1.0])
         };
-        if mtl.diffuse.is_some() {
-            let diff = from_color(&mtl.diffuse);
-            let new_diff = [diff[0], diff[1], diff[2], 1.0];
-            ret.diffuse = Some(TextureOrColor::Value(new_diff));
-        }
-        if mtl.emissive.is_some() {
-            let emis = from_color(&mtl.emissive);
-            let new_emis = [emis[0], emis[1], emis[2], 1.0];
-            ret.emissive = Some(TextureOrColor::Value(new_emis));
-        }
-        if mtl.optical_density.is_some() {
-            let specular = ((mtl.optical_density.unwrap() - 1.0)
-                / (mtl.optical_density.unwrap() + 1.0))
-                .powi(2)
-                / 0.08;
-            ret.specular = Some(TextureOrScalar::Value(specular));
+
+        let specular_color = color(&mtl.specular).map(|c| (c[0] + c[1] + c[2]) / 3.0);
+        // Without Ks, approximate the specular strength from the index of refraction.
+        let fresnel = mtl.optical_density.map(|ior| ((ior - 1.0) / (ior + 1.0)).powi(2) / 0.08);
+        Self {
+            diffuse: texture_or(map(mtl.diffuse_map.as_ref().map(|m| &m.file)), color(&mtl.diffuse)),
+            emissive: texture_or(map(mtl.emissive_map.as_ref().map(|m| &m.file)), color(&mtl.emissive)),
+            specular: texture_or(
+                map(mtl.specular_map.as_ref().map(|m| &m.file)),
+                specular_color.or(fresnel),
+            ),
+            shininess: mtl.specular_exponent,
+            opacity: texture_or(
+                map(mtl.dissolve_map.as_ref().map(|m| &m.file)),
+                mtl.dissolve.or_else(|| extensions.transparency.map(|t| 1.0 - t)),
+            ),
+            metallic: texture_or(map(extensions.metallic_map.as_ref()), extensions.metallic),
+            roughness: texture_or(map(extensions.roughness_map.as_ref()), extensions.roughness),
+            ambient_scaling: None,
+            normal: map(mtl.bump_map.as_ref().map(|m| &m.file).or(extensions.normal_map.as_ref())).map(TextureOr::Texture),
         }
-        ret
-        // todo: provide waay better support for this.
     }
     /// # Errors
     /// Returns an error if the material cannot be created from the glTF material.
@@ -593,19 +722,29 @@ impl NarrowingMaterial {
             roughness: None,
             ambient_scaling: None,
             normal: None,
+            shininess: None,
+            opacity: None,
         };
         ret.diffuse = Some(texture_or_factor!(material.pbr_metallic_roughness().base_color_texture(), material.pbr_metallic_roughness().base_color_factor()));
         ret.emissive = Some(texture_or_factor!(material.emissive_texture(), [material.emissive_factor()[0], material.emissive_factor()[1], material.emissive_factor()[2], 1.0]));
         if let Some(spec) = material.specular() {
             ret.specular = Some(texture_or_factor!(spec.specular_texture(), spec.specular_factor()));
         }
-        ret.metallic = Some(TextureOrScalar::Value(material.pbr_metallic_roughness().metallic_factor()));
-        ret.roughness = Some(TextureOrScalar::Value(material.pbr_metallic_roughness().roughness_factor()));
+        // glTF's metallic and roughness factors default to 1 and are meant for a PBR model the
+        // base shader doesn't implement, so they are left out to keep Phong shading.
         ret.ambient_scaling = Some(TextureOrScalar::Value(1.0)); // todo: maybe change this? provide parameter?
         ret.normal = Some(texture_or_factor!(material.normal_texture(), 1.0));
         Ok(ret)
     }
     pub(crate) fn with_path(self, base_path: &str) -> Result<Shader, Box<dyn Error>> {
+        self.with_path_defines(base_path, &[])
+    }
+    /// Like `with_path`, but also `#define`s each of `defines` to select shader variants.
+    pub(crate) fn with_path_defines(
+        self,
+        base_path: &str,
+        defines: &[&str],
+    ) -> Result<Shader, Box<dyn Error>> {
         let vert_string = base_path.to_owned() + ".vert";
         let vert_source = load_file(vert_string).to_str()?.to_owned();
         let frag_string = base_path.to_owned() + ".frag";
@@ -615,15 +754,26 @@ impl NarrowingMaterial {
             CString::new(frag_source.clone())?,
             CString::new("")?,
         ];
-        let mut ret = self.into_shader(vert_source, frag_source)?;
+        let mut ret = self.into_shader_with_defines(vert_source, frag_source, defines)?;
         ret.debug_sources.extend(debug_sources);
         ret.path = Some(base_path.to_string());
         Ok(ret)
     }
     pub fn into_shader(
+        self,
+        vert_source: String,
+        frag_source: String,
+    ) -> Result<Shader, Box<dyn Error>> {
+        self.into_shader_with_defines(vert_source, frag_source, &[])
+    }
+    /// Compiles the material into a shader, `#define`ing each of `defines` in both stages.
+    /// # Errors
+    /// If the shader fails to compile or a material value cannot be set.
+    pub fn into_shader_with_defines(
         self,
         mut vert_source: String,
         mut frag_source: String,
+        defines: &[&str],
     ) -> Result<Shader, Box<dyn Error>> {
         let mut ret = Shader {
             path: None,
@@ -639,6 +789,20 @@ impl NarrowingMaterial {
         ret.insert_texture_or_color(&self.diffuse, "diffuse", TextureOr::Value([0.5;4]));
         ret.insert_texture_or_scalar(&self.specular, "specular", TextureOr::Value(1.0));
         ret.insert_texture_or_color(&self.emissive, "emissive", TextureOr::Value([0.0;4]));
+        ret.insert_texture_or_scalar(&self.opacity, "opacity", TextureOr::Value(1.0));
+        // Without them the shader falls back to plain Phong with `specular_exponent`.
+        if self.metallic.is_some() {
+            ret.insert_texture_or_scalar(&self.metallic, "metallic", TextureOr::Value(0.0));
+        }
+        if self.roughness.is_some() {
+            ret.insert_texture_or_scalar(&self.roughness, "roughness", TextureOr::Value(1.0));
+        }
+        if let Some(TextureOr::Texture(image)) = &self.normal {
+            ret.textures.insert(NORMAL_MAP.to_owned(), Shader::create_image_texture(image.clone()));
+        }
+        if let Some(shininess) = self.shininess {
+            ret.values.insert("specular_exponent".to_owned(), shininess);
+        }
 
         if !ret.textures.is_empty() {
             let fmt_str = "#define TEXTURES 1\n";
@@ -650,6 +814,11 @@ impl NarrowingMaterial {
             vert_source.insert_str(vert_source.find('\n').unwrap()+1, fmt_str.as_str());
             frag_source.insert_str(frag_source.find('\n').unwrap()+1, fmt_str.as_str());
         }
+        for i in defines {
+            let fmt_str = format!("#define {i} 1\n");
+            vert_source.insert_str(vert_source.find('\n').unwrap()+1, fmt_str.as_str());
+            frag_source.insert_str(frag_source.find('\n').unwrap()+1, fmt_str.as_str());
+        }
         let pretty_frag_source: String = frag_source.split('\n').enumerate().map(|(x, i)| {format!("\n\x1b[36m {x:01}\x1b[39m\x1b[49m: {i}")}).collect();
         let pretty_vert_source: String = vert_source.split('\n').enumerate().map(|(x, i)| {format!("\n\x1b[36m {x:01}\x1b[39m\x1b[49m: {i}")}).collect();
         debug!("frag_source: {pretty_frag_source}");
@@ -670,6 +839,11 @@ impl NarrowingMaterial {
             let os = i.clone();
             ret.set(v, os.as_str())?;
         }
+        // `use_textures` binds the textures to consecutive units in iteration order.
+        let samplers: Vec<String> = ret.textures.keys().cloned().collect();
+        for (unit, name) in samplers.iter().enumerate() {
+            ret.set(i32::try_from(unit)?, name)?;
+        }
         Shader::clear_shader();
         ret.check_optionals();
         Ok(ret)
@@ -828,3 +1002,28 @@ vector_matrix_value!(1, f32, GLType::VecFloat, fv);
 vector_matrix_value!(2, f32, GLType::VecFloat, fv);
 vector_matrix_value!(3, f32, GLType::VecFloat, fv);
 vector_matrix_value!(4, f32, GLType::VecFloat, fv);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_gltf_material_keeps_its_diffuse_term() {
+        let gltf = gltf::Gltf::from_slice(br#"{"asset": {"version": "2.0"}, "materials": [{}]}"#).unwrap();
+        let material = gltf.materials().next().unwrap();
+        let material = NarrowingMaterial::from_gltf_mtl(&material, &[], &[], "").unwrap();
+        // Without metallic, the shader's default of 0 keeps the whole base color as albedo.
+        assert!(material.metallic.is_none());
+        assert!(material.roughness.is_none());
+        assert!(matches!(material.diffuse, Some(TextureOr::Value([1.0, 1.0, 1.0, 1.0]))));
+    }
+
+    #[test]
+    fn mtl_pbr_keys_are_only_set_when_present() {
+        let extensions = parse_mtl_extensions("newmtl metal\nPm 1\nPr 0.25\nnewmtl plastic\nKd 1 0 0\n");
+        assert_eq!(extensions["metal"].metallic, Some(1.0));
+        assert_eq!(extensions["metal"].roughness, Some(0.25));
+        assert_eq!(extensions["plastic"].metallic, None);
+        assert_eq!(extensions["plastic"].roughness, None);
+    }
+}
@@ -0,0 +1,303 @@
+//! Reducing the triangle count of meshes with quadric error metrics (Garland and Heckbert).
+//!
+//! Edges are collapsed into one of their vertices, cheapest first, so the remaining vertices
+//! keep their attributes unchanged and the result indexes into the original vertex data.
+use cgmath::{InnerSpace, Vector3};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// How much more moving a vertex away from an open border costs than away from a surface.
+const BOUNDARY_WEIGHT: f64 = 10.0;
+
+/// Minimum cosine of the angle a triangle's normal may turn by in one collapse.
+const MAX_FLIP: f64 = 0.2;
+
+/// The symmetric 4x4 matrix of a sum of squared distances to planes, upper triangle only.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Squared distances to the plane `normal · p + d = 0`, times `weight`.
+    fn plane(normal: Vector3<f64>, d: f64, weight: f64) -> Self {
+        let [a, b, c] = [normal.x, normal.y, normal.z];
+        Self([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|v| v * weight))
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a += b;
+        }
+    }
+
+    /// The summed squared distance of `point` to the planes, `pᵀ Q p` for `p = (point, 1)`.
+    fn error(&self, point: Vector3<f64>) -> f64 {
+        let q = &self.0;
+        let p = [point.x, point.y, point.z, 1.0];
+        let dot = |row: [f64; 4]| row.iter().zip(p).map(|(a, b)| a * b).sum::<f64>();
+        let rows = [
+            [q[0], q[1], q[2], q[3]],
+            [q[1], q[4], q[5], q[6]],
+            [q[2], q[5], q[7], q[8]],
+            [q[3], q[6], q[8], q[9]],
+        ];
+        dot(rows.map(dot)).max(0.0)
+    }
+}
+
+/// A possible collapse of `from` into `to`, valid while both vertices have the recorded versions.
+#[derive(Clone, Copy, Debug)]
+struct Collapse {
+    cost: f64,
+    from: u32,
+    to: u32,
+    versions: (u32, u32),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Collapse {}
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cost
+            .total_cmp(&other.cost)
+            .then(self.from.cmp(&other.from))
+            .then(self.to.cmp(&other.to))
+    }
+}
+
+/// Simplifies a triangle list until it has at most `target_triangles` triangles or any further
+/// collapse would move the surface by more than `target_error`.
+///
+/// Returns the remaining triangles, indexing into `positions`, and the largest error made: the
+/// root of the summed squared distances of a removed vertex to its original planes.
+///
+/// Vertices at the same position, such as along texture seams or hard edges, are welded for the
+/// collapses so they move together and the surface can't tear; triangles with two corners at the
+/// same position are dropped. Each corner keeps its own vertex: when its position collapses, it
+/// takes the vertex the collapsed triangles used on its side of the edge, or else the vertex at
+/// the new position whose triangles face the most alike among those `compatible` with it.
+/// Collapses that leave a corner without a compatible vertex are skipped, so e.g. texture seams
+/// only move along themselves. Open borders are preserved where possible.
+#[must_use]
+#[allow(clippy::cast_possible_truncation, clippy::too_many_lines)]
+pub fn simplify(
+    positions: &[Vector3<f32>],
+    indices: &[u32],
+    target_triangles: usize,
+    target_error: f32,
+    compatible: impl Fn(u32, u32) -> bool,
+) -> (Vec<u32>, f32) {
+    let position = |v: u32| positions[v as usize].cast::<f64>().unwrap_or_else(|| Vector3::new(0.0, 0.0, 0.0));
+    // The first vertex at each position stands in for all of them.
+    let mut first_at: HashMap<[u32; 3], u32> = HashMap::new();
+    let welded: Vec<u32> = positions
+        .iter()
+        .enumerate()
+        .map(|(v, p)| *first_at.entry([p.x, p.y, p.z].map(|c| (c + 0.0).to_bits())).or_insert(v as u32))
+        .collect();
+    // The original vertices of each triangle's corners, and the welded ones the collapses use.
+    let mut corners: Vec<[u32; 3]> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut triangles: Vec<[u32; 3]> = corners.iter().map(|t| t.map(|v| welded[v as usize])).collect();
+    let mut alive: Vec<bool> = triangles.iter().map(|&[a, b, c]| a != b && b != c && a != c).collect();
+    let mut remaining = alive.iter().filter(|a| **a).count();
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); positions.len()];
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    let mut edges: HashMap<(u32, u32), (usize, u32)> = HashMap::new();
+    for (t, &[a, b, c]) in triangles.iter().enumerate().filter(|(t, _)| alive[*t]) {
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+        let plane = if normal.magnitude2() > 0.0 {
+            let normal = normal.normalize();
+            Quadric::plane(normal, -normal.dot(position(a)), 1.0)
+        } else {
+            Quadric::default()
+        };
+        for (v, (next, other)) in [a, b, c].into_iter().zip([(b, c), (c, a), (a, b)]) {
+            vertex_triangles[v as usize].push(t);
+            quadrics[v as usize].add(&plane);
+            let key = (v.min(next), v.max(next));
+            edges.entry(key).or_insert((0, other)).0 += 1;
+        }
+    }
+    // Planes through open edges, perpendicular to their triangle, keep borders in place.
+    for (&(a, b), &(count, other)) in &edges {
+        if count != 1 {
+            continue;
+        }
+        let edge = position(b) - position(a);
+        let normal = edge.cross(edge.cross(position(other) - position(a)));
+        if normal.magnitude2() > 0.0 {
+            let normal = normal.normalize();
+            let plane = Quadric::plane(normal, -normal.dot(position(a)), BOUNDARY_WEIGHT * edge.magnitude2());
+            quadrics[a as usize].add(&plane);
+            quadrics[b as usize].add(&plane);
+        }
+    }
+    let face_normal = |[a, b, c]: [u32; 3]| {
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+        if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            normal
+        }
+    };
+
+    let mut versions = vec![0u32; positions.len()];
+    let mut heap = BinaryHeap::new();
+    let candidate = |from: u32, to: u32, quadrics: &[Quadric], versions: &[u32]| {
+        let mut quadric = quadrics[from as usize];
+        quadric.add(&quadrics[to as usize]);
+        Reverse(Collapse {
+            cost: quadric.error(position(to)),
+            from,
+            to,
+            versions: (versions[from as usize], versions[to as usize]),
+        })
+    };
+    for &(a, b) in edges.keys() {
+        heap.push(candidate(a, b, &quadrics, &versions));
+        heap.push(candidate(b, a, &quadrics, &versions));
+    }
+
+    let max_cost = f64::from(target_error) * f64::from(target_error);
+    let mut error: f64 = 0.0;
+    while remaining > target_triangles {
+        let Some(Reverse(collapse)) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (collapse.from as usize, collapse.to as usize);
+        if collapse.versions != (versions[from], versions[to]) {
+            continue;
+        }
+        if collapse.cost > max_cost {
+            break;
+        }
+        let shared: Vec<usize> = around(&vertex_triangles[from], &alive)
+            .filter(|&t| triangles[t].contains(&collapse.to))
+            .collect();
+        if shared.is_empty() {
+            continue;
+        }
+        // Link condition: the only neighbours both vertices share are the ones across the
+        // collapsed edge, otherwise the surface would pinch.
+        let link = |v: usize| neighbours(v, &vertex_triangles[v], &triangles, &alive);
+        if link(from).intersection(&link(to)).count() != shared.len() {
+            continue;
+        }
+        let flips = around(&vertex_triangles[from], &alive).filter(|t| !shared.contains(t)).any(|t| {
+            let corners = triangles[t].map(position);
+            let moved = triangles[t].map(|v| if v == collapse.from { position(collapse.to) } else { position(v) });
+            let before = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+            let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+            after.magnitude2() == 0.0 || before.dot(after) <= MAX_FLIP * before.magnitude() * after.magnitude()
+        });
+        if flips {
+            continue;
+        }
+        // The vertex each moved corner switches to, or None if one has no compatible vertex.
+        let switched: Option<Vec<(usize, usize, u32)>> = around(&vertex_triangles[from], &alive)
+            .filter(|t| !shared.contains(t))
+            .map(|t| {
+                let k = triangles[t].iter().position(|&v| v == collapse.from)?;
+                let own = corners[t][k];
+                let across = shared.iter().filter(|&&s| corners[s].contains(&own)).find_map(|&s| {
+                    triangles[s].iter().position(|&v| v == collapse.to).map(|j| corners[s][j])
+                });
+                let normal = face_normal(triangles[t]);
+                let closest = || {
+                    around(&vertex_triangles[to], &alive)
+                        .filter_map(|other| {
+                            let j = triangles[other].iter().position(|&v| v == collapse.to)?;
+                            let vertex = corners[other][j];
+                            compatible(own, vertex).then(|| (face_normal(triangles[other]).dot(normal), vertex))
+                        })
+                        .max_by(|a, b| a.0.total_cmp(&b.0))
+                        .map(|(_, vertex)| vertex)
+                };
+                Some((t, k, across.or_else(closest)?))
+            })
+            .collect();
+        let Some(switched) = switched else {
+            continue;
+        };
+
+        for &t in &shared {
+            alive[t] = false;
+            remaining -= 1;
+        }
+        for (t, k, vertex) in switched {
+            triangles[t][k] = collapse.to;
+            corners[t][k] = vertex;
+            vertex_triangles[to].push(t);
+        }
+        let removed = quadrics[from];
+        quadrics[to].add(&removed);
+        versions[from] += 1;
+        versions[to] += 1;
+        error = error.max(collapse.cost);
+        for neighbour in neighbours(to, &vertex_triangles[to], &triangles, &alive) {
+            heap.push(candidate(collapse.to, neighbour, &quadrics, &versions));
+            heap.push(candidate(neighbour, collapse.to, &quadrics, &versions));
+        }
+    }
+    let indices = corners
+        .iter()
+        .zip(&alive)
+        .filter(|(_, alive)| **alive)
+        .flat_map(|(t, _)| *t)
+        .collect();
+    (indices, error.sqrt() as f32)
+}
+
+/// The triangles of `of` that haven't been collapsed.
+fn around<'a>(of: &'a [usize], alive: &'a [bool]) -> impl Iterator<Item = usize> + 'a {
+    of.iter().copied().filter(|&t| alive[t])
+}
+
+/// The vertices sharing a remaining triangle with `vertex`.
+fn neighbours(vertex: usize, of: &[usize], triangles: &[[u32; 3]], alive: &[bool]) -> HashSet<u32> {
+    around(of, alive)
+        .flat_map(|t| triangles[t])
+        .filter(|&n| n as usize != vertex)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitives;
+
+    #[test]
+    fn triangle_soups_simplify() {
+        let sphere = primitives::uv_sphere(1.0, 32, 16);
+        // Every triangle with its own three vertices, as in STL files or flat shaded meshes.
+        let positions: Vec<_> = sphere.indices.iter().map(|&i| sphere.vertices[i as usize]).collect();
+        let indices: Vec<u32> = (0..positions.len() as u32).collect();
+        let target = indices.len() / 3 / 4;
+        let (simplified, error) = simplify(&positions, &indices, target, f32::INFINITY, |_, _| true);
+        assert!(simplified.len() / 3 <= target, "{} triangles left", simplified.len() / 3);
+        assert!(error > 0.0 && error < 0.5);
+    }
+
+    #[test]
+    fn texture_seams_stay_in_place() {
+        let sphere = primitives::uv_sphere(1.0, 32, 16);
+        let same_uv = |a: u32, b: u32| sphere.tex_coords[a as usize] == sphere.tex_coords[b as usize];
+        let target = sphere.indices.len() / 3 / 4;
+        let (simplified, _) = simplify(&sphere.vertices, &sphere.indices, target, f32::INFINITY, same_uv);
+        assert!(simplified.len() / 3 <= target * 2, "{} triangles left", simplified.len() / 3);
+        // A triangle switched across the seam would span most of the texture.
+        for triangle in simplified.chunks_exact(3) {
+            let us = triangle.iter().map(|&v| sphere.tex_coords[v as usize].x);
+            let (min, max) = us.fold((f32::MAX, f32::MIN), |(min, max), u| (min.min(u), max.max(u)));
+            assert!(max - min < 0.5, "triangle {triangle:?} wraps around the texture");
+        }
+    }
+}
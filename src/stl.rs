@@ -0,0 +1,299 @@
+//! Reading and writing STL files, the triangle soup format used by CAD and slicing software.
+//!
+//! Both the binary and the ASCII variant are supported. STL stores every triangle with its own
+//! three corners, so vertices are welded when reading.
+use cgmath::{InnerSpace, Vector3};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+/// A facet normal and its three corners.
+type Facet = (Vector3<f32>, [Vector3<f32>; 3]);
+
+/// An indexed triangle mesh read from or written to an STL file.
+#[derive(Clone, Debug, Default)]
+pub struct StlMesh {
+    pub positions: Vec<Vector3<f32>>,
+    /// The normal of the facet each vertex belongs to.
+    pub normals: Vec<Vector3<f32>>,
+    pub indices: Vec<u32>,
+}
+
+impl StlMesh {
+    /// Reads an STL file from disk.
+    /// # Errors
+    /// If the file cannot be opened or isn't a valid STL file.
+    pub fn open(path: &str) -> Result<Self, Box<dyn Error>> {
+        let file = File::open(path).map_err(|e| format!("Couldn't open file {path}: {e}"))?;
+        Self::read(BufReader::new(file)).map_err(|e| format!("Couldn't parse stl {path}: {e}").into())
+    }
+
+    /// Reads STL data, detecting whether it is binary or ASCII.
+    ///
+    /// Corners with the same position and facet normal become one vertex, so flat regions share
+    /// vertices while edges stay sharp. Facets with a zero normal get one computed from their
+    /// winding.
+    /// # Errors
+    /// If the data is neither a valid binary nor a valid ASCII STL file.
+    pub fn read(mut reader: impl Read) -> Result<Self, Box<dyn Error>> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let mut ret = Self::default();
+        let mut welded = HashMap::new();
+        // Binary files may also start with "solid", so the size is checked first.
+        if Self::is_binary(&data) {
+            for facet in data[84..].chunks_exact(50) {
+                let mut floats = facet[..48]
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+                let mut vector = || Vector3::new(floats.next().unwrap_or(0.0), floats.next().unwrap_or(0.0), floats.next().unwrap_or(0.0));
+                let normal = vector();
+                let corners = [vector(), vector(), vector()];
+                ret.push_facet(normal, &corners, &mut welded)?;
+            }
+        } else {
+            ret.read_ascii(std::str::from_utf8(&data).map_err(|_| "Not an STL file")?, &mut welded)?;
+        }
+        Ok(ret)
+    }
+
+    /// Writes the mesh to `path`.
+    /// # Errors
+    /// If the file cannot be written.
+    pub fn save(&self, path: &str, format: StlFormat) -> Result<(), Box<dyn Error>> {
+        let file = File::create(path).map_err(|e| format!("Couldn't create file {path}: {e}"))?;
+        let mut writer = BufWriter::new(file);
+        self.write(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the mesh as STL. Facet normals are computed from the triangles' winding.
+    /// # Errors
+    /// If writing fails, an index is out of range or a binary file would have more than
+    /// `u32::MAX` triangles.
+    pub fn write(&self, mut writer: impl Write, format: StlFormat) -> Result<(), Box<dyn Error>> {
+        // Checked up front so nothing is written for invalid meshes.
+        let triangles = self.triangles()?;
+        match format {
+            StlFormat::Ascii => {
+                writeln!(writer, "solid mesh")?;
+                for (normal, corners) in triangles {
+                    writeln!(writer, "  facet normal {:e} {:e} {:e}", normal.x, normal.y, normal.z)?;
+                    writeln!(writer, "    outer loop")?;
+                    for corner in corners {
+                        writeln!(writer, "      vertex {:e} {:e} {:e}", corner.x, corner.y, corner.z)?;
+                    }
+                    writeln!(writer, "    endloop")?;
+                    writeln!(writer, "  endfacet")?;
+                }
+                writeln!(writer, "endsolid mesh")?;
+            }
+            StlFormat::Binary => {
+                let mut header = [0u8; 80];
+                header[..14].copy_from_slice(b"binary stl    ");
+                writer.write_all(&header)?;
+                writer.write_all(&u32::try_from(self.indices.len() / 3)?.to_le_bytes())?;
+                for (normal, corners) in triangles {
+                    for vector in [normal, corners[0], corners[1], corners[2]] {
+                        for value in [vector.x, vector.y, vector.z] {
+                            writer.write_all(&value.to_le_bytes())?;
+                        }
+                    }
+                    // Attribute byte count, unused.
+                    writer.write_all(&[0, 0])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn is_binary(data: &[u8]) -> bool {
+        data.len() >= 84
+            && u32::from_le_bytes([data[80], data[81], data[82], data[83]]) as usize * 50 + 84 == data.len()
+    }
+
+    fn read_ascii(&mut self, text: &str, welded: &mut HashMap<[u32; 6], u32>) -> Result<(), Box<dyn Error>> {
+        let mut tokens = text.split_ascii_whitespace();
+        if tokens.next() != Some("solid") {
+            return Err("Not an STL file".into());
+        }
+        let vector = |tokens: &mut std::str::SplitAsciiWhitespace| -> Result<Vector3<f32>, Box<dyn Error>> {
+            let mut next = || -> Result<f32, Box<dyn Error>> {
+                Ok(tokens.next().ok_or("Unexpected end of STL data")?.parse()?)
+            };
+            Ok(Vector3::new(next()?, next()?, next()?))
+        };
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        let mut corners = Vec::with_capacity(3);
+        while let Some(token) = tokens.next() {
+            match token {
+                "normal" => normal = vector(&mut tokens)?,
+                "vertex" => corners.push(vector(&mut tokens)?),
+                "endfacet" => {
+                    self.push_facet(normal, &corners, welded)?;
+                    corners.clear();
+                }
+                // Solid names, "facet", "outer loop", "endloop" and "endsolid".
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a facet, triangulated as a fan if it has more than three corners.
+    fn push_facet(
+        &mut self,
+        normal: Vector3<f32>,
+        corners: &[Vector3<f32>],
+        welded: &mut HashMap<[u32; 6], u32>,
+    ) -> Result<(), Box<dyn Error>> {
+        if corners.len() < 3 {
+            return Err("STL facet has fewer than three vertices".into());
+        }
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            face_normal(corners[0], corners[1], corners[2])
+        };
+        let mut ids = Vec::with_capacity(corners.len());
+        for corner in corners {
+            // Adding 0.0 turns -0.0 into 0.0 so both weld together.
+            let key = [corner.x, corner.y, corner.z, normal.x, normal.y, normal.z].map(|v| (v + 0.0).to_bits());
+            let id = match welded.entry(key) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => {
+                    let id = *entry.insert(u32::try_from(self.positions.len())?);
+                    self.positions.push(*corner);
+                    self.normals.push(normal);
+                    id
+                }
+            };
+            ids.push(id);
+        }
+        for i in 1..ids.len() - 1 {
+            self.indices.extend([ids[0], ids[i], ids[i + 1]]);
+        }
+        Ok(())
+    }
+
+    /// Each triangle's normal and corners.
+    /// # Errors
+    /// If an index is out of range.
+    fn triangles(&self) -> Result<Vec<Facet>, Box<dyn Error>> {
+        self.indices
+            .chunks_exact(3)
+            .map(|t| {
+                let corner = |i: u32| {
+                    self.positions.get(i as usize).copied().ok_or_else(|| {
+                        format!("Index {i} is out of range for {} positions", self.positions.len())
+                    })
+                };
+                let corners = [corner(t[0])?, corner(t[1])?, corner(t[2])?];
+                Ok((face_normal(corners[0], corners[1], corners[2]), corners))
+            })
+            .collect()
+    }
+}
+
+/// The unit normal of a counter-clockwise triangle, or zero if it is degenerate.
+fn face_normal(a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>) -> Vector3<f32> {
+    let normal = (b - a).cross(c - a);
+    if normal.magnitude2() > 0.0 {
+        normal.normalize()
+    } else {
+        normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two triangles of a unit square in the z = 0 plane, sharing an edge.
+    fn square() -> StlMesh {
+        StlMesh {
+            positions: vec![
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(1.0, 1.0, 0.0),
+                Vector3::new(0.0, 1.0, 0.0),
+            ],
+            normals: vec![Vector3::new(0.0, 0.0, 1.0); 4],
+            indices: vec![0, 1, 2, 0, 2, 3],
+        }
+    }
+
+    fn corners(mesh: &StlMesh) -> Vec<Vector3<f32>> {
+        mesh.indices.iter().map(|&i| mesh.positions[i as usize]).collect()
+    }
+
+    #[test]
+    fn reads_ascii_and_welds_shared_corners() {
+        let source = "solid square
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 0
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex -0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+        let mesh = StlMesh::read(source.as_bytes()).unwrap();
+        assert_eq!(corners(&mesh), corners(&square()));
+        // The zero normal was computed from the winding, so both facets weld along their edge.
+        assert_eq!(mesh.positions.len(), 4);
+        assert!(mesh.normals.iter().all(|n| *n == Vector3::new(0.0, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn reads_binary_starting_with_solid() {
+        let mut data = Vec::new();
+        square().write(&mut data, StlFormat::Binary).unwrap();
+        assert_eq!(data.len(), 84 + 2 * 50);
+        data[..6].copy_from_slice(b"solid ");
+        let mesh = StlMesh::read(data.as_slice()).unwrap();
+        assert_eq!(corners(&mesh), corners(&square()));
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        for format in [StlFormat::Ascii, StlFormat::Binary] {
+            let mut data = Vec::new();
+            square().write(&mut data, format).unwrap();
+            let mesh = StlMesh::read(data.as_slice()).unwrap();
+            assert_eq!(mesh.indices.len(), 6, "{format:?}");
+            assert_eq!(corners(&mesh), corners(&square()), "{format:?}");
+            assert!(mesh.normals.iter().all(|n| *n == Vector3::new(0.0, 0.0, 1.0)), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_data() {
+        assert!(StlMesh::read(b"not an stl".as_slice()).is_err());
+        assert!(StlMesh::read(b"solid\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n".as_slice()).is_err());
+        assert!(StlMesh::read(b"solid\nfacet normal 0 0 1\nouter loop\nvertex 0 0\n".as_slice()).is_err());
+        let mut mesh = square();
+        mesh.indices.push(7);
+        mesh.indices.extend([0, 1]);
+        let mut data = Vec::new();
+        assert!(mesh.write(&mut data, StlFormat::Ascii).is_err());
+        assert!(data.is_empty());
+    }
+}
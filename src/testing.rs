@@ -0,0 +1,228 @@
+//! Golden-image regression testing.
+//!
+//! Renders a scene with a headless [`Engine`] on a fixed clock and compares the result
+//! against a reference PNG. Set `RUST_GL_BLESS=1` to (re)write the references instead.
+use crate::Engine;
+use image::{ImageBuffer, Rgba, RgbaImage};
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that makes [`GoldenTest::check`] overwrite the reference images.
+/// Empty and `0` leave it off.
+pub const BLESS_VAR: &str = "RUST_GL_BLESS";
+
+/// Whether a value of [`BLESS_VAR`] asks for the references to be rewritten.
+fn is_blessing(value: Option<&str>) -> bool {
+    value.is_some_and(|v| !v.is_empty() && v != "0")
+}
+
+/// Renders `frames` frames, setting the clock to `frame * frame_time` before each one,
+/// and returns the last frame.
+pub fn render_frames(engine: &mut Engine, frames: u32, frame_time: f64) -> RgbaImage {
+    for frame in 0..frames.max(1) {
+        engine.set_time(f64::from(frame) * frame_time);
+        engine.update(|_, _, _| {});
+    }
+    engine.read_pixels()
+}
+
+/// The result of comparing two images channel by channel.
+pub struct ImageComparison {
+    /// Number of pixels with at least one channel outside the tolerance
+    pub mismatched_pixels: usize,
+    /// Largest absolute difference seen in any channel
+    pub max_difference: u8,
+    /// Per-pixel absolute difference; mismatched pixels are drawn opaque
+    pub diff: RgbaImage,
+}
+
+impl ImageComparison {
+    #[must_use]
+    pub const fn matches(&self) -> bool {
+        self.mismatched_pixels == 0
+    }
+}
+
+/// Compares two images of the same size, allowing each channel to differ by `tolerance`.
+/// # Errors
+/// If the images have different dimensions.
+pub fn compare_images(
+    actual: &RgbaImage,
+    expected: &RgbaImage,
+    tolerance: u8,
+) -> Result<ImageComparison, Box<dyn Error>> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "Image sizes differ: got {:?}, expected {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        )
+        .into());
+    }
+    let mut mismatched_pixels = 0;
+    let mut max_difference = 0;
+    let diff = ImageBuffer::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y).0;
+        let e = expected.get_pixel(x, y).0;
+        let delta: [u8; 4] = core::array::from_fn(|i| a[i].abs_diff(e[i]));
+        let worst = delta.iter().copied().max().unwrap_or(0);
+        max_difference = max_difference.max(worst);
+        if worst > tolerance {
+            mismatched_pixels += 1;
+            Rgba([delta[0], delta[1], delta[2], 255])
+        } else {
+            Rgba([delta[0], delta[1], delta[2], 0])
+        }
+    });
+    Ok(ImageComparison {
+        mismatched_pixels,
+        max_difference,
+        diff,
+    })
+}
+
+/// Returned when a rendered frame does not match its reference image.
+#[derive(Debug)]
+pub struct GoldenMismatch {
+    pub reference: PathBuf,
+    pub diff_image: Option<PathBuf>,
+    pub mismatched_pixels: usize,
+    pub max_difference: u8,
+}
+impl Error for GoldenMismatch {}
+impl fmt::Display for GoldenMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Frame differs from {} in {} pixels (max channel difference {})",
+            self.reference.display(),
+            self.mismatched_pixels,
+            self.max_difference
+        )?;
+        if let Some(diff) = &self.diff_image {
+            write!(f, ", diff written to {}", diff.display())?;
+        }
+        Ok(())
+    }
+}
+
+/// Configuration for a golden-image comparison.
+pub struct GoldenTest {
+    /// Number of frames rendered before capturing
+    pub frames: u32,
+    /// Seconds the clock advances per frame
+    pub frame_time: f64,
+    /// Allowed absolute difference per color channel
+    pub tolerance: u8,
+    /// Directory for diff images; defaults to next to the reference
+    pub diff_dir: Option<PathBuf>,
+}
+
+impl Default for GoldenTest {
+    fn default() -> Self {
+        Self {
+            frames: 1,
+            frame_time: 1.0 / 60.0,
+            tolerance: 2,
+            diff_dir: None,
+        }
+    }
+}
+
+impl GoldenTest {
+    /// Renders the scene in `engine` and compares the last frame against `reference`.
+    ///
+    /// On mismatch, writes `<name>.diff.png` and `<name>.actual.png` next to the reference
+    /// (or into `diff_dir`). If [`BLESS_VAR`] is on, the reference is overwritten instead.
+    /// # Errors
+    /// If the reference is missing or cannot be read, the images differ in size,
+    /// or the frame doesn't match within the tolerance.
+    pub fn check(&self, engine: &mut Engine, reference: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+        let reference = reference.as_ref();
+        let actual = render_frames(engine, self.frames, self.frame_time);
+        if is_blessing(std::env::var(BLESS_VAR).ok().as_deref()) {
+            actual.save(reference)?;
+            return Ok(());
+        }
+        let expected = image::open(reference)
+            .map_err(|e| format!("Couldn't open reference {}: {e}", reference.display()))?
+            .to_rgba8();
+        let comparison = compare_images(&actual, &expected, self.tolerance)?;
+        if comparison.matches() {
+            return Ok(());
+        }
+        let stem = reference
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or("Invalid reference path")?;
+        let dir = self
+            .diff_dir
+            .as_deref()
+            .or_else(|| reference.parent())
+            .unwrap_or_else(|| Path::new("."));
+        let diff_path = dir.join(format!("{stem}.diff.png"));
+        let written = comparison.diff.save(&diff_path).is_ok()
+            && actual.save(dir.join(format!("{stem}.actual.png"))).is_ok();
+        Err(GoldenMismatch {
+            reference: reference.to_path_buf(),
+            diff_image: written.then_some(diff_path),
+            mismatched_pixels: comparison.mismatched_pixels,
+            max_difference: comparison.max_difference,
+        }
+        .into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, color: [u8; 4]) -> RgbaImage {
+        ImageBuffer::from_pixel(width, height, Rgba(color))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = solid(4, 3, [10, 20, 30, 255]);
+        let comparison = compare_images(&image, &image, 0).unwrap();
+        assert!(comparison.matches());
+        assert_eq!(comparison.max_difference, 0);
+        assert!(comparison.diff.pixels().all(|p| p.0 == [0, 0, 0, 0]));
+    }
+
+    #[test]
+    fn one_changed_pixel_is_reported() {
+        let expected = solid(4, 3, [10, 20, 30, 255]);
+        let mut actual = expected.clone();
+        actual.put_pixel(2, 1, Rgba([10, 20, 40, 255]));
+        let comparison = compare_images(&actual, &expected, 2).unwrap();
+        assert_eq!(comparison.mismatched_pixels, 1);
+        assert_eq!(comparison.max_difference, 10);
+        assert_eq!(comparison.diff.get_pixel(2, 1).0, [0, 0, 10, 255]);
+        assert_eq!(comparison.diff.get_pixel(0, 0).0[3], 0);
+    }
+
+    #[test]
+    fn differences_within_tolerance_match() {
+        let expected = solid(2, 2, [100, 100, 100, 255]);
+        let actual = solid(2, 2, [102, 98, 100, 255]);
+        let comparison = compare_images(&actual, &expected, 2).unwrap();
+        assert!(comparison.matches());
+        assert_eq!(comparison.max_difference, 2);
+    }
+
+    #[test]
+    fn size_mismatch_is_an_error() {
+        assert!(compare_images(&solid(4, 3, [0; 4]), &solid(3, 4, [0; 4]), 0).is_err());
+    }
+
+    #[test]
+    fn bless_needs_a_value_other_than_zero() {
+        assert!(!is_blessing(None));
+        assert!(!is_blessing(Some("")));
+        assert!(!is_blessing(Some("0")));
+        assert!(is_blessing(Some("1")));
+        assert!(is_blessing(Some("yes")));
+    }
+}
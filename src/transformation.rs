@@ -2,9 +2,13 @@ extern crate proc_macro;
 use std::mem::size_of;
 use std::ptr::null;
 
+use crate::bounds::{BoundingSphere, Frustum, Ray};
 use crate::util::{find_gl_error, GLFunctionError};
 use cgmath::num_traits::AsPrimitive;
-use cgmath::{perspective, Array, Deg, EuclideanSpace, Euler, Matrix, Matrix4, Point3, Rad, Vector2, Vector3, Zero};
+use cgmath::{
+    ortho, perspective, Array, Deg, EuclideanSpace, Euler, InnerSpace, Matrix, Matrix4, Point3, Rad, SquareMatrix,
+    Vector2, Vector3, Vector4, Zero,
+};
 use gl::types::{GLsizeiptr, GLuint};
 use gl::{DYNAMIC_DRAW, UNIFORM_BUFFER};
 use imgui::sys::cty::c_double;
@@ -166,10 +170,92 @@ pub trait Transformable<T: AsPrimitive<f32> + Copy = f32> {
     }
 }
 */
+/// The lens of a camera, used to rebuild its projection matrix when the aspect ratio changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view in radians.
+        yfov: f32,
+        /// The aspect ratio the camera was authored for; the viewport's aspect ratio is used when rendering.
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        /// `None` for an infinite far plane.
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        /// Half of the view's width; only used for `aspect_ratio`, the width follows the viewport.
+        xmag: f32,
+        /// Half of the view's height.
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Self::Perspective {
+            yfov: Rad::from(Deg(100.0)).0,
+            aspect_ratio: None,
+            znear: 0.01,
+            zfar: Some(1000.0),
+        }
+    }
+}
+
+impl Projection {
+    #[must_use]
+    pub fn matrix(&self, aspect: f32) -> Matrix4<f32> {
+        match *self {
+            Self::Perspective {
+                yfov,
+                znear,
+                zfar: Some(zfar),
+                ..
+            } => perspective(Rad(yfov), aspect, znear, zfar),
+            Self::Perspective {
+                yfov, znear, zfar: None, ..
+            } => {
+                let f = 1.0 / (yfov / 2.0).tan();
+                Matrix4::from_cols(
+                    Vector4::new(f / aspect, 0.0, 0.0, 0.0),
+                    Vector4::new(0.0, f, 0.0, 0.0),
+                    Vector4::new(0.0, 0.0, -1.0, -1.0),
+                    Vector4::new(0.0, 0.0, -2.0 * znear, 0.0),
+                )
+            }
+            Self::Orthographic {
+                ymag, znear, zfar, ..
+            } => ortho(-ymag * aspect, ymag * aspect, -ymag, ymag, znear, zfar),
+        }
+    }
+
+    /// The aspect ratio the lens was authored for, if it has one.
+    #[must_use]
+    pub fn aspect_ratio(&self) -> Option<f32> {
+        match *self {
+            Self::Perspective { aspect_ratio, .. } => aspect_ratio,
+            Self::Orthographic { xmag, ymag, .. } => (ymag != 0.0).then(|| xmag / ymag),
+        }
+    }
+}
+
+/// An authored camera position and lens, e.g. from a glTF file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraPreset {
+    pub name: Option<String>,
+    pub position: Vector3<f32>,
+    /// Unit vector the camera looks along.
+    pub direction: Vector3<f32>,
+    pub projection: Projection,
+}
+
 pub struct Camera {
     pub pos: Vector3<f32>,
     pub rot: Vector2<f32>,
     pub projection: Matrix4<f32>,
+    /// Lens `projection` is built from.
+    pub lens: Projection,
     uniform_buffer: GLuint,
     last_mouse: (f64, f64),
     pub(crate) front: Vector3<f32>,
@@ -192,7 +278,8 @@ impl Camera {
             //     Point3::new(0f32, 0f32, 0f32),
             //     vec3(0f32, 0f32, 1f32),
             // ),
-            projection: perspective(Deg(100.0), 16. / 9., 0.01, 1000.0),
+            projection: Projection::default().matrix(16. / 9.),
+            lens: Projection::default(),
             uniform_buffer: 0,
             last_mouse: (-55.5f64, 55.5f64),
             front: Vector3::new(0f32, 0f32, 1f32),
@@ -219,14 +306,59 @@ impl Camera {
 
     fn get_view_matrix(&mut self) -> Matrix4<f32> {
         self.update_vectors();
+        self.view_matrix()
+    }
+
+    /// The view matrix for the current position and direction.
+    #[must_use]
+    pub fn view_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_at_rh(
             Point3::from_vec(self.pos),
             Point3::from_vec(self.pos + self.front),
             Vector3::unit_y(),
         )
     }
+
+    /// The world space frustum of the current view and projection.
+    #[must_use]
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&(self.projection * self.view_matrix()))
+    }
+
+    /// The world space ray from the near plane through a point of the viewport, e.g. the cursor
+    /// for picking. `cursor_pos` is in pixels from the top left corner, like
+    /// `Engine::get_cursor_pos`, and `viewport` is the size of the area it is measured in.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn screen_ray(&self, cursor_pos: (f64, f64), viewport: [usize; 2]) -> Ray {
+        let x = (cursor_pos.0 / viewport[0] as f64).mul_add(2.0, -1.0) as f32;
+        let y = (cursor_pos.1 / viewport[1] as f64).mul_add(-2.0, 1.0) as f32;
+        let inverse = (self.projection * self.view_matrix())
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        let unproject = |depth: f32| {
+            let point = inverse * Vector4::new(x, y, depth, 1.0);
+            point.truncate() / point.w
+        };
+        // The middle of the depth range, since the far plane may be at infinity.
+        let near = unproject(-1.0);
+        Ray::new(near, unproject(0.0) - near)
+    }
     pub fn update_projection(&mut self, aspect: f32) {
-        self.projection = perspective(Deg(100.0), aspect, 0.01, 1000.0);
+        self.projection = self.lens.matrix(aspect);
+    }
+
+    /// Moves the camera to `preset` and switches to its lens.
+    ///
+    /// The camera has no roll, so a preset's roll around its view direction is dropped.
+    pub fn apply_preset(&mut self, preset: &CameraPreset, aspect: f32) {
+        self.pos = preset.position;
+        let direction = preset.direction.normalize();
+        self.pitch = direction.y.clamp(-1.0, 1.0).asin();
+        self.yaw = direction.z.atan2(direction.x);
+        self.update_vectors();
+        self.lens = preset.projection;
+        self.update_projection(aspect);
     }
 
     pub fn update_vectors(&mut self) {
@@ -287,4 +419,172 @@ impl Camera {
     pub fn translate(&mut self, vector3: Vector3<f32>) {
         self.pos += vector3;
     }
+
+    /// Moves the camera back along its view direction until `sphere` fits in the view, e.g. to
+    /// frame a loaded model with `Render::bounds`.
+    ///
+    /// An orthographic view's size doesn't change with distance, so its magnification is set
+    /// to fit the sphere instead and the camera is only moved back past the near plane.
+    pub fn frame(&mut self, sphere: &BoundingSphere) {
+        self.update_vectors();
+        let radius = sphere.radius.max(0.0);
+        if let (Projection::Orthographic { xmag, ymag, .. }, true) = (&mut self.lens, radius > 0.0) {
+            // The aspect ratio the projection was last built for.
+            let aspect = self.projection.y.y / self.projection.x.x;
+            let fitted = radius / aspect.min(1.0);
+            if *ymag > 0.0 {
+                *xmag *= fitted / *ymag;
+            }
+            *ymag = fitted;
+            self.update_projection(aspect);
+        }
+        let distance = match self.lens {
+            Projection::Perspective { .. } => {
+                // The narrower half field of view, taken from the projection for the current aspect ratio.
+                let tan = (1.0 / self.projection.x.x).min(1.0 / self.projection.y.y);
+                radius / tan.atan().sin()
+            }
+            Projection::Orthographic { znear, .. } => radius + znear.max(0.0),
+        };
+        self.pos = sphere.center - self.front * distance;
+    }
+
+    /// The height of a sphere on screen as a fraction of the viewport's height, e.g. for picking
+    /// a level of detail. Spheres containing the camera are infinitely large.
+    #[must_use]
+    pub fn projected_size(&self, center: Vector3<f32>, radius: f32) -> f32 {
+        match self.lens {
+            Projection::Perspective { yfov, .. } => {
+                let distance = (center - self.pos).magnitude();
+                if distance <= radius {
+                    f32::INFINITY
+                } else {
+                    radius / (distance * (yfov / 2.0).tan())
+                }
+            }
+            Projection::Orthographic { ymag, .. } => radius / ymag,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const VIEWPORT: [usize; 2] = [800, 600];
+
+    fn assert_looks_along_front(camera: &Camera) {
+        let ray = camera.screen_ray((400.0, 300.0), VIEWPORT);
+        assert!((ray.direction - camera.front).magnitude() < 1e-4, "{:?} != {:?}", ray.direction, camera.front);
+        assert!((ray.origin - camera.pos).dot(camera.front) > 0.0);
+    }
+
+    #[test]
+    fn center_ray_looks_along_the_camera() {
+        let mut camera = Camera::new();
+        camera.yaw = 0.7;
+        camera.pitch = -0.3;
+        camera.update_vectors();
+        camera.pos = Vector3::new(1.0, 2.0, 3.0);
+        assert_looks_along_front(&camera);
+
+        let lenses = [
+            Projection::Perspective {
+                yfov: FRAC_PI_2,
+                aspect_ratio: None,
+                znear: 0.1,
+                zfar: None,
+            },
+            Projection::Orthographic {
+                xmag: 4.0,
+                ymag: 3.0,
+                znear: 0.1,
+                zfar: 50.0,
+            },
+        ];
+        for projection in lenses {
+            let preset = CameraPreset {
+                name: None,
+                position: Vector3::new(-2.0, 0.5, 4.0),
+                direction: Vector3::new(1.0, -0.5, -1.0),
+                projection,
+            };
+            camera.apply_preset(&preset, 4.0 / 3.0);
+            assert_looks_along_front(&camera);
+        }
+    }
+
+    #[test]
+    fn corner_rays_run_along_the_frustum_planes() {
+        let mut camera = Camera::new();
+        camera.update_projection(4.0 / 3.0);
+        let frustum = camera.frustum();
+        let point = camera.screen_ray((0.0, 0.0), VIEWPORT).at(10.0);
+        // On the left and top planes, inside the others.
+        for (i, plane) in frustum.planes.iter().enumerate() {
+            let distance = plane.dot(point.extend(1.0));
+            if i == 0 || i == 3 {
+                assert!(distance.abs() < 1e-3, "plane {i} is {distance} away");
+            } else {
+                assert!(distance > 0.0, "plane {i} is {distance} away");
+            }
+        }
+    }
+
+    #[test]
+    fn projected_size_follows_the_lens() {
+        let mut camera = Camera::new();
+        camera.pos = Vector3::new(1.0, 0.0, 0.0);
+        camera.lens = Projection::Perspective {
+            yfov: FRAC_PI_2,
+            aspect_ratio: None,
+            znear: 0.1,
+            zfar: None,
+        };
+        let size = |camera: &Camera, distance: f32| camera.projected_size(Vector3::new(1.0, 0.0, -distance), 1.0);
+        assert!((size(&camera, 4.0) - 0.25).abs() < 1e-6);
+        assert!((size(&camera, 8.0) - 0.125).abs() < 1e-6);
+        // The size only depends on the distance, not the direction.
+        assert!((camera.projected_size(Vector3::new(1.0, 4.0, 0.0), 1.0) - 0.25).abs() < 1e-6);
+        assert!(size(&camera, 0.5).is_infinite());
+
+        camera.lens = Projection::Orthographic {
+            xmag: 4.0,
+            ymag: 2.0,
+            znear: 0.1,
+            zfar: 50.0,
+        };
+        assert!((size(&camera, 4.0) - 0.5).abs() < 1e-6);
+        assert!((size(&camera, 40.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn framing_fits_the_sphere_in_the_narrower_direction() {
+        let sphere = BoundingSphere::new(Vector3::new(1.0, 2.0, 3.0), 3.0);
+        let orthographic = Projection::Orthographic {
+            xmag: 1.0,
+            ymag: 0.5,
+            znear: 0.1,
+            zfar: 50.0,
+        };
+        for lens in [Projection::default(), orthographic] {
+            for (aspect, tight) in [(2.0, [2, 3]), (0.5, [0, 1])] {
+                let mut camera = Camera::new();
+                camera.yaw = 0.7;
+                camera.pitch = -0.3;
+                camera.lens = lens;
+                camera.update_projection(aspect);
+                camera.frame(&sphere);
+                let frustum = camera.frustum();
+                for (i, plane) in frustum.planes.iter().enumerate() {
+                    let distance = plane.dot(sphere.center.extend(1.0));
+                    assert!(distance >= sphere.radius - 1e-3, "{lens:?} plane {i} is {distance} away");
+                    if tight.contains(&i) {
+                        assert!(distance <= sphere.radius + 1e-3, "{lens:?} plane {i} is {distance} away");
+                    }
+                }
+            }
+        }
+    }
 }
@@ -0,0 +1,453 @@
+//! Checking mesh data for problems before it is uploaded, and repairing what can be repaired.
+//!
+//! Loaders run these checks so bad files fail with a description of what is wrong instead of
+//! drawing garbage or failing with an OpenGL error at draw time.
+use crate::renderable::Mesh;
+use crate::vertex::AttributeValues;
+use cgmath::{InnerSpace, Vector2, Vector3};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+
+/// A problem found by `validate`. Triangles are numbered by their position in the index list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeshIssue {
+    /// The index count isn't a multiple of three; the last `extra` indices form no triangle.
+    IncompleteTriangle { extra: usize },
+    IndexOutOfRange { triangle: usize, index: u32 },
+    /// A position is NaN or infinite.
+    NonFinitePosition { vertex: usize },
+    /// A value of another attribute is NaN or infinite.
+    NonFiniteAttribute { attribute: String, vertex: usize },
+    /// An attribute has a value count other than the vertex count.
+    AttributeLength { attribute: String, expected: usize, found: usize },
+    /// A triangle with a repeated vertex or no area.
+    DegenerateTriangle { triangle: usize },
+    /// An edge shared by more than two triangles.
+    NonManifoldEdge { vertices: (u32, u32), triangles: usize },
+}
+
+impl MeshIssue {
+    /// Whether the issue makes the mesh unusable: drawing it would read out of bounds or produce
+    /// undefined geometry. The other issues only affect shading or further processing.
+    #[must_use]
+    pub const fn is_error(&self) -> bool {
+        matches!(
+            self,
+            Self::IncompleteTriangle { .. }
+                | Self::IndexOutOfRange { .. }
+                | Self::NonFinitePosition { .. }
+                | Self::AttributeLength { .. }
+        )
+    }
+
+    /// A short name of the kind of issue.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::IncompleteTriangle { .. } => "incomplete triangle",
+            Self::IndexOutOfRange { .. } => "index out of range",
+            Self::NonFinitePosition { .. } => "non-finite position",
+            Self::NonFiniteAttribute { .. } => "non-finite attribute value",
+            Self::AttributeLength { .. } => "attribute length mismatch",
+            Self::DegenerateTriangle { .. } => "degenerate triangle",
+            Self::NonManifoldEdge { .. } => "non-manifold edge",
+        }
+    }
+}
+
+impl Display for MeshIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::IncompleteTriangle { extra } => write!(f, "{extra} indices after the last triangle"),
+            Self::IndexOutOfRange { triangle, index } => {
+                write!(f, "Triangle {triangle} uses vertex {index}, which doesn't exist")
+            }
+            Self::NonFinitePosition { vertex } => write!(f, "Vertex {vertex} has a non-finite position"),
+            Self::NonFiniteAttribute { attribute, vertex } => {
+                write!(f, "Vertex {vertex} has a non-finite {attribute} value")
+            }
+            Self::AttributeLength { attribute, expected, found } => {
+                write!(f, "Attribute {attribute} has {found} values for {expected} vertices")
+            }
+            Self::DegenerateTriangle { triangle } => write!(f, "Triangle {triangle} is degenerate"),
+            Self::NonManifoldEdge { vertices: (a, b), triangles } => {
+                write!(f, "Edge {a}-{b} is shared by {triangles} triangles")
+            }
+        }
+    }
+}
+
+/// The issues found in a mesh, in the order they were found.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationReport {
+    pub issues: Vec<MeshIssue>,
+}
+
+impl ValidationReport {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Whether any issue makes the mesh unusable; see `MeshIssue::is_error`.
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(MeshIssue::is_error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &MeshIssue> {
+        self.issues.iter().filter(|i| i.is_error())
+    }
+}
+
+/// Summarizes the issues by kind, e.g. "2 x degenerate triangle, 1 x non-manifold edge".
+impl Display for ValidationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.issues.is_empty() {
+            return write!(f, "no issues");
+        }
+        let mut counts: Vec<(&str, usize)> = Vec::new();
+        for issue in &self.issues {
+            match counts.iter_mut().find(|(kind, _)| *kind == issue.kind()) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((issue.kind(), 1)),
+            }
+        }
+        let summary: Vec<String> = counts.iter().map(|(kind, count)| format!("{count} x {kind}")).collect();
+        write!(f, "{}", summary.join(", "))
+    }
+}
+
+impl Error for ValidationReport {}
+
+/// Which problems `apply` repairs. Non-manifold edges are only reported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RepairOptions {
+    /// Drops incomplete triangles, triangles with out-of-range indices and triangles using
+    /// non-finite positions, whose positions are then zeroed.
+    pub drop_invalid_triangles: bool,
+    /// Drops triangles with a repeated vertex or no area.
+    pub drop_degenerate_triangles: bool,
+    /// Pads attributes with too few values with zeros, truncates ones with too many, and replaces
+    /// non-finite values with zero.
+    pub fix_attributes: bool,
+}
+
+impl Default for RepairOptions {
+    /// Repairs everything that can be repaired.
+    fn default() -> Self {
+        Self {
+            drop_invalid_triangles: true,
+            drop_degenerate_triangles: true,
+            fix_attributes: true,
+        }
+    }
+}
+
+impl RepairOptions {
+    /// Repairs `mesh`, whose indices must be a triangle list, and returns the issues left. Unused
+    /// vertices are kept. The mesh must be initialized afterwards.
+    ///
+    /// Without `drop_invalid_triangles`, triangles with out-of-range indices are kept and
+    /// reported rather than checked for degeneracy.
+    pub fn apply(&self, mesh: &mut Mesh) -> ValidationReport {
+        let count = mesh.vertices.len();
+        if self.fix_attributes {
+            resize(&mut mesh.normals, count, Vector3::new(0.0, 0.0, 0.0));
+            resize(&mut mesh.tex_coords, count, Vector2::new(0.0, 0.0));
+            resize(&mut mesh.joints, count, [0; 4]);
+            resize(&mut mesh.weights, count, [0.0; 4]);
+            resize(&mut mesh.colors, count, [0.0; 4]);
+            for target in &mut mesh.morph_targets {
+                target.positions.resize(count, Vector3::new(0.0, 0.0, 0.0));
+                resize(&mut target.normals, count, Vector3::new(0.0, 0.0, 0.0));
+            }
+            for custom in &mut mesh.custom_attributes {
+                custom.values.resize(count * custom.attribute.count as usize);
+            }
+            zero_non_finite(mesh);
+        }
+        if self.drop_invalid_triangles {
+            let finite: Vec<bool> = mesh.vertices.iter().map(is_finite).collect();
+            mesh.indices.truncate(mesh.indices.len() / 3 * 3);
+            retain_triangles(&mut mesh.indices, |t| {
+                t.iter().all(|&i| finite.get(i as usize).copied().unwrap_or(false))
+            });
+            for v in mesh.vertices.iter_mut().filter(|v| !is_finite(v)) {
+                *v = Vector3::new(0.0, 0.0, 0.0);
+            }
+        }
+        if self.drop_degenerate_triangles {
+            let vertices = &mesh.vertices;
+            retain_triangles(&mut mesh.indices, |t| {
+                t.iter().any(|&i| i as usize >= vertices.len()) || !is_degenerate(vertices, t)
+            });
+        }
+        mesh.invalidate_bounds();
+        validate(mesh)
+    }
+}
+
+/// Checks `mesh`, whose indices must be a triangle list, for problems.
+#[must_use]
+pub fn validate(mesh: &Mesh) -> ValidationReport {
+    let mut issues = Vec::new();
+    let count = mesh.vertices.len();
+    let lengths = [
+        ("normal".to_owned(), mesh.normals.as_ref().map(Vec::len)),
+        ("texture coordinate".to_owned(), mesh.tex_coords.as_ref().map(Vec::len)),
+        ("joint".to_owned(), mesh.joints.as_ref().map(Vec::len)),
+        ("weight".to_owned(), mesh.weights.as_ref().map(Vec::len)),
+        ("color".to_owned(), mesh.colors.as_ref().map(Vec::len)),
+    ]
+    .into_iter()
+    .chain(mesh.morph_targets.iter().enumerate().flat_map(|(i, target)| {
+        [
+            (format!("morph target {i} position"), Some(target.positions.len())),
+            (format!("morph target {i} normal"), target.normals.as_ref().map(Vec::len)),
+        ]
+    }))
+    .chain(mesh.custom_attributes.iter().map(|custom| {
+        let components = custom.attribute.count as usize;
+        // Count a partial last value as a value so it is reported.
+        (custom.attribute.name.to_string(), Some(custom.values.len().div_ceil(components.max(1))))
+    }));
+    for (attribute, length) in lengths {
+        if let Some(found) = length.filter(|&found| found != count) {
+            issues.push(MeshIssue::AttributeLength { attribute, expected: count, found });
+        }
+    }
+    for (vertex, v) in mesh.vertices.iter().enumerate() {
+        if !is_finite(v) {
+            issues.push(MeshIssue::NonFinitePosition { vertex });
+        }
+    }
+    let mut check = |attribute: &str, vertex: Option<usize>| {
+        if let Some(vertex) = vertex {
+            issues.push(MeshIssue::NonFiniteAttribute { attribute: attribute.to_owned(), vertex });
+        }
+    };
+    check("normal", mesh.normals.as_deref().and_then(first_non_finite));
+    check("texture coordinate", mesh.tex_coords.as_deref().and_then(first_non_finite));
+    check("weight", mesh.weights.as_deref().and_then(first_non_finite));
+    check("color", mesh.colors.as_deref().and_then(first_non_finite));
+    for (i, target) in mesh.morph_targets.iter().enumerate() {
+        check(&format!("morph target {i} position"), first_non_finite(&target.positions));
+        check(&format!("morph target {i} normal"), target.normals.as_deref().and_then(first_non_finite));
+    }
+    for custom in &mesh.custom_attributes {
+        if let AttributeValues::F32(values) = &custom.values {
+            let components = (custom.attribute.count as usize).max(1);
+            check(&custom.attribute.name, values.iter().position(|v| !v.is_finite()).map(|i| i / components));
+        }
+    }
+
+    let extra = mesh.indices.len() % 3;
+    if extra != 0 {
+        issues.push(MeshIssue::IncompleteTriangle { extra });
+    }
+    let mut edges: HashMap<(u32, u32), usize> = HashMap::new();
+    for (triangle, t) in mesh.indices.chunks_exact(3).enumerate() {
+        if let Some(&index) = t.iter().find(|&&i| i as usize >= count) {
+            issues.push(MeshIssue::IndexOutOfRange { triangle, index });
+            continue;
+        }
+        if is_degenerate(&mesh.vertices, t) {
+            issues.push(MeshIssue::DegenerateTriangle { triangle });
+            continue;
+        }
+        for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    let mut non_manifold: Vec<_> = edges.into_iter().filter(|(_, triangles)| *triangles > 2).collect();
+    non_manifold.sort_unstable();
+    issues.extend(
+        non_manifold
+            .into_iter()
+            .map(|(vertices, triangles)| MeshIssue::NonManifoldEdge { vertices, triangles }),
+    );
+    ValidationReport { issues }
+}
+
+/// Per-vertex floating point values.
+trait Floats: Copy {
+    const ZERO: Self;
+    fn is_finite(&self) -> bool;
+}
+
+impl Floats for Vector2<f32> {
+    const ZERO: Self = Self { x: 0.0, y: 0.0 };
+    fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite()
+    }
+}
+
+impl Floats for Vector3<f32> {
+    const ZERO: Self = Self { x: 0.0, y: 0.0, z: 0.0 };
+    fn is_finite(&self) -> bool {
+        is_finite(self)
+    }
+}
+
+impl Floats for [f32; 4] {
+    const ZERO: Self = [0.0; 4];
+    fn is_finite(&self) -> bool {
+        self.iter().all(|v| v.is_finite())
+    }
+}
+
+fn first_non_finite<T: Floats>(values: &[T]) -> Option<usize> {
+    values.iter().position(|v| !v.is_finite())
+}
+
+/// Replaces non-finite values of every attribute besides the positions with zero.
+fn zero_non_finite(mesh: &mut Mesh) {
+    fn zero<T: Floats>(values: &mut [T]) {
+        for v in values.iter_mut().filter(|v| !v.is_finite()) {
+            *v = T::ZERO;
+        }
+    }
+    if let Some(normals) = mesh.normals.as_deref_mut() {
+        zero(normals);
+    }
+    if let Some(tex_coords) = mesh.tex_coords.as_deref_mut() {
+        zero(tex_coords);
+    }
+    if let Some(weights) = mesh.weights.as_deref_mut() {
+        zero(weights);
+    }
+    if let Some(colors) = mesh.colors.as_deref_mut() {
+        zero(colors);
+    }
+    for target in &mut mesh.morph_targets {
+        zero(&mut target.positions);
+        if let Some(normals) = target.normals.as_deref_mut() {
+            zero(normals);
+        }
+    }
+    for custom in &mut mesh.custom_attributes {
+        if let AttributeValues::F32(values) = &mut custom.values {
+            for v in values.iter_mut().filter(|v| !v.is_finite()) {
+                *v = 0.0;
+            }
+        }
+    }
+}
+
+const fn is_finite(v: &Vector3<f32>) -> bool {
+    v.x.is_finite() && v.y.is_finite() && v.z.is_finite()
+}
+
+/// Whether a triangle, whose indices must be in range, repeats a vertex or has no area.
+fn is_degenerate(vertices: &[Vector3<f32>], t: &[u32]) -> bool {
+    if t[0] == t[1] || t[1] == t[2] || t[2] == t[0] {
+        return true;
+    }
+    let [a, b, c] = [t[0], t[1], t[2]].map(|i| vertices[i as usize]);
+    (b - a).cross(c - a).magnitude2() == 0.0
+}
+
+/// Resizes an optional attribute to `count` values, padding with `value`.
+fn resize<T: Clone>(values: &mut Option<Vec<T>>, count: usize, value: T) {
+    if let Some(values) = values {
+        values.resize(count, value);
+    }
+}
+
+/// Keeps the triangles of a triangle list for which `keep` returns true.
+fn retain_triangles(indices: &mut Vec<u32>, mut keep: impl FnMut(&[u32]) -> bool) {
+    let complete = indices.len() / 3 * 3;
+    // Leftover indices stay so they are still reported.
+    *indices = indices[..complete]
+        .chunks_exact(3)
+        .filter(|t| keep(t))
+        .flatten()
+        .chain(&indices[complete..])
+        .copied()
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle_mesh(indices: Vec<u32>) -> Mesh {
+        let vertices = vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)];
+        Mesh::new(vertices, indices, None, None)
+    }
+
+    #[test]
+    fn dropping_degenerate_triangles_keeps_out_of_range_ones() {
+        let mut mesh = triangle_mesh(vec![0, 1, 2, 0, 0, 1, 0, 1, 7, 2]);
+        let options = RepairOptions {
+            drop_invalid_triangles: false,
+            ..RepairOptions::default()
+        };
+        let report = options.apply(&mut mesh);
+        assert_eq!(mesh.indices, vec![0, 1, 2, 0, 1, 7, 2]);
+        assert!(report.issues.contains(&MeshIssue::IndexOutOfRange { triangle: 1, index: 7 }));
+        assert!(report.issues.contains(&MeshIssue::IncompleteTriangle { extra: 1 }));
+    }
+
+    #[test]
+    fn repairing_updates_the_bounds() {
+        let mut mesh = triangle_mesh(vec![0, 1, 2]);
+        assert_eq!(mesh.bounds().aabb.max, Vector3::new(1.0, 1.0, 0.0));
+        mesh.vertices[1] = Vector3::new(f32::NAN, 0.0, 0.0);
+        mesh.vertices.push(Vector3::new(3.0, 3.0, 3.0));
+        mesh.indices.extend([0, 2, 3]);
+        assert!(RepairOptions::default().apply(&mut mesh).is_empty());
+        assert_eq!(mesh.indices, vec![0, 2, 3]);
+        assert_eq!(mesh.bounds().aabb.max, Vector3::new(3.0, 3.0, 3.0));
+    }
+
+    #[test]
+    fn edges_shared_by_three_triangles_are_non_manifold() {
+        let mut mesh = triangle_mesh(vec![0, 1, 2, 1, 0, 3, 0, 1, 4]);
+        mesh.vertices.extend([Vector3::new(0.0, -1.0, 0.0), Vector3::new(0.0, 0.0, 1.0)]);
+        let report = mesh.validate();
+        assert_eq!(report.issues, vec![MeshIssue::NonManifoldEdge { vertices: (0, 1), triangles: 3 }]);
+        assert!(!report.has_errors());
+        // Repairing leaves them alone.
+        assert_eq!(RepairOptions::default().apply(&mut mesh), report);
+        assert_eq!(mesh.indices.len(), 9);
+    }
+
+    #[test]
+    fn fixing_attributes_pads_and_truncates_them() {
+        let mut mesh = triangle_mesh(vec![0, 1, 2]);
+        mesh.normals = Some(vec![Vector3::unit_z()]);
+        mesh.colors = Some(vec![[1.0; 4]; 5]);
+        let report = mesh.validate();
+        assert_eq!(
+            report.issues,
+            vec![
+                MeshIssue::AttributeLength { attribute: "normal".to_owned(), expected: 3, found: 1 },
+                MeshIssue::AttributeLength { attribute: "color".to_owned(), expected: 3, found: 5 },
+            ]
+        );
+        assert!(report.has_errors());
+
+        let options = RepairOptions { fix_attributes: false, ..RepairOptions::default() };
+        assert_eq!(options.apply(&mut mesh), report);
+        assert!(RepairOptions::default().apply(&mut mesh).is_empty());
+        assert_eq!(mesh.normals, Some(vec![Vector3::unit_z(), Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 0.0)]));
+        assert_eq!(mesh.colors, Some(vec![[1.0; 4]; 3]));
+    }
+
+    #[test]
+    fn non_finite_attributes_are_zeroed() {
+        let mut mesh = triangle_mesh(vec![0, 1, 2]);
+        mesh.tex_coords = Some(vec![Vector2::new(0.0, 0.0), Vector2::new(f32::INFINITY, 0.0), Vector2::new(0.0, 1.0)]);
+        let report = mesh.validate();
+        assert_eq!(
+            report.issues,
+            vec![MeshIssue::NonFiniteAttribute { attribute: "texture coordinate".to_owned(), vertex: 1 }]
+        );
+        assert!(!report.has_errors());
+        assert!(RepairOptions::default().apply(&mut mesh).is_empty());
+        assert_eq!(mesh.tex_coords.as_ref().map(|t| t[1]), Some(Vector2::new(0.0, 0.0)));
+    }
+}
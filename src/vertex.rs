@@ -0,0 +1,444 @@
+//! Vertex layouts: which attributes a mesh's vertices have, their types and shader locations.
+//!
+//! The standard attributes have fixed locations shared by all shaders, so a shader input keeps
+//! its location whether or not the other attributes are present:
+//!
+//! | Location | Name         | Type   |
+//! |----------|--------------|--------|
+//! | 0        | `aPos`       | `vec3` |
+//! | 1        | `aNormal`    | `vec3` |
+//! | 2        | `aTexCoord`  | `vec2` |
+//! | 3        | `aJoints`    | `vec4` |
+//! | 4        | `aWeights`   | `vec4` |
+//! | 5        | `aColor`     | `vec4` |
+//! | 6        | `aTangent`   | `vec4` |
+//! | 7        | `aTexCoord1` | `vec2` |
+//!
+//! Custom attributes should use locations from 8 on.
+use crate::glutil::Vaa;
+use crate::shader::Shader;
+use gl::types::GLenum;
+use std::borrow::Cow;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComponentType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+}
+
+impl ComponentType {
+    #[must_use]
+    pub const fn gl_type(self) -> GLenum {
+        match self {
+            Self::I8 => gl::BYTE,
+            Self::U8 => gl::UNSIGNED_BYTE,
+            Self::I16 => gl::SHORT,
+            Self::U16 => gl::UNSIGNED_SHORT,
+            Self::I32 => gl::INT,
+            Self::U32 => gl::UNSIGNED_INT,
+            Self::F32 => gl::FLOAT,
+        }
+    }
+
+    #[must_use]
+    pub const fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+        }
+    }
+}
+
+/// One attribute of a vertex: a shader input `name` at `location` made of `count` components.
+///
+/// Integer components reach the shader as floats, mapped to 0..1 (or -1..1 when signed) if
+/// `normalized` is set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexAttribute {
+    pub name: Cow<'static, str>,
+    pub component: ComponentType,
+    pub count: u32,
+    pub normalized: bool,
+    pub location: u32,
+}
+
+impl VertexAttribute {
+    pub const POSITION: Self = Self::float("aPos", 3, 0);
+    pub const NORMAL: Self = Self::float("aNormal", 3, 1);
+    pub const TEX_COORD: Self = Self::float("aTexCoord", 2, 2);
+    pub const JOINTS: Self = Self::float("aJoints", 4, 3);
+    pub const WEIGHTS: Self = Self::float("aWeights", 4, 4);
+    pub const COLOR: Self = Self::float("aColor", 4, 5);
+    pub const TANGENT: Self = Self::float("aTangent", 4, 6);
+    pub const TEX_COORD_1: Self = Self::float("aTexCoord1", 2, 7);
+
+    #[must_use]
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        component: ComponentType,
+        count: u32,
+        normalized: bool,
+        location: u32,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            component,
+            count,
+            normalized,
+            location,
+        }
+    }
+
+    /// An attribute of `count` floats.
+    #[must_use]
+    pub const fn float(name: &'static str, count: u32, location: u32) -> Self {
+        Self {
+            name: Cow::Borrowed(name),
+            component: ComponentType::F32,
+            count,
+            normalized: false,
+            location,
+        }
+    }
+
+    /// Size of one vertex's value in bytes, padded to four bytes so following attributes stay aligned.
+    #[must_use]
+    pub const fn size(&self) -> usize {
+        (self.component.size() * self.count as usize).next_multiple_of(4)
+    }
+}
+
+/// The values of a custom attribute, `count` components per vertex.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValues {
+    I8(Vec<i8>),
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    F32(Vec<f32>),
+}
+
+impl AttributeValues {
+    #[must_use]
+    pub const fn component(&self) -> ComponentType {
+        match self {
+            Self::I8(_) => ComponentType::I8,
+            Self::U8(_) => ComponentType::U8,
+            Self::I16(_) => ComponentType::I16,
+            Self::U16(_) => ComponentType::U16,
+            Self::I32(_) => ComponentType::I32,
+            Self::U32(_) => ComponentType::U32,
+            Self::F32(_) => ComponentType::F32,
+        }
+    }
+
+    /// The number of components, not vertices.
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        match self {
+            Self::I8(v) => v.len(),
+            Self::U8(v) => v.len(),
+            Self::I16(v) => v.len(),
+            Self::U16(v) => v.len(),
+            Self::I32(v) => v.len(),
+            Self::U32(v) => v.len(),
+            Self::F32(v) => v.len(),
+        }
+    }
+
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The values of vertex `sources[i]` for each new vertex `i`, `count` components each.
+    pub(crate) fn remap(&self, count: usize, sources: &[u32]) -> Self {
+        fn pick<T: Copy>(values: &[T], count: usize, sources: &[u32]) -> Vec<T> {
+            sources
+                .iter()
+                .flat_map(|&s| &values[s as usize * count..(s as usize + 1) * count])
+                .copied()
+                .collect()
+        }
+        match self {
+            Self::I8(v) => Self::I8(pick(v, count, sources)),
+            Self::U8(v) => Self::U8(pick(v, count, sources)),
+            Self::I16(v) => Self::I16(pick(v, count, sources)),
+            Self::U16(v) => Self::U16(pick(v, count, sources)),
+            Self::I32(v) => Self::I32(pick(v, count, sources)),
+            Self::U32(v) => Self::U32(pick(v, count, sources)),
+            Self::F32(v) => Self::F32(pick(v, count, sources)),
+        }
+    }
+
+    /// Truncates or pads the values with zeros to `len` components.
+    pub(crate) fn resize(&mut self, len: usize) {
+        match self {
+            Self::I8(v) => v.resize(len, 0),
+            Self::U8(v) => v.resize(len, 0),
+            Self::I16(v) => v.resize(len, 0),
+            Self::U16(v) => v.resize(len, 0),
+            Self::I32(v) => v.resize(len, 0),
+            Self::U32(v) => v.resize(len, 0),
+            Self::F32(v) => v.resize(len, 0.0),
+        }
+    }
+
+    /// The bytes of the components `range`.
+    fn bytes(&self, range: std::ops::Range<usize>) -> &[u8] {
+        match self {
+            Self::I8(v) => bytemuck::cast_slice(&v[range]),
+            Self::U8(v) => &v[range],
+            Self::I16(v) => bytemuck::cast_slice(&v[range]),
+            Self::U16(v) => bytemuck::cast_slice(&v[range]),
+            Self::I32(v) => bytemuck::cast_slice(&v[range]),
+            Self::U32(v) => bytemuck::cast_slice(&v[range]),
+            Self::F32(v) => bytemuck::cast_slice(&v[range]),
+        }
+    }
+}
+
+/// An attribute beyond the ones `Mesh` has fields for, such as tangents or a second UV set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CustomAttribute {
+    pub attribute: VertexAttribute,
+    pub values: AttributeValues,
+}
+
+impl CustomAttribute {
+    /// # Errors
+    /// If the values' type doesn't match the attribute's component type, or their number isn't a
+    /// multiple of the attribute's component count.
+    pub fn new(attribute: VertexAttribute, values: AttributeValues) -> Result<Self, String> {
+        if values.component() != attribute.component {
+            return Err(format!(
+                "Attribute {} has {:?} components but {:?} values",
+                attribute.name,
+                attribute.component,
+                values.component()
+            ));
+        }
+        if attribute.count == 0 || !values.len().is_multiple_of(attribute.count as usize) {
+            return Err(format!(
+                "Attribute {} has {} components per vertex, which {} values can't be split into",
+                attribute.name,
+                attribute.count,
+                values.len()
+            ));
+        }
+        Ok(Self { attribute, values })
+    }
+
+    /// Whether vertices `a` and `b` have the same value. Floating point components may differ by
+    /// up to `tolerance`; integer components must be equal.
+    pub(crate) fn same_vertex(&self, a: usize, b: usize, tolerance: f32) -> bool {
+        let count = self.attribute.count as usize;
+        let (a, b) = (a * count..(a + 1) * count, b * count..(b + 1) * count);
+        if let AttributeValues::F32(values) = &self.values {
+            values[a].iter().zip(&values[b]).all(|(x, y)| (x - y).abs() <= tolerance)
+        } else {
+            self.values.bytes(a) == self.values.bytes(b)
+        }
+    }
+
+    /// Appends the value of `vertex`, padded to the attribute's size.
+    pub(crate) fn write_vertex(&self, vertex: usize, out: &mut Vec<u8>) {
+        let count = self.attribute.count as usize;
+        let start = out.len();
+        out.extend_from_slice(self.values.bytes(vertex * count..(vertex + 1) * count));
+        out.resize(start + self.attribute.size(), 0);
+    }
+}
+
+/// The interleaved attributes of a vertex buffer, in buffer order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VertexLayout {
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+    #[must_use]
+    pub const fn new(attributes: Vec<VertexAttribute>) -> Self {
+        Self { attributes }
+    }
+
+    /// Size of one vertex in bytes.
+    #[must_use]
+    pub fn stride(&self) -> usize {
+        self.attributes.iter().map(VertexAttribute::size).sum()
+    }
+
+    #[must_use]
+    pub fn find(&self, name: &str) -> Option<&VertexAttribute> {
+        self.attributes.iter().find(|a| a.name == name)
+    }
+
+    /// The structure passed to `VertexArrayObject::configure`.
+    pub(crate) fn vaas(&self, vbo_index: u32) -> Vec<Vaa> {
+        self.attributes
+            .iter()
+            .map(|a| {
+                Vaa::new(a.component.gl_type(), a.count, vbo_index)
+                    .with_location(a.location)
+                    .with_normalized(a.normalized)
+            })
+            .collect()
+    }
+
+    /// Checks that the layout can be passed to OpenGL: every attribute has one to four
+    /// components and its own location.
+    /// # Errors
+    /// If an attribute has no or more than four components, or shares its location.
+    pub fn check(&self) -> Result<(), String> {
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            if !(1..=4).contains(&attribute.count) {
+                return Err(format!("Attribute {} has {} components, but only 1 to 4 are allowed", attribute.name, attribute.count));
+            }
+            if let Some(other) = self.attributes[..i].iter().find(|a| a.location == attribute.location) {
+                return Err(format!("Attributes {} and {} share location {}", other.name, attribute.name, attribute.location));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every active vertex input of `shader` is fed by an attribute at its location.
+    ///
+    /// Inputs without an attribute silently read a constant, which is usually a mistake. Normals
+    /// are the exception: shaders shade meshes without them flat.
+    /// # Errors
+    /// If an input has no attribute at its location, or is an integer input, since attributes are
+    /// always passed as floats.
+    pub fn validate(&self, shader: &Shader) -> Result<(), String> {
+        for (name, location, kind) in shader.active_attributes() {
+            let Ok(location) = u32::try_from(location) else {
+                // Built-ins such as gl_VertexID have no location.
+                continue;
+            };
+            if is_integer_type(kind) {
+                return Err(format!("Shader input {name} is an integer type, but vertex attributes are passed as floats"));
+            }
+            if location != VertexAttribute::NORMAL.location && !self.attributes.iter().any(|a| a.location == location) {
+                return Err(format!("Shader input {name} at location {location} has no vertex attribute"));
+            }
+        }
+        Ok(())
+    }
+}
+
+const fn is_integer_type(kind: GLenum) -> bool {
+    matches!(
+        kind,
+        gl::INT
+            | gl::INT_VEC2
+            | gl::INT_VEC3
+            | gl::INT_VEC4
+            | gl::UNSIGNED_INT
+            | gl::UNSIGNED_INT_VEC2
+            | gl::UNSIGNED_INT_VEC3
+            | gl::UNSIGNED_INT_VEC4
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderable::Mesh;
+    use cgmath::{Vector2, Vector3};
+
+    fn triangle() -> Mesh {
+        Mesh::new(
+            vec![Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0)],
+            vec![0, 1, 2],
+            None,
+            None,
+        )
+    }
+
+    #[test]
+    fn attribute_sizes_are_padded_to_four_bytes() {
+        assert_eq!(VertexAttribute::POSITION.size(), 12);
+        assert_eq!(VertexAttribute::new("a", ComponentType::U8, 3, true, 8).size(), 4);
+        assert_eq!(VertexAttribute::new("a", ComponentType::I8, 4, true, 8).size(), 4);
+        assert_eq!(VertexAttribute::new("a", ComponentType::I16, 3, false, 8).size(), 8);
+    }
+
+    #[test]
+    fn write_vertex_pads_each_value() {
+        let attribute = VertexAttribute::new("aId", ComponentType::U8, 3, false, 8);
+        let custom = CustomAttribute::new(attribute, AttributeValues::U8(vec![1, 2, 3, 4, 5, 6])).unwrap();
+        let mut out = vec![9];
+        custom.write_vertex(1, &mut out);
+        custom.write_vertex(0, &mut out);
+        assert_eq!(out, [9, 4, 5, 6, 0, 1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn custom_attributes_reject_mismatched_values() {
+        let attribute = VertexAttribute::new("aWeight", ComponentType::U16, 2, true, 8);
+        assert!(CustomAttribute::new(attribute.clone(), AttributeValues::F32(vec![0.0; 4])).is_err());
+        assert!(CustomAttribute::new(attribute.clone(), AttributeValues::U16(vec![0; 3])).is_err());
+        assert!(CustomAttribute::new(attribute, AttributeValues::U16(vec![0; 4])).is_ok());
+        let empty = VertexAttribute::new("aNothing", ComponentType::F32, 0, false, 8);
+        assert!(CustomAttribute::new(empty, AttributeValues::F32(Vec::new())).is_err());
+    }
+
+    #[test]
+    fn mesh_layout_lists_standard_attributes_first() {
+        let mut mesh = triangle();
+        assert_eq!(mesh.layout().attributes, [VertexAttribute::POSITION]);
+        mesh.tex_coords = Some(vec![Vector2::new(0.0, 0.0); 3]);
+        mesh.normals = Some(vec![Vector3::new(0.0, 0.0, 1.0); 3]);
+        // Joints are only used together with weights.
+        mesh.joints = Some(vec![[0; 4]; 3]);
+        mesh.colors = Some(vec![[1.0; 4]; 3]);
+        mesh.custom_attributes.push(
+            CustomAttribute::new(VertexAttribute::TANGENT, AttributeValues::F32(vec![0.0; 12])).unwrap(),
+        );
+        let layout = mesh.layout();
+        let names: Vec<&str> = layout.attributes.iter().map(|a| a.name.as_ref()).collect();
+        assert_eq!(names, ["aPos", "aNormal", "aTexCoord", "aColor", "aTangent"]);
+        assert_eq!(layout.stride(), 12 + 12 + 8 + 16 + 16);
+        assert_eq!(layout.find("aColor").map(|a| a.location), Some(5));
+        mesh.weights = Some(vec![[1.0, 0.0, 0.0, 0.0]; 3]);
+        assert!(mesh.layout().find("aJoints").is_some() && mesh.layout().find("aWeights").is_some());
+    }
+
+    #[test]
+    fn check_rejects_shared_locations_and_bad_counts() {
+        let clash = VertexAttribute::float("aOther", 2, VertexAttribute::TEX_COORD.location);
+        assert!(VertexLayout::new(vec![VertexAttribute::POSITION, VertexAttribute::TEX_COORD]).check().is_ok());
+        assert!(VertexLayout::new(vec![VertexAttribute::TEX_COORD, clash]).check().is_err());
+        assert!(VertexLayout::new(vec![VertexAttribute::float("aMatrix", 16, 8)]).check().is_err());
+    }
+
+    #[test]
+    fn mesh_init_rejects_invalid_layouts() {
+        let mut mesh = triangle();
+        mesh.custom_attributes
+            .push(CustomAttribute::new(VertexAttribute::float("aValue", 1, 8), AttributeValues::F32(vec![0.0; 2])).unwrap());
+        assert!(mesh.init().is_err());
+        let mut mesh = triangle();
+        mesh.custom_attributes
+            .push(CustomAttribute::new(VertexAttribute::float("aPosition2", 3, 0), AttributeValues::F32(vec![0.0; 9])).unwrap());
+        assert!(mesh.init().is_err());
+    }
+
+    #[test]
+    fn remap_and_resize_work_per_vertex() {
+        let values = AttributeValues::I16(vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(values.remap(2, &[2, 0, 2]), AttributeValues::I16(vec![5, 6, 1, 2, 5, 6]));
+        let mut values = AttributeValues::F32(vec![1.0, 2.0]);
+        values.resize(4);
+        assert_eq!(values, AttributeValues::F32(vec![1.0, 2.0, 0.0, 0.0]));
+        values.resize(1);
+        assert_eq!(values.len(), 1);
+    }
+}
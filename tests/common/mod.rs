@@ -0,0 +1,17 @@
+//! Helpers shared by the integration tests that need an OpenGL context.
+//!
+//! Those tests are `#[ignore]`d, as no context can be created on machines without a GPU or
+//! display; run them with `cargo test -- --ignored`.
+use rust_gl::Engine;
+
+/// A headless engine.
+/// # Panics
+/// If no OpenGL context can be created here.
+pub fn engine(width: usize, height: usize) -> Engine {
+    Engine::builder()
+        .size(width, height)
+        .headless(true)
+        .clear_color((0.0, 0.0, 1.0, 1.0))
+        .build()
+        .unwrap_or_else(|e| panic!("No OpenGL context: {e}"))
+}
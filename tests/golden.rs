@@ -0,0 +1,46 @@
+//! Golden-image tests; they need an OpenGL 4.6 context, so they only run with `--ignored`.
+//! Run with `RUST_GL_BLESS=1` to rewrite the references after intended changes.
+use rust_gl::renderable::Renderable;
+use rust_gl::shader::Shader;
+use rust_gl::testing::GoldenTest;
+use cgmath::Vector3;
+
+mod common;
+
+const VERTEX_SHADER: &str = "#version 460 core
+layout (location = 0) in vec3 aPos;
+uniform mat4 model;
+void main() {
+    gl_Position = model * vec4(aPos, 1.0);
+}";
+
+const FRAGMENT_SHADER: &str = "#version 460 core
+out vec4 FragColor;
+void main() {
+    FragColor = vec4(1.0, 0.0, 0.0, 1.0);
+}";
+
+/// A quad drawn straight in clip space over the left half of a blue frame.
+#[test]
+#[ignore = "needs an OpenGL 4.6 context"]
+fn left_half_quad() {
+    let mut engine = common::engine(64, 64);
+    let shader = Shader::from_source(VERTEX_SHADER, FRAGMENT_SHADER, "").expect("Shader should compile");
+    let shader = engine.data.shader_manager.register(shader);
+    let mut quad = Renderable::new(
+        vec![
+            Vector3::new(-1.0, -1.0, 0.0),
+            Vector3::new(0.0, -1.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+            Vector3::new(-1.0, 1.0, 0.0),
+        ],
+        vec![0, 1, 2, 0, 2, 3],
+        None,
+        &shader,
+    );
+    // The quad is placed in clip space, so the camera's frustum doesn't apply to it.
+    quad.screen_space = true;
+    engine.add_renderable(Box::new(quad)).unwrap();
+    let reference = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/left_half_quad.png");
+    GoldenTest::default().check(&mut engine, reference).unwrap();
+}
@@ -0,0 +1,53 @@
+//! OBJ export round trips; they need an OpenGL context, so they only run with `--ignored`.
+use cgmath::{Matrix4, Vector3};
+use rust_gl::primitives;
+use rust_gl::renderable::{Renderable, RenderableGroup};
+use rust_gl::shader::{NarrowingMaterial, TextureOr};
+
+mod common;
+
+/// Every triangle corner of a renderable in world space.
+fn corners(renderable: &Renderable) -> Vec<Vector3<f32>> {
+    let matrix: Matrix4<f32> = renderable.world_matrix();
+    let mesh = &renderable.mesh_data;
+    mesh.indices.iter().map(|&i| (matrix * mesh.vertices[i as usize].extend(1.0)).truncate()).collect()
+}
+
+#[test]
+#[ignore = "needs an OpenGL 4.6 context"]
+fn group_survives_an_obj_round_trip() {
+    let mut engine = common::engine(16, 16);
+    let material = NarrowingMaterial {
+        metallic: Some(TextureOr::Value(0.25)),
+        roughness: Some(TextureOr::Value(0.5)),
+        ..NarrowingMaterial::default()
+    };
+    let vert = std::fs::read_to_string("shaders/base_shader.vert").unwrap();
+    let frag = std::fs::read_to_string("shaders/base_shader.frag").unwrap();
+    let shader = material.into_shader(vert, frag).expect("Shader should compile");
+    let shader = engine.data.shader_manager.register(shader);
+    let mut group = RenderableGroup::new();
+    group.push(Renderable::from_mesh(primitives::cube(1.0).into_mesh(), &shader).unwrap(), None);
+    let mut sphere = Renderable::from_mesh(primitives::uv_sphere(0.5, 8, 6).into_mesh(), &shader).unwrap();
+    sphere.transform.position = Vector3::new(2.0, 0.5, -1.0);
+    group.push(sphere, None);
+
+    // Unique per process, so parallel test runs don't overwrite each other's files.
+    let path = std::env::temp_dir().join(format!("rust_gl_round_trip_{}.obj", std::process::id()));
+    group.write_obj(path.to_str().unwrap()).unwrap();
+    let loaded = RenderableGroup::from_obj(path.to_str().unwrap(), "shaders/base_shader", &mut engine.data.shader_manager);
+    let _ = std::fs::remove_file(&path);
+    let _ = std::fs::remove_file(path.with_extension("mtl"));
+    let loaded = loaded.unwrap();
+
+    assert_eq!(loaded.renderables().len(), group.renderables().len());
+    for (original, loaded) in group.renderables().iter().zip(loaded.renderables()) {
+        assert_eq!(loaded.mesh_data.indices.len(), original.mesh_data.indices.len());
+        let shader = loaded.shader.borrow();
+        assert_eq!(shader.value("metallic"), Some(0.25));
+        assert_eq!(shader.value("roughness"), Some(0.5));
+        for (a, b) in corners(original).iter().zip(corners(loaded)) {
+            assert!((a - b).x.abs() + (a - b).y.abs() + (a - b).z.abs() < 1e-5, "{a:?} != {b:?}");
+        }
+    }
+}
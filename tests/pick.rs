@@ -0,0 +1,86 @@
+//! Picking through the scene; it needs an OpenGL 4.6 context, so it only runs with `--ignored`.
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use rust_gl::bounds::Ray;
+use rust_gl::renderable::{Render, Renderable};
+use rust_gl::shader::{Shader, ShaderPtr};
+use rust_gl::transformation::Transformable;
+
+mod common;
+
+const VERTEX_SHADER: &str = "#version 460 core
+layout (location = 0) in vec3 aPos;
+uniform mat4 model;
+void main() {
+    gl_Position = model * vec4(aPos, 1.0);
+}";
+
+const FRAGMENT_SHADER: &str = "#version 460 core
+out vec4 FragColor;
+void main() {
+    FragColor = vec4(1.0);
+}";
+
+/// A unit quad around the origin, facing +z.
+fn quad(shader: &ShaderPtr) -> Renderable {
+    Renderable::new(
+        vec![
+            Vector3::new(-0.5, -0.5, 0.0),
+            Vector3::new(0.5, -0.5, 0.0),
+            Vector3::new(0.5, 0.5, 0.0),
+            Vector3::new(-0.5, 0.5, 0.0),
+        ],
+        vec![0, 1, 2, 0, 2, 3],
+        None,
+        shader,
+    )
+}
+
+fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+    (a - b).magnitude() < 1e-5
+}
+
+#[test]
+#[ignore = "needs an OpenGL 4.6 context"]
+fn picks_the_nearest_placed_quad() {
+    let mut engine = common::engine(16, 16);
+    let shader = Shader::from_source(VERTEX_SHADER, FRAGMENT_SHADER, "").expect("Shader should compile");
+    let shader = engine.data.shader_manager.register(shader);
+
+    // Scaled by 2, turned a quarter around z and moved to (1, 2, -3).
+    let mut placed = quad(&shader);
+    placed.set_parent_matrix(
+        Matrix4::from_translation(Vector3::new(1.0, 2.0, -3.0))
+            * Matrix4::from_angle_z(Deg(90.0))
+            * Matrix4::from_scale(2.0),
+    );
+    // Further along the ray, so the placed quad hides it.
+    let mut behind = quad(&shader);
+    behind.transform.set_translation(1.5, 2.5, -6.0);
+    // Nearer, but neither of these can be hit.
+    let mut overlay = quad(&shader);
+    overlay.transform.set_translation(1.5, 2.5, 0.0);
+    overlay.screen_space = true;
+    let mut hidden = quad(&shader);
+    hidden.transform.set_translation(1.5, 2.5, 1.0);
+    hidden.set_is(false);
+    for renderable in [behind, overlay, placed, hidden] {
+        engine.add_renderable(Box::new(renderable)).unwrap();
+    }
+
+    let ray = Ray::new(Vector3::new(1.5, 2.5, 5.0), Vector3::new(0.0, 0.0, -1.0));
+    let pick = engine.data.pick(&ray).unwrap().expect("The ray should hit the placed quad");
+    assert_eq!(pick.index, 2);
+    assert_eq!(pick.hit.triangle, 0);
+    assert!((pick.hit.distance - 8.0).abs() < 1e-5);
+    assert!(close(pick.hit.position, Vector3::new(1.5, 2.5, -3.0)));
+    assert!(close(pick.hit.barycentric, Vector3::new(0.25, 0.5, 0.25)));
+
+    // Moving the placed quad away refits the scene's tree, so the one behind it is hit.
+    pick.renderable.borrow_mut().set_translation(0.0, 0.0, 10.0);
+    let pick = engine.data.pick(&ray).unwrap().expect("The ray should hit the quad behind");
+    assert_eq!(pick.index, 0);
+    assert!(close(pick.hit.position, Vector3::new(1.5, 2.5, -6.0)));
+
+    let beside = Ray::new(Vector3::new(5.0, 5.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+    assert!(engine.data.pick(&beside).unwrap().is_none());
+}